@@ -1,32 +1,104 @@
 //! An archive of soundings in various formats.
 
 use crate::{
+    archive_config::ArchiveConfig,
+    archive_stats::ArchiveStats,
+    backfill_report::BackfillReport,
+    blob_store::{BlobStore, FileSystemBlobStore},
+    codec::Codec,
+    compression_stats::CompressionStats,
+    coverage::CoverageComparison,
+    decoder::{Decoders, SoundingDecoder},
+    diagnosis::Diagnosis,
     errors::{BufkitDataErr, Result},
+    file_name_template::FileNameTemplate,
+    file_query::FileQuery,
+    file_record::{FileRecord, FileRecordIter, RecordResolver, FILE_ROW_COLUMNS},
     inventory::Inventory,
     location::Location,
-    site::Site,
+    site::{Site, StateProv},
+    size_estimate::SizeEstimate,
     sounding_type::{FileType, SoundingType},
+    surface::SurfaceData,
 };
-use chrono::NaiveDateTime;
-use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use flate2::{read::GzEncoder, Compression, Crc, CrcReader};
+use fnv::FnvHashMap;
 use rusqlite::{types::ToSql, Connection, OpenFlags, Row, NO_PARAMS};
 use sounding_analysis::Analysis;
-use sounding_bufkit::BufkitData;
 use std::{
-    collections::HashSet,
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
     fs::{create_dir, create_dir_all, read_dir, remove_file, File},
-    io::Read,
+    io::{BufRead, BufReader, Read, Write},
     path::{Path, PathBuf},
-    str::from_utf8,
+    str::FromStr,
 };
 use strum::AsStaticRef;
 
 /// The archive.
 #[derive(Debug)]
 pub struct Archive {
-    root: PathBuf,       // The root directory.
-    file_dir: PathBuf,   // the directory containing the downloaded files.
-    db_conn: Connection, // An sqlite connection.
+    root: PathBuf,                          // The root directory.
+    file_dir: PathBuf,                      // the directory containing the downloaded files.
+    blobs: Box<dyn BlobStore>,              // Where the files are stored, file_dir by default.
+    db_conn: Connection,                    // An sqlite connection.
+    track_access: bool,                     // Record the last time each file is read.
+    codec: Codec,                           // The codec used to store new files.
+    compression: Compression,               // The gzip level used to store new files.
+    on_conflict: OnConflict,                // What to do when a file moves to a new location.
+    init_time_match: InitTimeMatch,         // How init times are matched when looking up files.
+    max_init_time_lead: Option<Duration>,   // How far in the future a new file's init time can be.
+    valid_time_tolerance: Option<Duration>, // How close a new file's first valid time must be.
+    trash_enabled: bool,                    // Move removed files to the trash instead of deleting.
+    decoders: Decoders,                     // Decoders for each file type.
+    file_name_template: FileNameTemplate,   // Template for naming new files.
+    fetch_hook: Option<FetchHook>,          // Used to fill in files missing from the archive.
+}
+
+/// A function to fetch the data for a file that is missing from the archive.
+///
+/// Given the `Site`, `SoundingType`, and initialization time, it returns the uncompressed contents
+/// of the file.
+pub type FetchFn = dyn Fn(&Site, &SoundingType, &NaiveDateTime) -> Result<Vec<u8>>;
+
+/// A function to fetch the data for a run missing from the archive, `Ok(None)` if it isn't
+/// available.
+pub type BackfillFn = dyn Fn(&Site, &SoundingType, &NaiveDateTime) -> Result<Option<Vec<u8>>>;
+
+/// What to do when adding a file for a fixed site that is already in the index with a different
+/// location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// Replace the existing file and its location.
+    #[default]
+    Replace,
+    /// Return a `BufkitDataErr::LocationConflict` error and leave the existing file alone.
+    Reject,
+    /// Leave the existing file alone and return without an error.
+    KeepExisting,
+}
+
+/// How the initialization time given to `retrieve`, `file_exists`, and friends is matched against
+/// the initialization times of the files in the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InitTimeMatch {
+    /// Only a file with the same initialization time, to the minute, matches.
+    ///
+    /// File names only have the time to the minute, so the seconds are always dropped.
+    #[default]
+    Exact,
+    /// The file with the closest initialization time matches, as long as it is no more than this
+    /// far away from the time with the seconds dropped. A tie goes to the earlier file.
+    Within(Duration),
+}
+
+struct FetchHook(Box<FetchFn>);
+
+impl std::fmt::Debug for FetchHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FetchHook")
+    }
 }
 
 impl Archive {
@@ -39,12 +111,48 @@ impl Archive {
     where
         T: AsRef<Path>,
     {
-        let file_dir = root.as_ref().join(Archive::FILE_DIR);
-        let db_file = root.as_ref().join(Archive::INDEX);
+        Self::create_with_config(root, ArchiveConfig::default())
+    }
+
+    /// Initialize a new archive that names the files it stores with `template`.
+    ///
+    /// The template is text with the placeholders `{time}`, `{source}`, `{file_type}`, `{site}`,
+    /// and `{ext}`, which are filled in with the initialization time, `SoundingType` source,
+    /// `FileType`, `Site` short name, and the extension for the `Codec`. The `{time}`, `{source}`,
+    /// and `{site}` placeholders are required so that every file gets a unique name, and
+    /// placeholders must be separated by some text (except `{ext}`) so names can be parsed. The
+    /// default template is `"{time}_{source}_{file_type}_{site}{ext}"`.
+    ///
+    /// The template is saved in the index, so it is used again when the archive is opened with
+    /// `connect`.
+    pub fn create_with_file_name_template<T>(root: T, template: &str) -> Result<Self>
+    where
+        T: AsRef<Path>,
+    {
+        Self::create_with_config(
+            root,
+            ArchiveConfig::default().with_file_name_template(template),
+        )
+    }
+
+    /// Initialize a new archive with the names and file name template in `config`.
+    ///
+    /// The names of the file directory and index are saved in the root directory, so they are used
+    /// again when the archive is opened with `connect`.
+    pub fn create_with_config<T>(root: T, config: ArchiveConfig) -> Result<Self>
+    where
+        T: AsRef<Path>,
+    {
+        config.validate()?;
+        let file_name_template = FileNameTemplate::new(config.file_name_template())?;
+
+        let file_dir = root.as_ref().join(config.file_dir());
+        let db_file = root.as_ref().join(config.index());
         let root = root.as_ref().to_path_buf();
 
         create_dir_all(&root)?;
         create_dir(&file_dir)?;
+        config.save(&root)?;
 
         // Create and set up the archive
         let db_conn = Connection::open_with_flags(
@@ -53,33 +161,136 @@ impl Archive {
         )?;
 
         db_conn.execute_batch(include_str!("create_index.sql"))?;
+        crate::meta::store_meta(
+            &db_conn,
+            Archive::FILE_NAME_TEMPLATE_KEY,
+            file_name_template.as_str(),
+        )?;
+        crate::meta::store_meta(
+            &db_conn,
+            Archive::CREATED_BY_VERSION_KEY,
+            env!("CARGO_PKG_VERSION"),
+        )?;
 
         Ok(Archive {
             root,
+            blobs: Box::new(FileSystemBlobStore::new(&file_dir)),
             file_dir,
             db_conn,
+            track_access: true,
+            codec: Codec::default(),
+            compression: Compression::default(),
+            on_conflict: OnConflict::default(),
+            init_time_match: InitTimeMatch::default(),
+            max_init_time_lead: Some(Duration::hours(Archive::DEFAULT_MAX_INIT_TIME_LEAD_HOURS)),
+            valid_time_tolerance: None,
+            trash_enabled: false,
+            decoders: Decoders::default(),
+            file_name_template,
+            fetch_hook: None,
         })
     }
 
     /// Open an existing archive.
+    ///
+    /// An index made by an older version of this crate is upgraded by adding the tables and
+    /// columns it is missing. Returns `BufkitDataErr::ArchiveNotFound` if there is no index in
+    /// `root`.
     pub fn connect<T>(root: T) -> Result<Self>
     where
         T: AsRef<Path>,
     {
-        let file_dir = root.as_ref().join(Archive::FILE_DIR);
-        let db_file = root.as_ref().join(Archive::INDEX);
+        let config = ArchiveConfig::load(root.as_ref())?;
+
+        let db_file = root.as_ref().join(config.index());
         let root = root.as_ref().to_path_buf();
 
+        if !db_file.is_file() {
+            return Err(BufkitDataErr::ArchiveNotFound(root));
+        }
+
         // Create and set up the archive
         let db_conn = Connection::open_with_flags(db_file, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        crate::schema::migrate(&db_conn)?;
+
+        let file_dir = match crate::meta::retrieve_meta(&db_conn, Archive::BLOB_ROOT_KEY)? {
+            Some(blob_root) => PathBuf::from(blob_root),
+            None => root.join(config.file_dir()),
+        };
+
+        let file_name_template =
+            match crate::meta::retrieve_meta(&db_conn, Archive::FILE_NAME_TEMPLATE_KEY)? {
+                Some(template) => FileNameTemplate::new(&template)?,
+                None => FileNameTemplate::default(),
+            };
 
         Ok(Archive {
             root,
+            blobs: Box::new(FileSystemBlobStore::new(&file_dir)),
             file_dir,
             db_conn,
+            track_access: true,
+            codec: Codec::default(),
+            compression: Compression::default(),
+            on_conflict: OnConflict::default(),
+            init_time_match: InitTimeMatch::default(),
+            max_init_time_lead: Some(Duration::hours(Archive::DEFAULT_MAX_INIT_TIME_LEAD_HOURS)),
+            valid_time_tolerance: None,
+            trash_enabled: false,
+            decoders: Decoders::default(),
+            file_name_template,
+            fetch_hook: None,
         })
     }
 
+    /// Open an existing archive, or create a new one with the default settings if there isn't one
+    /// in `root`.
+    ///
+    /// Only a missing archive leads to creating a new one, any other error from `connect`, such as
+    /// a corrupt index, is returned.
+    pub fn open_or_create<T>(root: T) -> Result<Self>
+    where
+        T: AsRef<Path>,
+    {
+        match Self::connect(root.as_ref()) {
+            Err(BufkitDataErr::ArchiveNotFound(_)) => Self::create(root),
+            result => result,
+        }
+    }
+
+    /// Check if there is an archive in `root` without opening it.
+    ///
+    /// This only looks for the index file, using the names from the archive config if there is
+    /// one, so it is `true` exactly when `connect` would not return
+    /// `BufkitDataErr::ArchiveNotFound`. The files directory isn't required since it may have been
+    /// moved with `relocate_blobs`, and where it went is only recorded in the index, see
+    /// `fsck_paths` to check it.
+    pub fn exists<T>(root: T) -> bool
+    where
+        T: AsRef<Path>,
+    {
+        ArchiveConfig::load(root.as_ref())
+            .map(|config| root.as_ref().join(config.index()).is_file())
+            .unwrap_or(false)
+    }
+
+    /// Check that the tables and columns in the index are the ones this version of the crate
+    /// expects.
+    ///
+    /// An archive created by an older or newer version may have a different schema, which would
+    /// otherwise only show up as a confusing error from some later query. Returns
+    /// `BufkitDataErr::SchemaMismatch` listing the differences.
+    pub fn check_schema(&self) -> Result<()> {
+        crate::schema::check_schema(&self.db_conn)
+    }
+
+    /// The version of this crate that created the archive.
+    ///
+    /// This is `None` for archives created before the version was recorded.
+    pub fn created_by_version(&self) -> Result<Option<String>> {
+        crate::meta::retrieve_meta(&self.db_conn, Archive::CREATED_BY_VERSION_KEY)
+    }
+
     /// Check for errors in the index.
     ///
     /// Return a list of files in the index that are missing on the system and a list of files on
@@ -101,12 +312,7 @@ impl Archive {
             .collect();
         let index_vals = index_vals?;
 
-        let file_system_vals: HashSet<String> = read_dir(&self.file_dir)?
-            .filter_map(|de| de.ok())
-            .map(|de| de.path())
-            .filter(|p| p.is_file())
-            .filter_map(|p| p.file_name().map(|f| f.to_string_lossy().to_string()))
-            .collect();
+        let file_system_vals: HashSet<String> = self.blobs.list()?.into_iter().collect();
 
         let files_in_index_but_not_on_file_system: Vec<String> = index_vals
             .difference(&file_system_vals)
@@ -120,947 +326,7828 @@ impl Archive {
         Ok((files_in_index_but_not_on_file_system, files_not_in_index))
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // The file system aspects of the archive, e.g. the root directory of the archive
-    // ---------------------------------------------------------------------------------------------
-    const FILE_DIR: &'static str = "files";
-    const INDEX: &'static str = "index.sqlite";
+    /// Run every consistency check on the archive and collect the results in a single report.
+    ///
+    /// This includes the results of `check` and `check_init_times`, locations that are not used by
+    /// any file, and files for the same site and sounding type whose initialization times fall in
+    /// the same minute. Decompressing every file to look for corruption is slow, so it is only done if
+    /// `check_contents` is `true`.
+    pub fn diagnose(&self, check_contents: bool) -> Result<Diagnosis> {
+        let (missing_files, unindexed_files) = self.check()?;
+
+        let (corrupt_files, checksum_mismatches) = if check_contents {
+            let mut corrupt = vec![];
+            let mut mismatches = vec![];
+            for (file_name, codec, stored) in self.files_with_checksums(false)? {
+                if missing_files.contains(&file_name) {
+                    continue;
+                }
+
+                match self.content_checksum(&file_name, codec) {
+                    Ok(checksum) if stored.map(|s| s != checksum).unwrap_or(false) => {
+                        mismatches.push(file_name)
+                    }
+                    Ok(_) => {}
+                    Err(_) => corrupt.push(file_name),
+                }
+            }
+            (Some(corrupt), Some(mismatches))
+        } else {
+            (None, None)
+        };
 
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify site metadata
-    // ---------------------------------------------------------------------------------------------
+        let orphan_locations = crate::location::unused_locations(&self.db_conn)?;
 
-    /// Retrieve a list of all the `Site`s in the archive.
-    pub fn sites(&self) -> Result<Vec<Site>> {
-        crate::site::all_sites(&self.db_conn)
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT GROUP_CONCAT(file_name, '/')
+                FROM files
+                GROUP BY site_id, type_id, strftime('%Y-%m-%dT%H:%M', init_time)
+                HAVING COUNT(*) > 1
+            ",
+        )?;
+        let duplicate_init_times: Result<Vec<Vec<String>>> = stmt
+            .query_map(NO_PARAMS, |row: &Row| -> std::result::Result<String, _> {
+                row.get(0)
+            })?
+            .map(|res| res.map_err(BufkitDataErr::Database))
+            .map(|res| res.map(|names| names.split('/').map(String::from).collect()))
+            .collect();
+        let duplicate_init_times = duplicate_init_times?;
+
+        let init_time_mismatches = self.check_init_times()?;
+
+        Ok(Diagnosis {
+            missing_files,
+            unindexed_files,
+            corrupt_files,
+            checksum_mismatches,
+            orphan_locations,
+            duplicate_init_times,
+            init_time_mismatches,
+        })
     }
 
-    /// Retrieve the information about a single `Site` with the supplied `short_name`.
+    /// Compare the initialization time in each file name to the one in the index.
     ///
-    /// Returns `Ok(None)` if none exists in the archive, and returns `Ok(Some(_))` with the
-    /// corresponding `Site` object if one does exist.
-    pub fn site_info(&self, short_name: &str) -> Result<Option<Site>> {
-        crate::site::retrieve_site(&self.db_conn, short_name)
+    /// `check` only compares the sets of names, so it can't catch a file whose name disagrees
+    /// with the index about when it was initialized. Returns the names of the files that don't
+    /// match, or that can't be parsed with the archive's file name template.
+    pub fn check_init_times(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT file_name, init_time FROM files ORDER BY file_name")?;
+
+        let rows: Result<Vec<(String, NaiveDateTime)>> = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mismatches = rows?
+            .into_iter()
+            .filter(|(file_name, init_time)| {
+                self.file_name_template
+                    .parse(file_name)
+                    .map(|parsed| parsed.init_time != truncate_to_minute(init_time))
+                    .unwrap_or(true)
+            })
+            .map(|(file_name, _)| file_name)
+            .collect();
+
+        Ok(mismatches)
     }
 
-    /// Modify an existing `Site`'s values.
+    /// Check the contents of every file against the checksum stored when it was added.
     ///
-    /// The supplied site need not be validated, the returned site will be. It is an error if there
-    /// is not a site in the index with the same `short_name` to modify.
-    pub fn set_site_info(&self, site: Site) -> Result<Site> {
-        crate::site::update_site(&self.db_conn, site)
+    /// Returns the names of the files that don't match or can't be read. Files without a stored
+    /// checksum are skipped, use `backfill_checksums` to compute them for older archives.
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let mut failed = vec![];
+        for (file_name, codec, stored) in self.files_with_checksums(false)? {
+            let stored = match stored {
+                Some(stored) => stored,
+                None => continue,
+            };
+
+            match self.content_checksum(&file_name, codec) {
+                Ok(checksum) if checksum == stored => {}
+                _ => failed.push(file_name),
+            }
+        }
+
+        Ok(failed)
     }
 
-    /// Validate that this `Site` is in the index.
+    /// Compute and store the checksum for every file that doesn't have one yet.
     ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_site(&self, site: Site) -> Result<Site> {
-        if site.is_valid() {
-            Ok(site)
-        } else if let Some(retrieved_site) =
-            crate::site::retrieve_site(&self.db_conn, site.short_name())?
-        {
-            Ok(retrieved_site)
-        } else {
-            Err(BufkitDataErr::InvalidSite(site))
+    /// Files added before checksums were stored don't have one. Each checksum is saved as soon as
+    /// it is computed, so this can be stopped and run again later to pick up where it left off.
+    /// Files that can't be read are skipped and left without a checksum, `diagnose` will report
+    /// them. Returns the number of checksums stored.
+    pub fn backfill_checksums(&self) -> Result<usize> {
+        let mut num_filled = 0;
+        for (file_name, codec, _) in self.files_with_checksums(true)? {
+            let checksum = match self.content_checksum(&file_name, codec) {
+                Ok(checksum) => checksum,
+                Err(_) => continue,
+            };
+
+            self.db_conn.execute(
+                "UPDATE files SET checksum = ?2 WHERE file_name = ?1",
+                &[&file_name as &dyn ToSql, &i64::from(checksum)],
+            )?;
+            num_filled += 1;
         }
+
+        Ok(num_filled)
     }
 
-    /// Validate that this `Site` is in the index, if not, insert it into the index.
+    /// Count and store the number of soundings in every file that hasn't been counted yet.
     ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_or_add_site(&self, site: Site) -> Result<Site> {
-        if site.is_valid() {
-            Ok(site)
-        } else {
-            if let Some(retrieved_site) =
-                crate::site::retrieve_site(&self.db_conn, site.short_name())?
+    /// Like `backfill_checksums`, each count is saved as soon as it is made and files that can't
+    /// be decoded are skipped. Returns the number of files counted.
+    pub fn backfill_profile_counts(&self) -> Result<usize> {
+        let mut num_filled = 0;
+        for (file_name, codec, file_type) in self.files_without_profile_counts(None)? {
+            if self
+                .store_profile_count(&file_name, codec, file_type)
+                .is_ok()
             {
-                Ok(retrieved_site)
-            } else {
-                crate::site::insert_site(&self.db_conn, site)
+                num_filled += 1;
             }
         }
-    }
-
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify sounding type metadata
-    // ---------------------------------------------------------------------------------------------
 
-    /// Retrieve a list of all the `SoundingType`s in the archive.
-    pub fn sounding_types(&self) -> Result<Vec<SoundingType>> {
-        crate::sounding_type::all_sounding_types(&self.db_conn)
+        Ok(num_filled)
     }
 
-    /// Retrieve the information about a single `SoundingType` with the supplied description, which
-    /// is the same as the result from its `source()` method.
+    /// Copy everything in the write-ahead log into the index and truncate the log.
     ///
-    /// Returns `Ok(None)` if none exists in the archive, and returns `Ok(Some(_))` with the
-    /// corresponding `SoundingType` object if one does exist.
-    pub fn sounding_type_info(&self, sounding_type: &str) -> Result<Option<SoundingType>> {
-        crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type)
+    /// When the index is in WAL mode, the `-wal` file next to it keeps growing during a long run
+    /// of writes until the connection is closed. Calling this now and then from a long running
+    /// writer keeps it small. In the default rollback journal mode there is no log, so this does
+    /// nothing.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.db_conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_| Ok(()))?;
+
+        Ok(())
     }
 
-    /// Modify an existing `SoundingType`'s values.
+    /// Add the files in the archive that are missing from the index back into it.
     ///
-    /// The supplied sounding type need not be validated, the returned one will be. It is an error
-    /// if there is not a sounding type in the index with the same `.source()` to modify.
-    pub fn set_sounding_type_info(&self, sounding_type: SoundingType) -> Result<SoundingType> {
-        crate::sounding_type::update_sounding_type(&self.db_conn, sounding_type)
+    /// This is for recovering from a lost or damaged index when the files survive. The name of each
+    /// file that `check` finds is not in the index is parsed with the file name template to get the
+    /// initialization time, sounding type, site, and codec, and the file is decoded to get its
+    /// location and last valid time. Sites and sounding types that aren't in the index are added,
+    /// new sounding types are assumed to be models with an unknown interval, so use
+    /// `set_sounding_type_info` to fix them up afterwards.
+    ///
+    /// Files that don't fit the template, can't be decoded, or are for a run that is already in the
+    /// index are skipped, and `check` will still list them. Returns the number of files recovered.
+    pub fn rebuild_index(&self) -> Result<usize> {
+        let (_, not_indexed) = self.check()?;
+
+        let mut num_recovered = 0;
+        for file_name in not_indexed {
+            if self.reindex_file(&file_name)? {
+                num_recovered += 1;
+            }
+        }
+
+        Ok(num_recovered)
     }
 
-    /// Get a list of `SoundingType`s in the archive for this `site`.
-    pub fn sounding_types_for_site(&self, site: &Site) -> Result<Vec<SoundingType>> {
-        debug_assert!(site.id() > 0);
-        crate::sounding_type::all_sounding_types_for_site(&self.db_conn, site)
+    /// Add a single file to the index based on its name and contents, returning `false` if it was
+    /// skipped.
+    fn reindex_file(&self, file_name: &str) -> Result<bool> {
+        let parsed = match self.file_name_template.parse(file_name) {
+            Some(parsed) => parsed,
+            None => return Ok(false),
+        };
+        let codec = match [Codec::Gzip, Codec::None]
+            .iter()
+            .find(|codec| codec.extension() == parsed.ext)
+        {
+            Some(codec) => *codec,
+            None => return Ok(false),
+        };
+
+        let data = match self.read_file(file_name, codec) {
+            Ok(data) => data,
+            Err(_) => return Ok(false),
+        };
+
+        let existing_type = self.sounding_type_info(&parsed.source)?;
+        let file_type = match &existing_type {
+            Some(sounding_type) => sounding_type.file_type(),
+            None => parsed
+                .file_type
+                .as_ref()
+                .and_then(|file_type| FileType::from_str(file_type).ok())
+                .unwrap_or_else(|| FileType::detect(&data)),
+        };
+
+        let anals = match self.decode_data(&data, file_name, file_type) {
+            Ok(anals) => anals,
+            Err(_) => return Ok(false),
+        };
+        let (lat, lon, elev_m, end_time) = match Self::location_and_end_time(&anals) {
+            Some(vals) => vals,
+            None => return Ok(false),
+        };
+
+        let sounding_type = match existing_type {
+            Some(sounding_type) => sounding_type,
+            None => self.validate_or_add_sounding_type(SoundingType::new_model(
+                &parsed.source,
+                file_type,
+                None,
+            ))?,
+        };
+        let site = self.validate_or_add_site(Site::new(&parsed.site))?;
+        let location = self.retrieve_or_add_location(lat, lon, elev_m)?;
+
+        let mut checksum = Crc::new();
+        checksum.update(&data);
+
+        let num_added = self.db_conn.execute(
+            "
+                INSERT OR IGNORE INTO files
+                    (type_id, site_id, location_id, init_time, end_time, file_name, codec,
+                     checksum)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ",
+            &[
+                &sounding_type.id(),
+                &site.id(),
+                &location.id(),
+                &parsed.init_time as &dyn ToSql,
+                &end_time as &dyn ToSql,
+                &file_name,
+                &codec.as_static(),
+                &i64::from(checksum.sum()),
+            ],
+        )?;
+
+        Ok(num_added > 0)
     }
 
-    /// Validate that this `SoundingType` is in the index.
+    /// Check that the paths the archive depends on are usable, e.g. after moving it to another
+    /// machine.
     ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_sounding_type(&self, sounding_type: SoundingType) -> Result<SoundingType> {
-        if sounding_type.is_valid() {
-            Ok(sounding_type)
-        } else if let Some(retrieved_st) =
-            crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
-        {
-            Ok(retrieved_st)
-        } else {
-            Err(BufkitDataErr::InvalidSoundingType(sounding_type))
+    /// The files directory must exist and be writable, and every file name in the index must be a
+    /// file in that directory. Returns `BufkitDataErr::BadArchivePath` describing the first problem
+    /// found.
+    pub fn fsck_paths(&self) -> Result<()> {
+        let bad_path =
+            |reason: String| BufkitDataErr::BadArchivePath(self.file_dir.clone(), reason);
+
+        if !self.file_dir.is_dir() {
+            return Err(bad_path(
+                "the files directory does not exist, if it was moved use relocate_blobs to \
+                 point the archive at the new location"
+                    .to_owned(),
+            ));
+        }
+
+        let probe = self.file_dir.join(".fsck_paths_probe");
+        File::create(&probe)
+            .and_then(|_| remove_file(&probe))
+            .map_err(|err| bad_path(format!("the files directory is not writable: {}", err)))?;
+
+        let mut stmt = self.db_conn.prepare("SELECT file_name FROM files")?;
+        let file_names: Result<Vec<String>> = stmt
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .map(|res| res.map_err(BufkitDataErr::Database))
+            .collect();
+
+        let mut unresolved = file_names?.into_iter().filter(|file_name| {
+            let mut components = Path::new(file_name).components();
+            let is_plain_name = matches!(
+                (components.next(), components.next()),
+                (Some(std::path::Component::Normal(_)), None)
+            );
+
+            !is_plain_name || !self.file_dir.join(file_name).is_file()
+        });
+
+        if let Some(first) = unresolved.next() {
+            return Err(bad_path(format!(
+                "{} file(s) in the index are not in the files directory, starting with {}, use \
+                 check or diagnose for the full list",
+                1 + unresolved.count(),
+                first
+            )));
         }
+
+        Ok(())
     }
 
-    /// Validate that this `SoundingType` is in the index, if not, add it to the index.
-    ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_or_add_sounding_type(
+    /// Get the name, codec, and stored checksum of the files in the index, or only the ones
+    /// without a stored checksum if `only_missing` is `true`.
+    fn files_with_checksums(
         &self,
-        sounding_type: SoundingType,
-    ) -> Result<SoundingType> {
-        if sounding_type.is_valid() {
-            Ok(sounding_type)
+        only_missing: bool,
+    ) -> Result<Vec<(String, Codec, Option<u32>)>> {
+        let mut stmt = self.db_conn.prepare(if only_missing {
+            "SELECT file_name, codec, checksum FROM files WHERE checksum IS NULL"
         } else {
-            if let Some(retrieved_st) =
-                crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
-            {
-                Ok(retrieved_st)
-            } else {
-                crate::sounding_type::insert_sounding_type(&self.db_conn, sounding_type)
-            }
-        }
-    }
+            "SELECT file_name, codec, checksum FROM files"
+        })?;
 
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify location metadata
-    // ---------------------------------------------------------------------------------------------
+        let rows: Result<Vec<(String, String, Option<i64>)>> = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
 
-    /// Retrieve a list of all the `Location`s in the archive.
-    pub fn all_locations(&self) -> Result<Vec<Location>> {
-        crate::location::all_locations(&self.db_conn)
+        rows?
+            .into_iter()
+            .map(|(file_name, codec, checksum)| {
+                Ok((
+                    file_name,
+                    Codec::from_str(&codec)?,
+                    checksum.map(|c| c as u32),
+                ))
+            })
+            .collect()
     }
 
-    /// Get the `Location` object for these coordinates.
-    ///
-    /// If there were no errors while querying the index, this will return an `Ok(None)` meaning
-    /// that no errors occurred, but there was matching locaiton in the index.
-    pub fn location_info(
+    /// Get the files without a profile count, for a single site and sounding type or all of them.
+    fn files_without_profile_counts(
         &self,
-        latitude: f64,
-        longitude: f64,
-        elevation_m: i32,
-    ) -> Result<Option<Location>> {
-        crate::location::retrieve_location(&self.db_conn, latitude, longitude, elevation_m)
+        series: Option<(&Site, &SoundingType)>,
+    ) -> Result<Vec<(String, Codec, FileType)>> {
+        let (site_id, type_id) = series
+            .map(|(site, sounding_type)| (Some(site.id()), Some(sounding_type.id())))
+            .unwrap_or((None, None));
+
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT files.file_name, files.codec, types.file_type
+                FROM files JOIN types ON files.type_id = types.id
+                WHERE files.profile_count IS NULL
+                    AND (?1 IS NULL OR files.site_id = ?1)
+                    AND (?2 IS NULL OR files.type_id = ?2)
+            ",
+        )?;
+
+        let rows: Result<Vec<(String, String, String)>> = stmt
+            .query_map([site_id, type_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        rows?
+            .into_iter()
+            .map(|(file_name, codec, file_type)| {
+                Ok((
+                    file_name,
+                    Codec::from_str(&codec)?,
+                    FileType::from_str(&file_type).unwrap_or(FileType::UNKNOWN),
+                ))
+            })
+            .collect()
     }
 
-    /// Retrieve the `Location` object associated with these coordinates, or insert a new one into
-    /// the index.
-    pub fn retrieve_or_add_location(
+    /// Decode a file to count the soundings in it and save the count in the index.
+    fn store_profile_count(
         &self,
-        latitude: f64,
-        longitude: f64,
-        elevation_m: i32,
-    ) -> Result<Location> {
-        crate::location::retrieve_or_add_location(&self.db_conn, latitude, longitude, elevation_m)
+        file_name: &str,
+        codec: Codec,
+        file_type: FileType,
+    ) -> Result<()> {
+        let buf = self.read_file(file_name, codec)?;
+        let count = self.decode_data(&buf, file_name, file_type)?.len() as i64;
+
+        self.db_conn.execute(
+            "UPDATE files SET profile_count = ?2 WHERE file_name = ?1",
+            &[&file_name as &dyn ToSql, &count],
+        )?;
+
+        Ok(())
     }
 
-    /// Modify an existing `Location`'s values.
+    /// Turn tracking of the last time each file was read on or off.
     ///
-    /// The supplied location need not be validated, the returned one will be. It is an error if
-    /// there is not a matching `Location` in the index with the same coordinates to modify.
-    /// Basically you can only modify the time zone offset information.
-    pub fn set_location_info(&self, location: Location) -> Result<Location> {
-        crate::location::update_location(&self.db_conn, location)
+    /// Tracking is on by default, and every call to `retrieve`, `retrieve_all`, or `export` costs
+    /// a single `UPDATE` to the index. For a pure archive, where the extra writes are not wanted,
+    /// it can be turned off. Files added while tracking is off have no last accessed time, so they
+    /// are the first to go when using `prune_least_recently_used`.
+    pub fn set_access_tracking(&mut self, track_access: bool) {
+        self.track_access = track_access;
     }
 
-    /// Get a list of `Location`s in the archive for this site.
-    pub fn locations_for_site_and_type(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-    ) -> Result<Vec<Location>> {
-        debug_assert!(site.id() > 0);
-        crate::location::all_locations_for_site_and_type(&self.db_conn, site, sounding_type)
+    /// Set a hook to fetch files that are missing from the archive.
+    ///
+    /// When `retrieve` asks for a file that is not in the archive, the hook is called to get it, the
+    /// result is added to the archive, and then it is returned as if it had been there all along.
+    /// When `most_recent_analysis` finds no files at all for a site and sounding type, the hook is
+    /// asked for the latest scheduled run instead. This lets the archive act as a lazily filled
+    /// cache in front of a remote source. Without a hook, a missing file is an error as usual.
+    ///
+    /// The location and end time of the new file are taken from the fetched soundings.
+    pub fn set_fetch_hook(&mut self, hook: Box<FetchFn>) {
+        self.fetch_hook = Some(FetchHook(hook));
     }
 
-    /// Validate that this `Location` is in the index.
+    /// Register a decoder for a `FileType`, replacing any decoder already registered for it.
     ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_location(&self, location: Location) -> Result<Location> {
-        if location.is_valid() {
-            Ok(location)
-        } else if let Some(retrieved_loc) = crate::location::retrieve_location(
-            &self.db_conn,
-            location.latitude(),
-            location.longitude(),
-            location.elevation(),
-        )? {
-            Ok(retrieved_loc)
-        } else {
-            Err(BufkitDataErr::InvalidLocation(location))
-        }
+    /// Files are decoded with the decoder for the `FileType` of their `SoundingType`. A decoder
+    /// for `FileType::BUFKIT` is registered by default, retrieving any other type without
+    /// registering a decoder for it is a `BufkitDataErr::UnknownFileType` error.
+    pub fn register_decoder(&mut self, file_type: FileType, decoder: Box<dyn SoundingDecoder>) {
+        self.decoders.insert(file_type, decoder);
     }
 
-    /// Validate that this `Location` is in the index, if not, add it to the index.
+    /// The template used to name the files stored in the archive.
+    pub fn file_name_template(&self) -> &str {
+        self.file_name_template.as_str()
+    }
+
+    /// Set the codec used to store files added to the archive from now on.
     ///
-    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
-    /// method.
-    pub fn validate_or_add_location(&self, location: Location) -> Result<Location> {
-        if location.is_valid() {
-            Ok(location)
-        } else {
-            if let Some(retrieved_loc) = crate::location::retrieve_location(
-                &self.db_conn,
-                location.latitude(),
-                location.longitude(),
-                location.elevation(),
-            )? {
-                Ok(retrieved_loc)
-            } else {
-                crate::location::insert_location(&self.db_conn, location)
-            }
-        }
+    /// The default is `Codec::Gzip`. Files already in the archive keep the codec they were stored
+    /// with, so an archive may hold a mix of codecs.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Query archive inventory
-    // ---------------------------------------------------------------------------------------------
+    /// Set the level of gzip compression used to store files added to the archive from now on.
+    ///
+    /// This goes from 0 for no compression to 9 for the best and slowest, levels above 9 are
+    /// treated as 9. The default is 6, which is a good balance for most archives. It only applies
+    /// while the codec is `Codec::Gzip`, and isn't saved in the index, so it has to be set again
+    /// each time the archive is opened.
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.compression = Compression::new(level.min(9));
+    }
 
-    /// Get an inventory of soundings for a `Site` and `SoundingType`.
-    pub fn inventory(&self, site: &Site) -> Result<Inventory> {
-        debug_assert!(site.id() > 0);
-        crate::inventory::inventory(&self.db_conn, site.clone())
+    /// Set what happens when a file is added for a fixed site, but it is already in the archive
+    /// with a different location.
+    ///
+    /// This is the default for every add, `add_file_with_policy` can override it for a single
+    /// file. The default is `OnConflict::Replace`. Mobile sites are expected to move, so this
+    /// doesn't apply to them.
+    pub fn set_on_conflict(&mut self, on_conflict: OnConflict) {
+        self.on_conflict = on_conflict;
     }
 
-    /// Retrieve the model initialization time of the most recent model in the archive.
-    pub fn most_recent_init_time(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-    ) -> Result<NaiveDateTime> {
-        debug_assert!(site.id() > 0);
-        debug_assert!(sounding_type.id() > 0);
+    /// Set how the initialization time is matched when looking up a file.
+    ///
+    /// The default is `InitTimeMatch::Exact`, so a request for 12:01 will not find the 12Z run.
+    /// With `InitTimeMatch::Within` the closest run inside the tolerance is used instead. This
+    /// applies to `file_exists` and the methods that look up a single file by its initialization
+    /// time, such as `retrieve` and `export`, but not to adding or removing files.
+    pub fn set_init_time_match(&mut self, init_time_match: InitTimeMatch) {
+        self.init_time_match = init_time_match;
+    }
 
-        let init_time: NaiveDateTime = self.db_conn.query_row(
-            "
-                SELECT init_time FROM files
-                WHERE site_id = ?1 AND type_id = ?2
-                ORDER BY init_time DESC
-                LIMIT 1
-            ",
-            &[&site.id(), &sounding_type.id()],
-            |row| row.get(0),
-        )?;
+    /// Set how far past the current time the init time of a new file may be, or `None` to allow
+    /// any init time.
+    ///
+    /// An init time further in the future than this is almost certainly a bad file, and it would
+    /// be returned as the most recent run until the real time caught up with it. Adding such a
+    /// file is a `BufkitDataErr::InitTimeInFuture` error. The default is 48 hours.
+    pub fn set_max_init_time_lead(&mut self, max_lead: Option<Duration>) {
+        self.max_init_time_lead = max_lead;
+    }
 
-        Ok(init_time)
+    /// Check that the init time given when adding a file matches the file's contents, or `None`
+    /// to not check.
+    ///
+    /// The init time is supplied separately from the file, so a mislabeled file can end up stored
+    /// under the wrong time. With a tolerance set, each file is decoded as it is added and the
+    /// valid time of its first sounding, the analysis or launch time, must be within the tolerance
+    /// of the init time or it is a `BufkitDataErr::ValidTimeMismatch` error. Decoding makes adding
+    /// files slower, so this is off by default.
+    pub fn set_valid_time_tolerance(&mut self, tolerance: Option<Duration>) {
+        self.valid_time_tolerance = tolerance;
     }
 
-    /// Check to see if a file is present in the archive and it is retrieveable.
-    pub fn file_exists(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        init_time: &NaiveDateTime,
-    ) -> Result<bool> {
-        debug_assert!(site.id() > 0);
-        debug_assert!(sounding_type.id() > 0);
+    /// Store the files somewhere other than the files directory, such as object storage.
+    ///
+    /// Files already in the archive are not copied to the new store. `relocate_blobs` and
+    /// `fsck_paths` only work with the files directory, and `relocate_blobs` switches back to it.
+    pub fn set_blob_store(&mut self, blobs: Box<dyn BlobStore>) {
+        self.blobs = blobs;
+    }
 
-        let num_records: i32 = self.db_conn.query_row(
-            "SELECT COUNT(*) FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
-            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
-            |row| row.get(0),
-        )?;
+    /// Move the files to a different directory, such as on a bigger volume, while the index stays
+    /// in the root.
+    ///
+    /// The new directory is saved in the index, so it is used again when the archive is opened with
+    /// `connect`. It is created if it doesn't exist. If any file can't be moved, the files already
+    /// moved are put back and the archive keeps using the old directory.
+    pub fn relocate_blobs(&mut self, new_dir: &Path) -> Result<()> {
+        create_dir_all(new_dir)?;
+        let new_dir = new_dir.canonicalize()?;
+        if new_dir == self.file_dir.canonicalize()? {
+            return Ok(());
+        }
 
-        Ok(num_records == 1)
-    }
+        let file_names: Vec<_> = read_dir(&self.file_dir)?
+            .filter_map(|de| de.ok())
+            .filter(|de| de.path().is_file())
+            .map(|de| de.file_name())
+            .collect();
 
-    /// Get the number of files stored in the archive.
-    pub fn count(&self) -> Result<i64> {
-        let num_records: i64 =
-            self.db_conn
-                .query_row("SELECT COUNT(*) FROM files", NO_PARAMS, |row| row.get(0))?;
+        let mut moved = vec![];
+        for file_name in &file_names {
+            match move_file(&self.file_dir.join(file_name), &new_dir.join(file_name)) {
+                Ok(()) => moved.push(file_name),
+                Err(err) => {
+                    for file_name in moved {
+                        move_file(&new_dir.join(file_name), &self.file_dir.join(file_name))?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
 
-        Ok(num_records)
+        crate::meta::store_meta(
+            &self.db_conn,
+            Archive::BLOB_ROOT_KEY,
+            &new_dir.to_string_lossy(),
+        )?;
+        self.blobs = Box::new(FileSystemBlobStore::new(&new_dir));
+        self.file_dir = new_dir;
+
+        Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Add, remove, and retrieve files from the archive
-    // ---------------------------------------------------------------------------------------------
+    /// Turn the trash on or off.
+    ///
+    /// When it is on, files removed from the archive by `remove` or the prune methods are moved to
+    /// a `trash` directory in the root instead of being deleted. They can be put back with
+    /// `restore`, or deleted for good with `empty_trash`. The default is off.
+    pub fn set_trash_enabled(&mut self, trash_enabled: bool) {
+        self.trash_enabled = trash_enabled;
+    }
 
-    /// Add a file to the archive.
-    pub fn add_file(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        location: &Location,
-        init_time: &NaiveDateTime,
-        end_time: &NaiveDateTime,
-        file_name: &str,
-    ) -> Result<()> {
-        debug_assert!(site.is_valid());
-        debug_assert!(sounding_type.is_valid());
-        debug_assert!(location.is_valid());
+    /// Permanently delete all the files in the trash.
+    ///
+    /// Returns the number of files deleted.
+    pub fn empty_trash(&self) -> Result<usize> {
+        let mut stmt = self.db_conn.prepare("SELECT file_name FROM trash")?;
+        let file_names: Result<Vec<String>> = stmt
+            .query_map(NO_PARAMS, |row: &Row| -> std::result::Result<String, _> {
+                row.get(0)
+            })?
+            .map(|res| res.map_err(BufkitDataErr::Database))
+            .collect();
+        let file_names = file_names?;
 
-        let fname: String = self.compressed_file_name(&site, &sounding_type, init_time);
+        self.delete_from_trash(&file_names)?;
 
-        let mut in_file = File::open(file_name)?;
-        let out_file = File::create(self.file_dir.join(&fname))?;
-        let mut encoder = GzEncoder::new(out_file, Compression::default());
-        std::io::copy(&mut in_file, &mut encoder)?;
+        Ok(file_names.len())
+    }
 
-        self.db_conn.execute(
-            "
-                INSERT OR REPLACE INTO files 
-                    (type_id, site_id, location_id, init_time, end_time, file_name)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-            ",
-            &[
-                &sounding_type.id(),
-                &site.id(),
-                &location.id(),
-                &init_time as &ToSql,
-                &end_time as &ToSql,
-                &fname,
-            ],
-        )?;
+    /// Permanently delete files in the trash, along with their rows in the trash table.
+    fn delete_from_trash(&self, file_names: &[String]) -> Result<()> {
+        let trash_dir = self.root.join(Archive::TRASH_DIR);
+        for file_name in file_names {
+            match remove_file(trash_dir.join(file_name)) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(BufkitDataErr::Io(err)),
+            }
+            self.db_conn
+                .execute("DELETE FROM trash WHERE file_name = ?1", &[file_name])?;
+        }
 
         Ok(())
     }
 
-    fn get_file_name_for(
+    /// Put a file from the trash back in the archive.
+    ///
+    /// It is an error if the file isn't in the trash, or if another file for the same `site`,
+    /// `sounding_type`, and `init_time` has been added to the archive since it was removed.
+    pub fn restore(
         &self,
         site: &Site,
         sounding_type: &SoundingType,
         init_time: &NaiveDateTime,
-    ) -> Result<String> {
-        debug_assert!(site.id() > 0, "Site not checked or added in index");
-        debug_assert!(
-            sounding_type.id() > 0,
-            "Sounding type not checked or added in index."
-        );
-
+    ) -> Result<()> {
+        let init_time = &truncate_to_minute(init_time);
         let file_name: String = self.db_conn.query_row(
-            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
-            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
+            "SELECT file_name FROM trash WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
             |row| row.get(0),
         )?;
 
-        Ok(file_name)
+        self.db_conn.execute_batch("BEGIN")?;
+        let restored = self
+            .db_conn
+            .execute(
+                &format!(
+                    "INSERT INTO files ({0}) SELECT {0} FROM trash WHERE file_name = ?1",
+                    Archive::TRASH_COLUMNS
+                ),
+                &[&file_name],
+            )
+            .map_err(BufkitDataErr::from)
+            .and_then(|_| {
+                let trash_file = self.root.join(Archive::TRASH_DIR).join(&file_name);
+                self.blobs.put(&file_name, &mut File::open(&trash_file)?)?;
+                remove_file(trash_file).map_err(BufkitDataErr::from)
+            })
+            .and_then(|_| {
+                self.db_conn
+                    .execute("DELETE FROM trash WHERE file_name = ?1", &[&file_name])
+                    .map_err(BufkitDataErr::from)
+            });
+
+        match restored {
+            Ok(_) => self.db_conn.execute_batch("COMMIT")?,
+            Err(err) => {
+                self.db_conn.execute_batch("ROLLBACK")?;
+                return Err(err);
+            }
+        }
+
+        Ok(())
     }
 
-    fn load_data(&self, file_name: &str) -> Result<Vec<u8>> {
-        let file = File::open(self.file_dir.join(file_name))?;
-        let mut decoder = GzDecoder::new(file);
-        let mut buf: Vec<u8> = vec![];
-        let _bytes_read = decoder.read_to_end(&mut buf)?;
+    /// Remove a file from the blob store and the index, or move it to the trash if it is on.
+    fn delete_file(&self, file_name: &str) -> Result<()> {
+        if self.trash_enabled {
+            let trash_dir = self.root.join(Archive::TRASH_DIR);
+            create_dir_all(&trash_dir)?;
+            std::io::copy(
+                &mut self.blobs.get(file_name)?,
+                &mut File::create(trash_dir.join(file_name))?,
+            )?;
+            self.blobs.delete(file_name)?;
+
+            self.db_conn.execute(
+                &format!(
+                    "
+                        INSERT OR REPLACE INTO trash ({0}, deleted_at)
+                        SELECT {0}, ?2 FROM files WHERE file_name = ?1
+                    ",
+                    Archive::TRASH_COLUMNS
+                ),
+                &[&file_name as &dyn ToSql, &Utc::now().naive_utc()],
+            )?;
+        } else {
+            self.blobs.delete(file_name)?;
+        }
 
-        Ok(buf)
+        self.db_conn
+            .execute("DELETE FROM files WHERE file_name = ?1", &[file_name])?;
+
+        Ok(())
     }
 
-    fn decode_data(buf: &[u8], description: &str, ftype: FileType) -> Result<Vec<Analysis>> {
-        match ftype {
-            FileType::BUFKIT => {
-                let bufkit_str = from_utf8(&buf)?;
-                let bufkit_data = BufkitData::init(bufkit_str, description)?;
-                let bufkit_anals: Vec<Analysis> = bufkit_data.into_iter().collect();
-                Ok(bufkit_anals)
+    /// Remove several files with `delete_file`, returning how many were removed.
+    ///
+    /// A file that can't be removed is skipped and stays in both the blob store and the index. The
+    /// rest are still removed, and then the first error is returned.
+    fn delete_files<I, S>(&self, file_names: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut num_removed = 0;
+        let mut first_err = None;
+        for file_name in file_names {
+            match self.delete_file(file_name.as_ref()) {
+                Ok(()) => num_removed += 1,
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
             }
-            FileType::BUFR => unimplemented!(),
-            FileType::UNKNOWN => Err(BufkitDataErr::UnknownFileType),
         }
-    }
 
-    /// Retrieve an analysis from the archive.
-    pub fn retrieve(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        init_time: &NaiveDateTime,
-    ) -> Result<Vec<Analysis>> {
-        let file_name = self.get_file_name_for(site, sounding_type, init_time)?;
-        let data = self.load_data(&file_name)?;
-        Self::decode_data(&data, &file_name, sounding_type.file_type())
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(num_removed),
+        }
     }
 
-    /// Retrieve all analyses for a `Site` and `SoundingType` that have any data valid during
-    /// the specified period.
-    pub fn retrieve_all(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        start_time: &NaiveDateTime,
-        end_time: &NaiveDateTime,
-    ) -> Result<Vec<Vec<Analysis>>> {
-        // Get a list of file names
+    /// Remove the least recently accessed files until the total size of the files in the archive
+    /// is no more than `keep_bytes`.
+    ///
+    /// This is useful when the archive is used as a cache. Returns the number of files removed.
+    pub fn prune_least_recently_used(&self, keep_bytes: u64) -> Result<usize> {
         let mut stmt = self.db_conn.prepare(
             "
-                SELECT file_name 
+                SELECT file_name
                 FROM files
-                WHERE site_id = ?1 AND type_id = ?2 AND end_time >= ?3 AND init_time <= ?4
-                ORDER BY init_time ASC
+                ORDER BY last_accessed ASC, init_time ASC
             ",
         )?;
 
-        let vals: Result<Vec<Vec<Analysis>>> = stmt
-            .query_map(
-                &[
-                    &site.id(),
-                    &sounding_type.id(),
-                    &start_time as &ToSql,
-                    &end_time,
-                ],
-                |row: &Row| -> std::result::Result<String, rusqlite::Error> { row.get(0) },
-            )?
-            .map(|res| res.map_err(BufkitDataErr::from))
-            .map(|res| res.and_then(|fname| self.load_data(&fname).map(|data| (fname, data))))
+        let files: Result<Vec<(String, u64)>> = stmt
+            .query_map(NO_PARAMS, |row: &Row| -> std::result::Result<String, _> {
+                row.get(0)
+            })?
+            .map(|res| res.map_err(BufkitDataErr::Database))
             .map(|res| {
-                res.and_then(|(fname, data)| {
-                    Self::decode_data(&data, &fname, sounding_type.file_type())
+                res.map(|fname| {
+                    let size = self.blobs.size(&fname).unwrap_or(0);
+                    (fname, size)
                 })
             })
             .collect();
+        let files = files?;
 
-        vals
-    }
+        let mut total_bytes: u64 = files.iter().map(|(_, size)| size).sum();
+        let mut num_removed = 0;
+        for (fname, size) in files {
+            if total_bytes <= keep_bytes {
+                break;
+            }
 
-    /// Retrieve and uncompress a file.
-    pub fn export(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        init_time: &NaiveDateTime,
-    ) -> Result<impl Read> {
-        let file_name = self.get_file_name_for(site, sounding_type, init_time)?;
-        let file = File::open(self.file_dir.join(file_name))?;
-        Ok(GzDecoder::new(file))
+            self.delete_file(&fname)?;
+
+            total_bytes -= size;
+            num_removed += 1;
+        }
+
+        Ok(num_removed)
     }
 
-    /// Retrieve the  most recent file as a sounding.
-    pub fn most_recent_analysis(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-    ) -> Result<Vec<Analysis>> {
-        let init_time = self.most_recent_init_time(site, sounding_type)?;
-        self.retrieve(site, sounding_type, &init_time)
+    /// Remove all the files for a single `Site` initialized before `cutoff`.
+    ///
+    /// Files for other sites are left alone, so each site can be kept for a different length of
+    /// time. If the trash is enabled the files are moved there, see `set_trash_enabled`. Each file is
+    /// taken out of the index only after it is removed from storage, so a file that can't be removed
+    /// is skipped and stays in both. The rest are still removed, and then the first error is
+    /// returned. Otherwise returns the number of files removed.
+    pub fn prune_site_older_than(&self, site: &Site, cutoff: &NaiveDateTime) -> Result<usize> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT file_name FROM files WHERE site_id = ?1 AND init_time < ?2")?;
+        let file_names: Result<Vec<String>> = stmt
+            .query_map(&[&site.id(), cutoff as &dyn ToSql], |row| row.get(0))?
+            .map(|res| res.map_err(BufkitDataErr::Database))
+            .collect();
+
+        self.delete_files(file_names?)
     }
 
-    fn compressed_file_name(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
+    // The most parameters sqlite allows in a single statement by default.
+    const MAX_QUERY_PARAMS: usize = 999;
+
+    // Added to the name of a file while it waits to replace the file with its name.
+    const STAGED_EXT: &'static str = ".staged";
+
+    // The directory in the root for files removed while the trash is enabled.
+    const TRASH_DIR: &'static str = "trash";
+
+    // The columns the files and trash tables have in common.
+    const TRASH_COLUMNS: &'static str = "type_id, site_id, location_id, init_time, end_time, \
+                                         file_name, codec, last_accessed, checksum, \
+                                         received_time, profile_count, comment";
+
+    // Keys for settings stored in the meta table of the index.
+    const FILE_NAME_TEMPLATE_KEY: &'static str = "file_name_template";
+    const BLOB_ROOT_KEY: &'static str = "blob_root";
+    const CREATED_BY_VERSION_KEY: &'static str = "created_by_version";
+
+    // The default for how far in the future the init time of a new file may be, in hours.
+    const DEFAULT_MAX_INIT_TIME_LEAD_HOURS: i64 = 48;
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify site metadata
+    // ---------------------------------------------------------------------------------------------
+
+    /// Retrieve a list of all the `Site`s in the archive.
+    pub fn sites(&self) -> Result<Vec<Site>> {
+        crate::site::all_sites(&self.db_conn)
+    }
+
+    /// Retrieve a page of at most `limit` `Site`s, skipping the first `offset`.
+    ///
+    /// They are ordered by short name so the pages are stable.
+    pub fn sites_paged(&self, limit: usize, offset: usize) -> Result<Vec<Site>> {
+        crate::site::sites_paged(&self.db_conn, limit, offset)
+    }
+
+    /// Remove a `Site` from the index along with all of its files.
+    ///
+    /// The files are deleted for good, even if the trash is enabled, and any of the site's files
+    /// already in the trash are deleted too, since they couldn't be restored without the site. A
+    /// file that is already missing from storage is not an error. The site itself is only removed
+    /// once all its files are, so if deleting a file fails the error is returned and the site is
+    /// left with the files that are still there. Returns the number of files removed, not counting
+    /// the trash.
+    pub fn remove_site(&self, site: &Site) -> Result<usize> {
+        debug_assert!(site.id() > 0);
+
+        let num_removed = self.purge_files_with("site_id", site.id())?;
+        self.db_conn
+            .execute("DELETE FROM sites WHERE id = ?1", [site.id()])?;
+
+        Ok(num_removed)
+    }
+
+    /// Delete every file with this id in the `site_id` or `type_id` column for good, including
+    /// those in the trash, and return how many were deleted, not counting the trash.
+    ///
+    /// A file already missing from storage is not an error. On any other error the files not yet
+    /// deleted are left in the index.
+    fn purge_files_with(&self, id_column: &str, id: i64) -> Result<usize> {
+        let file_names = |table: &str| -> Result<Vec<String>> {
+            let mut stmt = self.db_conn.prepare(&format!(
+                "SELECT file_name FROM {} WHERE {} = ?1",
+                table, id_column
+            ))?;
+            let file_names: Result<Vec<String>> = stmt
+                .query_map([id], |row| row.get(0))?
+                .map(|res| res.map_err(BufkitDataErr::Database))
+                .collect();
+            file_names
+        };
+
+        let files = file_names("files")?;
+        for file_name in &files {
+            match self.blobs.delete(file_name) {
+                Ok(()) => {}
+                Err(BufkitDataErr::Io(ref err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            self.db_conn
+                .execute("DELETE FROM files WHERE file_name = ?1", &[file_name])?;
+        }
+
+        self.delete_from_trash(&file_names("trash")?)?;
+
+        Ok(files.len())
+    }
+
+    /// Retrieve only the mobile sites with `Some(true)`, only the fixed sites with `Some(false)`,
+    /// or all of them with `None`, ordered by short name.
+    pub fn sites_filtered(&self, mobile: Option<bool>) -> Result<Vec<Site>> {
+        crate::site::sites_filtered(&self.db_conn, mobile)
+    }
+
+    /// Retrieve the sites in a state or province, ordered by short name.
+    pub fn sites_in_state(&self, state: StateProv) -> Result<Vec<Site>> {
+        crate::site::sites_in_state(&self.db_conn, state)
+    }
+
+    /// Retrieve the sites with a long name containing `substring`, ordered by short name.
+    ///
+    /// The search ignores case, at least for ASCII letters, and sites without a long name never
+    /// match.
+    pub fn find_sites_by_long_name(&self, substring: &str) -> Result<Vec<Site>> {
+        crate::site::sites_by_long_name(&self.db_conn, substring)
+    }
+
+    /// Retrieve the sites that have any files, along with the most recent initialization time of
+    /// any of their files.
+    ///
+    /// The sites with the freshest data come first.
+    pub fn sites_by_recency(&self) -> Result<Vec<(Site, NaiveDateTime)>> {
+        crate::site::sites_by_recency(&self.db_conn)
+    }
+
+    /// Retrieve the information about a single `Site` with the supplied `short_name`.
+    ///
+    /// Returns `Ok(None)` if none exists in the archive, and returns `Ok(Some(_))` with the
+    /// corresponding `Site` object if one does exist.
+    pub fn site_info(&self, short_name: &str) -> Result<Option<Site>> {
+        crate::site::retrieve_site(&self.db_conn, short_name)
+    }
+
+    /// Modify an existing `Site`'s values.
+    ///
+    /// The supplied site need not be validated, the returned site will be. It is an error if there
+    /// is not a site in the index with the same `short_name` to modify.
+    pub fn set_site_info(&self, site: Site) -> Result<Site> {
+        crate::site::update_site(&self.db_conn, site)
+    }
+
+    /// Validate that this `Site` is in the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method.
+    pub fn validate_site(&self, site: Site) -> Result<Site> {
+        if site.is_valid() {
+            Ok(site)
+        } else if let Some(retrieved_site) =
+            crate::site::retrieve_site(&self.db_conn, site.short_name())?
+        {
+            Ok(retrieved_site)
+        } else {
+            Err(BufkitDataErr::InvalidSite(site))
+        }
+    }
+
+    /// Validate that this `Site` is in the index, if not, insert it into the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method. A site with a blank short name can't be added, that is a
+    /// `BufkitDataErr::InvalidSite` error.
+    pub fn validate_or_add_site(&self, site: Site) -> Result<Site> {
+        if site.is_valid() {
+            Ok(site)
+        } else {
+            if let Some(retrieved_site) =
+                crate::site::retrieve_site(&self.db_conn, site.short_name())?
+            {
+                Ok(retrieved_site)
+            } else {
+                crate::site::insert_site(&self.db_conn, site)
+            }
+        }
+    }
+
+    /// Look up a `Site` in the index again by its short name, ignoring the id it already has.
+    ///
+    /// A validated `Site` remembers the row it came from in the index. That is only a problem if
+    /// the index is edited with something other than this crate, for example a migration that
+    /// renumbers the rows, after which the remembered row may belong to a different site. Pass any
+    /// objects held across such an edit through here to get the current version from the index.
+    pub fn refresh_site(&self, site: Site) -> Result<Site> {
+        crate::site::retrieve_site(&self.db_conn, site.short_name())?
+            .ok_or(BufkitDataErr::InvalidSite(site))
+    }
+
+    /// Change the short name of a site, for example when a station gets a new identifier.
+    ///
+    /// The short name is part of the name of every file for the site, so the files are renamed
+    /// too. The files are copied to their new names before the index is updated, and nothing is
+    /// changed if that fails. The old copies are deleted last, any that can't be are left for
+    /// `check` to find. Files in the trash keep their old names. It is an error if there isn't a
+    /// site named `old_short_name`, or if there already is one named `new_short_name`.
+    pub fn rename_site(&self, old_short_name: &str, new_short_name: &str) -> Result<Site> {
+        let site = crate::site::retrieve_site(&self.db_conn, old_short_name)?
+            .ok_or_else(|| BufkitDataErr::InvalidSite(Site::new(old_short_name)))?;
+        if let Some(existing) = crate::site::retrieve_site(&self.db_conn, new_short_name)? {
+            return Err(BufkitDataErr::SiteExists(existing));
+        }
+
+        let renames: Vec<(String, String)> = self
+            .query()
+            .site(old_short_name)
+            .execute()?
+            .iter()
+            .map(|record| {
+                let new_name = self.file_name_for_site(record, new_short_name);
+                (record.file_name().to_owned(), new_name)
+            })
+            .collect();
+        self.copy_blobs(&renames)?;
+
+        self.db_conn.execute_batch("BEGIN")?;
+        let renamed =
+            crate::site::rename_site(&self.db_conn, &site, new_short_name).and_then(|renamed| {
+                for (old_name, new_name) in &renames {
+                    self.db_conn.execute(
+                        "UPDATE files SET file_name = ?2 WHERE file_name = ?1",
+                        &[old_name, new_name],
+                    )?;
+                }
+                Ok(renamed)
+            });
+
+        match renamed {
+            Ok(renamed) => {
+                self.db_conn.execute_batch("COMMIT")?;
+                for (old_name, _) in &renames {
+                    let _ = self.blobs.delete(old_name);
+                }
+                Ok(renamed)
+            }
+            Err(err) => {
+                self.db_conn.execute_batch("ROLLBACK")?;
+                for (_, new_name) in &renames {
+                    let _ = self.blobs.delete(new_name);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Move all the files for the site `from` to the site `into`, then remove `from` from the
+    /// index, for example to combine two entries that were added for the same station.
+    ///
+    /// The files are renamed for their new site in the same way as `rename_site`. Where both sites
+    /// have a file for the same sounding type and init time, the file already at `into` is kept and
+    /// the one from `from` is deleted. Files in the trash are moved to `into` but keep their old
+    /// names. Merging a site into itself does nothing.
+    pub fn merge_sites(&self, from: &Site, into: &Site) -> Result<()> {
+        debug_assert!(from.id() > 0);
+        debug_assert!(into.id() > 0);
+
+        if from.id() == into.id() {
+            return Ok(());
+        }
+
+        let mut renames: Vec<(String, String)> = vec![];
+        let mut dropped: Vec<String> = vec![];
+        for record in self.query().site(from.short_name()).execute()? {
+            let in_both: bool = self.db_conn.query_row(
+                "SELECT COUNT(*) > 0 FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+                &[&into.id(), &record.sounding_type().id(), &record.init_time() as &dyn ToSql],
+                |row| row.get(0),
+            )?;
+            if in_both {
+                dropped.push(record.file_name().to_owned());
+            } else {
+                let new_name = self.file_name_for_site(&record, into.short_name());
+                renames.push((record.file_name().to_owned(), new_name));
+            }
+        }
+        self.copy_blobs(&renames)?;
+
+        self.db_conn.execute_batch("BEGIN")?;
+        let merged = || -> Result<()> {
+            for (old_name, new_name) in &renames {
+                self.db_conn.execute(
+                    "UPDATE files SET site_id = ?2, file_name = ?3 WHERE file_name = ?1",
+                    &[old_name as &dyn ToSql, &into.id(), new_name],
+                )?;
+            }
+            for file_name in &dropped {
+                self.db_conn
+                    .execute("DELETE FROM files WHERE file_name = ?1", &[file_name])?;
+            }
+            self.db_conn.execute(
+                "UPDATE trash SET site_id = ?2 WHERE site_id = ?1",
+                [from.id(), into.id()],
+            )?;
+            self.db_conn
+                .execute("DELETE FROM sites WHERE id = ?1", [from.id()])?;
+            Ok(())
+        };
+
+        match merged() {
+            Ok(()) => {
+                self.db_conn.execute_batch("COMMIT")?;
+                let old_names = renames.iter().map(|(old_name, _)| old_name);
+                for file_name in old_names.chain(&dropped) {
+                    let _ = self.blobs.delete(file_name);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                self.db_conn.execute_batch("ROLLBACK")?;
+                for (_, new_name) in &renames {
+                    let _ = self.blobs.delete(new_name);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The name a file would have if it belonged to the site with this short name.
+    fn file_name_for_site(&self, record: &FileRecord, short_name: &str) -> String {
+        self.file_name_template.render(
+            &record.init_time(),
+            record.sounding_type().source(),
+            record.sounding_type().file_type().as_static(),
+            short_name,
+            record.codec().extension(),
+        )
+    }
+
+    /// Copy each blob to a new name, given as `(old_name, new_name)` pairs.
+    ///
+    /// If any copy fails, the copies already made are deleted before the error is returned.
+    fn copy_blobs(&self, renames: &[(String, String)]) -> Result<()> {
+        for (i, (old_name, new_name)) in renames.iter().enumerate() {
+            let copied = self
+                .blobs
+                .get(old_name)
+                .and_then(|mut src| self.blobs.put(new_name, &mut src));
+
+            if let Err(err) = copied {
+                for (_, new_name) in &renames[..i] {
+                    let _ = self.blobs.delete(new_name);
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify sounding type metadata
+    // ---------------------------------------------------------------------------------------------
+
+    /// Retrieve a list of all the `SoundingType`s in the archive.
+    pub fn sounding_types(&self) -> Result<Vec<SoundingType>> {
+        crate::sounding_type::all_sounding_types(&self.db_conn)
+    }
+
+    /// Retrieve a page of at most `limit` `SoundingType`s, skipping the first `offset`.
+    ///
+    /// They are ordered by source so the pages are stable.
+    pub fn sounding_types_paged(&self, limit: usize, offset: usize) -> Result<Vec<SoundingType>> {
+        crate::sounding_type::sounding_types_paged(&self.db_conn, limit, offset)
+    }
+
+    /// Find the sounding types that haven't had any new data in a while, such as models that are
+    /// no longer run.
+    ///
+    /// Returns each type whose most recent init time, at any site, is more than `older_than`
+    /// before `now`, along with that init time. Types without any files are included with `None`.
+    pub fn stale_sounding_types(
+        &self,
+        now: &NaiveDateTime,
+        older_than: Duration,
+    ) -> Result<Vec<(SoundingType, Option<NaiveDateTime>)>> {
+        crate::sounding_type::stale_sounding_types(&self.db_conn, &(*now - older_than))
+    }
+
+    /// Retrieve the information about a single `SoundingType` with the supplied description, which
+    /// is the same as the result from its `source()` method, ignoring case.
+    ///
+    /// Returns `Ok(None)` if none exists in the archive, and returns `Ok(Some(_))` with the
+    /// corresponding `SoundingType` object if one does exist.
+    pub fn sounding_type_info(&self, sounding_type: &str) -> Result<Option<SoundingType>> {
+        crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type)
+    }
+
+    /// Modify an existing `SoundingType`'s values.
+    ///
+    /// The supplied sounding type need not be validated, the returned one will be. It is an error
+    /// if there is not a sounding type in the index with the same `.source()` to modify. If the
+    /// `FileType` changes, the files already in the archive are checked first in the same way as
+    /// `set_sounding_type_file_type`, and nothing is changed if they don't decode as the new type.
+    pub fn set_sounding_type_info(&self, sounding_type: SoundingType) -> Result<SoundingType> {
+        if let Some(existing) =
+            crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
+        {
+            if existing.file_type() != sounding_type.file_type() {
+                self.check_files_decode_as(&existing, sounding_type.file_type())?;
+            }
+        }
+
+        crate::sounding_type::update_sounding_type(&self.db_conn, sounding_type)
+    }
+
+    /// Change the `FileType` of a `SoundingType`, for example to fix one added with the wrong type.
+    ///
+    /// The files already in the archive would be decoded differently, so every file of this type is
+    /// first decoded with the decoder for the new type, without falling back to any other decoder.
+    /// If any of them fail, the error is returned as a `BufkitDataErr::InFile` and nothing is
+    /// changed. This reads the whole series, so it can be slow for a type with many files.
+    pub fn set_sounding_type_file_type(
+        &self,
+        sounding_type: &SoundingType,
+        file_type: FileType,
+    ) -> Result<SoundingType> {
+        self.check_files_decode_as(sounding_type, file_type)?;
+
+        crate::sounding_type::update_file_type(&self.db_conn, sounding_type, file_type)
+    }
+
+    /// Remove a `SoundingType` from the index along with all of its files, at every site.
+    ///
+    /// This is for retiring a source entirely, such as a model that is no longer run. The files are
+    /// deleted in the same way as `remove_site`, and the type is only removed once all of them are.
+    /// It is an error if there is no sounding type in the index with the same `.source()`. Returns
+    /// the number of files removed, not counting the trash.
+    pub fn remove_sounding_type(&self, sounding_type: &SoundingType) -> Result<usize> {
+        let sounding_type =
+            crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
+                .ok_or_else(|| BufkitDataErr::InvalidSoundingType(sounding_type.clone()))?;
+
+        let num_removed = self.purge_files_with("type_id", sounding_type.id())?;
+        self.db_conn
+            .execute("DELETE FROM types WHERE id = ?1", [sounding_type.id()])?;
+
+        Ok(num_removed)
+    }
+
+    /// Check every file of a sounding type can be decoded as another `FileType`.
+    ///
+    /// A decoder for the new type is only needed if there are any files to check.
+    fn check_files_decode_as(
+        &self,
+        sounding_type: &SoundingType,
+        file_type: FileType,
+    ) -> Result<()> {
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT file_name, codec FROM files WHERE type_id = ?1")?;
+        let files: std::result::Result<Vec<(String, String)>, _> = stmt
+            .query_map([sounding_type.id()], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+        let files = files?;
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        let decoder = self
+            .decoders
+            .get(file_type)
+            .ok_or(BufkitDataErr::UnknownFileType)?;
+
+        for (file_name, codec) in files {
+            let decoded = Codec::from_str(&codec)
+                .map_err(BufkitDataErr::from)
+                .and_then(|codec| self.read_file(&file_name, codec))
+                .and_then(|buf| decoder.decode(&buf, &file_name));
+
+            if let Err(err) = decoded {
+                return Err(BufkitDataErr::InFile(file_name, Box::new(err)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get a list of `SoundingType`s in the archive for this `site`.
+    pub fn sounding_types_for_site(&self, site: &Site) -> Result<Vec<SoundingType>> {
+        debug_assert!(site.id() > 0);
+        crate::sounding_type::all_sounding_types_for_site(&self.db_conn, site)
+    }
+
+    /// Get the `SoundingType`s with any files for this `site` initialized on `date`, ordered by
+    /// source.
+    pub fn types_with_data_on(&self, site: &Site, date: NaiveDate) -> Result<Vec<SoundingType>> {
+        debug_assert!(site.id() > 0);
+        let (start, end) = day_range(date);
+        crate::sounding_type::sounding_types_for_site_between(&self.db_conn, site, &start, &end)
+    }
+
+    /// Get every `SoundingType` along with the number of distinct sites that have files of it.
+    ///
+    /// Types used at the most sites come first, this helps tell the sources available everywhere,
+    /// like "GFS", from ones only available for a few sites.
+    pub fn sounding_type_site_counts(&self) -> Result<Vec<(SoundingType, i64)>> {
+        crate::sounding_type::sounding_type_site_counts(&self.db_conn)
+    }
+
+    /// Validate that this `SoundingType` is in the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method.
+    pub fn validate_sounding_type(&self, sounding_type: SoundingType) -> Result<SoundingType> {
+        if sounding_type.is_valid() {
+            Ok(sounding_type)
+        } else if let Some(retrieved_st) =
+            crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
+        {
+            Ok(retrieved_st)
+        } else {
+            Err(BufkitDataErr::InvalidSoundingType(sounding_type))
+        }
+    }
+
+    /// Look up a `SoundingType` in the index again by its source, ignoring the id it already has.
+    ///
+    /// See `refresh_site` for when this is needed.
+    pub fn refresh_sounding_type(&self, sounding_type: SoundingType) -> Result<SoundingType> {
+        crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
+            .ok_or(BufkitDataErr::InvalidSoundingType(sounding_type))
+    }
+
+    /// Validate that this `SoundingType` is in the index, if not, add it to the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method.
+    pub fn validate_or_add_sounding_type(
+        &self,
+        sounding_type: SoundingType,
+    ) -> Result<SoundingType> {
+        if sounding_type.is_valid() {
+            Ok(sounding_type)
+        } else {
+            if let Some(retrieved_st) =
+                crate::sounding_type::retrieve_sounding_type(&self.db_conn, sounding_type.source())?
+            {
+                Ok(retrieved_st)
+            } else {
+                crate::sounding_type::insert_sounding_type(&self.db_conn, sounding_type)
+            }
+        }
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify location metadata
+    // ---------------------------------------------------------------------------------------------
+
+    /// Retrieve a list of all the `Location`s in the archive.
+    pub fn all_locations(&self) -> Result<Vec<Location>> {
+        crate::location::all_locations(&self.db_conn)
+    }
+
+    /// Retrieve a page of at most `limit` `Location`s, skipping the first `offset`.
+    ///
+    /// They are ordered by latitude, longitude, and elevation so the pages are stable.
+    pub fn locations_paged(&self, limit: usize, offset: usize) -> Result<Vec<Location>> {
+        crate::location::locations_paged(&self.db_conn, limit, offset)
+    }
+
+    /// Get the `Location` object for these coordinates.
+    ///
+    /// If there were no errors while querying the index, this will return an `Ok(None)` meaning
+    /// that no errors occurred, but there was matching locaiton in the index.
+    pub fn location_info(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        elevation_m: i32,
+    ) -> Result<Option<Location>> {
+        crate::location::retrieve_location(&self.db_conn, latitude, longitude, elevation_m)
+    }
+
+    /// Retrieve the `Location` object associated with these coordinates, or insert a new one into
+    /// the index.
+    pub fn retrieve_or_add_location(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        elevation_m: i32,
+    ) -> Result<Location> {
+        crate::location::retrieve_or_add_location(&self.db_conn, latitude, longitude, elevation_m)
+    }
+
+    /// Modify an existing `Location`'s values.
+    ///
+    /// The supplied location need not be validated, the returned one will be. It is an error if
+    /// there is not a matching `Location` in the index with the same coordinates to modify.
+    /// Basically you can only modify the time zone offset information.
+    pub fn set_location_info(&self, location: Location) -> Result<Location> {
+        crate::location::update_location(&self.db_conn, location)
+    }
+
+    /// Find the `Location` in the index closest to a point, as long as it is within `max_km`
+    /// kilometers of it.
+    ///
+    /// Unlike `location_info`, this doesn't need the coordinates to match exactly, so it can match
+    /// an incoming sounding to a location despite small differences in how the coordinates were
+    /// rounded. The elevation is ignored, and distances are great circle distances.
+    pub fn nearest_location(&self, lat: f64, lon: f64, max_km: f64) -> Result<Option<Location>> {
+        let nearest = crate::location::all_locations(&self.db_conn)?
+            .into_iter()
+            .map(|loc| {
+                let dist = crate::location::haversine_km(lat, lon, loc.latitude(), loc.longitude());
+                (dist, loc)
+            })
+            .filter(|(dist, _)| *dist <= max_km)
+            .fold(
+                None,
+                |nearest: Option<(f64, Location)>, (dist, loc)| match nearest {
+                    Some((nearest_dist, _)) if nearest_dist <= dist => nearest,
+                    _ => Some((dist, loc)),
+                },
+            )
+            .map(|(_, loc)| loc);
+
+        Ok(nearest)
+    }
+
+    /// Fill in the time zone offset for every `Location` in the index that doesn't have one.
+    ///
+    /// There is no time zone boundary data available offline, so the offset is estimated from the
+    /// longitude with `Location::estimated_tz_offset`. Locations that already have an offset are
+    /// left alone, so use `set_location_info` first for any that need to be exact. Returns the
+    /// number of locations updated.
+    pub fn populate_tz_offsets(&self) -> Result<usize> {
+        let locations = crate::location::locations_without_tz_offset(&self.db_conn)?;
+
+        self.db_conn.execute_batch("BEGIN")?;
+        let populated = locations.iter().try_for_each(|loc| {
+            let loc = loc.with_tz_offset(loc.estimated_tz_offset());
+            crate::location::update_location(&self.db_conn, loc).map(|_| ())
+        });
+
+        match populated {
+            Ok(()) => self.db_conn.execute_batch("COMMIT")?,
+            Err(err) => {
+                self.db_conn.execute_batch("ROLLBACK")?;
+                return Err(err);
+            }
+        }
+
+        Ok(locations.len())
+    }
+
+    /// Get a list of `Location`s in the archive for this site.
+    pub fn locations_for_site_and_type(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<Vec<Location>> {
+        debug_assert!(site.id() > 0);
+        crate::location::all_locations_for_site_and_type(&self.db_conn, site, sounding_type)
+    }
+
+    /// Count the distinct `Location`s the files for this site come from.
+    ///
+    /// For a mobile site this is the number of places it has reported from. A fixed site should
+    /// have 1, unless the station has moved, so more than that may point to bad data.
+    pub fn location_count_for_site(&self, site: &Site) -> Result<i64> {
+        debug_assert!(site.id() > 0);
+        crate::location::location_count_for_site(&self.db_conn, site)
+    }
+
+    /// Validate that this `Location` is in the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method.
+    pub fn validate_location(&self, location: Location) -> Result<Location> {
+        if location.is_valid() {
+            Ok(location)
+        } else if let Some(retrieved_loc) = crate::location::retrieve_location(
+            &self.db_conn,
+            location.latitude(),
+            location.longitude(),
+            location.elevation(),
+        )? {
+            Ok(retrieved_loc)
+        } else {
+            Err(BufkitDataErr::InvalidLocation(location))
+        }
+    }
+
+    /// Look up a `Location` in the index again by its coordinates, ignoring the id it already has.
+    ///
+    /// See `refresh_site` for when this is needed.
+    pub fn refresh_location(&self, location: Location) -> Result<Location> {
+        crate::location::retrieve_location(
+            &self.db_conn,
+            location.latitude(),
+            location.longitude(),
+            location.elevation(),
+        )?
+        .ok_or(BufkitDataErr::InvalidLocation(location))
+    }
+
+    /// Validate that this `Location` is in the index, if not, add it to the index.
+    ///
+    /// Any object returned in an `Ok(_)` from this method will return true from the `.is_valid()`
+    /// method.
+    pub fn validate_or_add_location(&self, location: Location) -> Result<Location> {
+        if location.is_valid() {
+            Ok(location)
+        } else {
+            if let Some(retrieved_loc) = crate::location::retrieve_location(
+                &self.db_conn,
+                location.latitude(),
+                location.longitude(),
+                location.elevation(),
+            )? {
+                Ok(retrieved_loc)
+            } else {
+                crate::location::insert_location(&self.db_conn, location)
+            }
+        }
+    }
+
+    /// Add the locations in a CSV file to the index.
+    ///
+    /// Each row has the columns `latitude,longitude,elevation,tz_offset`, with the time zone offset
+    /// in seconds and left empty if unknown. A header row, like the one written by
+    /// `export_locations_csv`, and blank lines are skipped. Locations already in the index are
+    /// left alone. Returns the number of rows read.
+    pub fn import_locations<R: Read>(&self, reader: R) -> Result<usize> {
+        let mut count = 0;
+        for (line_num, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("latitude") {
+                continue;
+            }
+
+            let bad_row =
+                |msg: &str| BufkitDataErr::GeneralError(format!("line {}: {}", line_num + 1, msg));
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() != 4 {
+                return Err(bad_row("expected 4 columns"));
+            }
+
+            let latitude: f64 = fields[0].parse().map_err(|_| bad_row("bad latitude"))?;
+            let longitude: f64 = fields[1].parse().map_err(|_| bad_row("bad longitude"))?;
+            let elevation: i32 = fields[2].parse().map_err(|_| bad_row("bad elevation"))?;
+            let tz_offset: Option<i32> = if fields[3].is_empty() {
+                None
+            } else {
+                Some(fields[3].parse().map_err(|_| bad_row("bad tz_offset"))?)
+            };
+
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(bad_row("coordinates out of range"));
+            }
+
+            self.validate_or_add_location(Location::new(
+                latitude, longitude, elevation, tz_offset,
+            ))?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Write all the locations in the index as CSV, in the format read by `import_locations`.
+    ///
+    /// Coordinates are written with the full micro-degree precision of the index. Returns the
+    /// number of locations written.
+    pub fn export_locations_csv<W: Write>(&self, mut writer: W) -> Result<usize> {
+        writeln!(writer, "latitude,longitude,elevation,tz_offset")?;
+
+        let locations = self.all_locations()?;
+        for location in &locations {
+            writeln!(
+                writer,
+                "{:.6},{:.6},{},{}",
+                location.latitude(),
+                location.longitude(),
+                location.elevation(),
+                location
+                    .tz_offset()
+                    .map(|offset| offset.to_string())
+                    .unwrap_or_default(),
+            )?;
+        }
+
+        Ok(locations.len())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query archive inventory
+    // ---------------------------------------------------------------------------------------------
+
+    /// Get an inventory of soundings for a `Site` and `SoundingType`.
+    pub fn inventory(&self, site: &Site) -> Result<Inventory> {
+        debug_assert!(site.id() > 0);
+        crate::inventory::inventory(&self.db_conn, site.clone())
+    }
+
+    /// Get the inventories for several `Site`s, in the same order as `sites`.
+    ///
+    /// This gives the same results as calling `inventory` for each site, but looks the sites up a
+    /// batch at a time instead of running several queries for each one. For 200 sites with a year
+    /// of runs of two sounding types each, that took 1.5 s instead of 22 s.
+    pub fn inventories_for(&self, sites: &[Site]) -> Result<Vec<Inventory>> {
+        crate::inventory::inventories_for(&self.db_conn, sites)
+    }
+
+    /// Compare the initialization times available for two `SoundingType`s at a `Site`.
+    ///
+    /// This finds the times both types have data, such as when a model and a radiosonde coincide,
+    /// and the times only one of them does.
+    pub fn compare_coverage(
+        &self,
+        site: &Site,
+        type_a: &SoundingType,
+        type_b: &SoundingType,
+    ) -> Result<CoverageComparison> {
+        crate::coverage::compare_coverage(&self.db_conn, site, type_a, type_b)
+    }
+
+    /// Get the inclusive ranges of initialization times with no missing runs in between.
+    ///
+    /// This is the complement of `Inventory::missing`, and a compact way to describe the available
+    /// data. It requires the `SoundingType` to have a known number of hours between
+    /// initializations; if it doesn't, each initialization time is returned as a range of its own.
+    pub fn coverage_runs(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<Vec<(NaiveDateTime, NaiveDateTime)>> {
+        crate::coverage::coverage_runs(&self.db_conn, site, sounding_type)
+    }
+
+    /// Get every initialization time between `start` and `end`, inclusive, that the
+    /// `SoundingType` should have run at, but there isn't a file for at the `Site`.
+    ///
+    /// Unlike `Inventory::missing`, this includes runs missing at the start or end of the window,
+    /// such as the latest run failing to download, so it is handy for driving a re-download job.
+    /// The expected times are whole steps of the hours between initializations from 00Z, e.g.
+    /// 00Z, 06Z, 12Z, and 18Z for a 6 hour interval. A type without a known interval returns an
+    /// empty list.
+    pub fn missing_between(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+    ) -> Result<Vec<NaiveDateTime>> {
+        crate::coverage::missing_between(&self.db_conn, site, sounding_type, start, end)
+    }
+
+    /// Guess the number of hours between initializations of a `SoundingType` from the files in
+    /// the archive.
+    ///
+    /// This is the most common spacing between consecutive initialization times at each site, with
+    /// ties going to the shorter spacing. It can be used to fill in the interval of a new
+    /// `SoundingType` after adding some files, so `Inventory::missing` can find the gaps. Returns
+    /// `None` if there isn't a site with at least two files of this type.
+    pub fn infer_interval(&self, sounding_type: &SoundingType) -> Result<Option<u16>> {
+        crate::coverage::infer_interval(&self.db_conn, sounding_type)
+    }
+
+    /// Get the initialization times of a `SoundingType` that are available at every one of
+    /// `sites`, sorted in ascending order.
+    ///
+    /// If `sites` is empty, every site with at least one file of this type is used. This is handy
+    /// for building an analysis across several sites that only uses runs they all have.
+    pub fn common_init_times(
+        &self,
+        sounding_type: &SoundingType,
+        sites: &[Site],
+    ) -> Result<Vec<NaiveDateTime>> {
+        crate::coverage::common_init_times(&self.db_conn, sounding_type, sites)
+    }
+
+    /// Get the average number of files per day for each `SoundingType` at a `Site`.
+    ///
+    /// The average is over the period of record of each type, counted in calendar days including
+    /// the first and last days. So 4 model runs on a single day is a density of 4.0. The results
+    /// are sorted by the `SoundingType` source.
+    pub fn data_density(&self, site: &Site) -> Result<Vec<(SoundingType, f64)>> {
+        crate::coverage::data_density(&self.db_conn, site)
+    }
+
+    /// Retrieve the model initialization time of the most recent model in the archive.
+    pub fn most_recent_init_time(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<NaiveDateTime> {
+        debug_assert!(site.id() > 0);
+        debug_assert!(sounding_type.id() > 0);
+
+        let init_time: NaiveDateTime = self.db_conn.query_row(
+            "
+                SELECT init_time FROM files
+                WHERE site_id = ?1 AND type_id = ?2
+                ORDER BY init_time DESC
+                LIMIT 1
+            ",
+            &[&site.id(), &sounding_type.id()],
+            |row| row.get(0),
+        )?;
+
+        Ok(init_time)
+    }
+
+    /// Retrieve the initialization time of the oldest model run or sounding in the archive, which
+    /// is the start of the period of record for this `site` and `sounding_type`.
+    ///
+    /// Returns `Ok(None)` if there are no files for this `site` and `sounding_type`.
+    pub fn earliest_valid_time(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<Option<NaiveDateTime>> {
+        debug_assert!(site.id() > 0);
+        debug_assert!(sounding_type.id() > 0);
+
+        match self.db_conn.query_row(
+            "
+                SELECT init_time FROM files
+                WHERE site_id = ?1 AND type_id = ?2
+                ORDER BY init_time ASC
+                LIMIT 1
+            ",
+            &[&site.id(), &sounding_type.id()],
+            |row| row.get(0),
+        ) {
+            Ok(init_time) => Ok(Some(init_time)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(BufkitDataErr::from(err)),
+        }
+    }
+
+    /// How long after initialization a file was received, `None` if the received time isn't
+    /// known.
+    ///
+    /// It is an error if the file isn't in the archive.
+    pub fn latency(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Option<Duration>> {
+        let init_time = &truncate_to_minute(init_time);
+        let received_time: Option<NaiveDateTime> = self.db_conn.query_row(
+            "
+                SELECT received_time
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3
+            ",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
+            |row| row.get(0),
+        )?;
+
+        Ok(received_time.map(|received_time| received_time - *init_time))
+    }
+
+    /// Attach a note to a file, replacing any note it already had.
+    ///
+    /// The note stays with the file until it is removed or replaced by adding the file again, and
+    /// it goes in and out of the trash with the file. It is an error if the file isn't in the
+    /// archive.
+    pub fn set_file_comment(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        comment: &str,
+    ) -> Result<()> {
+        let init_time = &truncate_to_minute(init_time);
+        let num_changed = self.db_conn.execute(
+            "
+                UPDATE files SET comment = ?4
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3
+            ",
+            &[
+                &site.id(),
+                &sounding_type.id(),
+                init_time as &dyn ToSql,
+                &comment,
+            ],
+        )?;
+
+        if num_changed == 0 {
+            return Err(BufkitDataErr::Database(
+                rusqlite::Error::QueryReturnedNoRows,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The note attached to a file with `set_file_comment`, `None` if there isn't one.
+    ///
+    /// It is an error if the file isn't in the archive.
+    pub fn file_comment(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Option<String>> {
+        let init_time = &truncate_to_minute(init_time);
+        let comment = self.db_conn.query_row(
+            "
+                SELECT comment
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3
+            ",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
+            |row| row.get(0),
+        )?;
+
+        Ok(comment)
+    }
+
+    /// Check if there are any files, of any `SoundingType`, for this `site` initialized on `date`.
+    pub fn has_data_on(&self, site: &Site, date: NaiveDate) -> Result<bool> {
+        debug_assert!(site.id() > 0);
+
+        let (start, end) = day_range(date);
+        let has_data: bool = self.db_conn.query_row(
+            "
+                SELECT EXISTS(
+                    SELECT 1 FROM files
+                    WHERE site_id = ?1 AND init_time >= ?2 AND init_time < ?3
+                )
+            ",
+            &[&site.id(), &start as &dyn ToSql, &end as &dyn ToSql],
+            |row| row.get(0),
+        )?;
+
+        Ok(has_data)
+    }
+
+    /// Check to see if a file is present in the archive and it is retrieveable.
+    pub fn file_exists(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<bool> {
+        debug_assert!(site.id() > 0);
+        debug_assert!(sounding_type.id() > 0);
+
+        let init_time = &self.match_init_time(site, sounding_type, init_time)?;
+        let num_records: i32 = self.db_conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
+            |row| row.get(0),
+        )?;
+
+        Ok(num_records == 1)
+    }
+
+    /// Iterate over the records for every file in the archive.
+    ///
+    /// The records are loaded from the index a page at a time, so this walks even a very large
+    /// archive in bounded memory. Each record has the file name, initialization time, and the
+    /// `Site`, `SoundingType`, and `Location` of the file, which hold their ids in the index. An
+    /// error loading a page or resolving a record is returned as an item, so a caller can decide
+    /// whether to keep going.
+    pub fn iter_records<'a>(&'a self) -> impl Iterator<Item = Result<FileRecord>> + 'a {
+        FileRecordIter::new(&self.db_conn)
+    }
+
+    /// Start a query selecting files by site, sounding type, initialization time, and state.
+    ///
+    /// Unlike filtering the results of `iter_records`, the filters are all applied in a single
+    /// query of the index. See `FileQuery` for the available filters.
+    pub fn query(&self) -> FileQuery<'_> {
+        FileQuery::new(&self.db_conn)
+    }
+
+    /// Get the record for a file by the name it is stored under, `None` if it isn't in the index.
+    ///
+    /// This is handy for working out what the names returned by `check` or `verify` are.
+    pub fn record_for_file(&self, file_name: &str) -> Result<Option<FileRecord>> {
+        let row = self.db_conn.query_row(
+            &format!(
+                "SELECT {} FROM files WHERE file_name = ?1",
+                FILE_ROW_COLUMNS
+            ),
+            &[&file_name],
+            |row| crate::file_record::parse_row_to_file_row(row, 0),
+        );
+
+        match row {
+            Ok(row) => RecordResolver::default()
+                .resolve(&self.db_conn, row)
+                .map(Some),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(BufkitDataErr::from(err)),
+        }
+    }
+
+    /// Get the records for every file with an initialization time in the inclusive range from
+    /// `start` to `end`, for all sites and sounding types.
+    ///
+    /// The records are sorted by initialization time, then by site short name.
+    pub fn files_between(
+        &self,
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+    ) -> Result<Vec<FileRecord>> {
+        let mut stmt = self.db_conn.prepare(&format!(
+            "
+                SELECT {}
+                FROM files JOIN sites ON files.site_id = sites.id
+                WHERE files.init_time BETWEEN ?1 AND ?2
+                ORDER BY files.init_time ASC, sites.short_name ASC
+            ",
+            FILE_ROW_COLUMNS
+        ))?;
+
+        let rows: Result<Vec<_>> = stmt
+            .query_map(&[start, end], |row| {
+                crate::file_record::parse_row_to_file_row(row, 0)
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mut resolver = RecordResolver::default();
+        rows?
+            .into_iter()
+            .map(|row| resolver.resolve(&self.db_conn, row))
+            .collect()
+    }
+
+    /// Find groups of files with identical contents.
+    ///
+    /// Files are compared by the checksum of their contents, so the same sounding stored under two
+    /// different sites, or with two different codecs, ends up in the same group. Only groups with
+    /// more than one file are returned, each sorted by initialization time and file name. Files
+    /// without a stored checksum are never included, use `backfill_checksums` to compute them for
+    /// older archives.
+    pub fn duplicate_blobs(&self) -> Result<Vec<Vec<FileRecord>>> {
+        let mut stmt = self.db_conn.prepare(&format!(
+            "
+                SELECT files.checksum, {}
+                FROM files
+                WHERE files.checksum IN (
+                    SELECT checksum
+                    FROM files
+                    WHERE checksum IS NOT NULL
+                    GROUP BY checksum
+                    HAVING COUNT(*) > 1
+                )
+                ORDER BY files.checksum ASC, files.init_time ASC, files.file_name ASC
+            ",
+            FILE_ROW_COLUMNS
+        ))?;
+
+        let rows: Result<Vec<(i64, _)>> = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0)?,
+                    crate::file_record::parse_row_to_file_row(row, 1)?,
+                ))
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mut resolver = RecordResolver::default();
+        let mut groups: Vec<Vec<FileRecord>> = vec![];
+        let mut last_checksum = None;
+        for (checksum, row) in rows? {
+            let record = resolver.resolve(&self.db_conn, row)?;
+            match groups.last_mut() {
+                Some(group) if last_checksum == Some(checksum) => group.push(record),
+                _ => groups.push(vec![record]),
+            }
+            last_checksum = Some(checksum);
+        }
+
+        Ok(groups)
+    }
+
+    /// Write a CSV listing of every file in the archive.
+    ///
+    /// After a header row, there is one row per file with the columns `short_name`, `source`,
+    /// `init_time`, `latitude`, `longitude`, `elevation`, and `file_name`. Returns the number of
+    /// files listed.
+    pub fn export_files_csv<W: Write>(&self, mut writer: W) -> Result<usize> {
+        writeln!(
+            writer,
+            "short_name,source,init_time,latitude,longitude,elevation,file_name"
+        )?;
+
+        let mut count = 0;
+        for record in self.iter_records() {
+            let record = record?;
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                csv_field(record.site().short_name()),
+                csv_field(record.sounding_type().source()),
+                record.init_time().format("%Y-%m-%dT%H:%M:%S"),
+                record.location().latitude(),
+                record.location().longitude(),
+                record.location().elevation(),
+                csv_field(record.file_name()),
+            )?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Estimate the total size of all the files in the archive once they are uncompressed.
+    ///
+    /// Only `sample` randomly chosen files are decompressed to measure the compression ratio,
+    /// which is then applied to the total size of the files in storage. A larger sample gives a
+    /// better estimate at the cost of more time, see `SizeEstimate::confidence`. If `sample` is at
+    /// least the number of files in the archive, the result is exact. Files in the index that are
+    /// missing from storage are skipped and listed in the estimate.
+    pub fn estimated_uncompressed_size(&self, sample: usize) -> Result<SizeEstimate> {
+        let stored: HashSet<String> = self.blobs.list()?.into_iter().collect();
+
+        let mut stmt = self
+            .db_conn
+            .prepare("SELECT file_name, codec FROM files ORDER BY RANDOM()")?;
+        let files: std::result::Result<Vec<(String, String)>, _> = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+
+        let mut estimate = SizeEstimate::default();
+        let mut total_compressed = 0;
+        let mut num_files = 0;
+        let mut sizes: Vec<(u64, u64)> = vec![];
+        for (file_name, codec) in files? {
+            if !stored.contains(&file_name) {
+                estimate.missing_files.push(file_name);
+                continue;
+            }
+
+            let compressed = self.blobs.size(&file_name)?;
+            total_compressed += compressed;
+            num_files += 1;
+
+            if sizes.len() < sample {
+                let uncompressed = self.uncompressed_size(&file_name, Codec::from_str(&codec)?)?;
+                sizes.push((compressed, uncompressed));
+            }
+        }
+
+        let (sample_compressed, sample_uncompressed) = sizes
+            .iter()
+            .fold((0, 0), |(c_acc, u_acc), (c, u)| (c_acc + c, u_acc + u));
+
+        estimate.sampled_files = sizes.len();
+        estimate.confidence = sample_confidence(&sizes, num_files);
+        estimate.bytes = if sample_compressed == 0 {
+            total_compressed
+        } else {
+            let ratio = sample_uncompressed as f64 / sample_compressed as f64;
+            (total_compressed as f64 * ratio).round() as u64
+        };
+
+        Ok(estimate)
+    }
+
+    /// Compare the stored and decoded sizes of every file for a site and sounding type.
+    ///
+    /// Each file is decoded as a stream to count its size, so this reads the whole series but
+    /// never holds more than a buffer of it in memory.
+    pub fn compression_stats(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<CompressionStats> {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2
+            ",
+        )?;
+        let files: std::result::Result<Vec<(String, String)>, _> = stmt
+            .query_map([site.id(), sounding_type.id()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect();
+
+        let mut stats = CompressionStats::default();
+        for (file_name, codec) in files? {
+            stats.file_count += 1;
+            stats.compressed_bytes += self.blobs.size(&file_name)?;
+            stats.uncompressed_bytes +=
+                self.uncompressed_size(&file_name, Codec::from_str(&codec)?)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Count the soundings, rather than the files, for a site and sounding type.
+    ///
+    /// A model run holds a sounding for every forecast hour, so this is much larger than the
+    /// number of files. The first time a file is counted it has to be decoded, which is slow for a
+    /// long series, but the count is saved in the index so later calls are cheap. Use
+    /// `backfill_profile_counts` to pay that cost up front for the whole archive.
+    pub fn profile_count(&self, site: &Site, sounding_type: &SoundingType) -> Result<u64> {
+        for (file_name, codec, file_type) in
+            self.files_without_profile_counts(Some((site, sounding_type)))?
+        {
+            self.store_profile_count(&file_name, codec, file_type)
+                .map_err(|err| BufkitDataErr::InFile(file_name, Box::new(err)))?;
+        }
+
+        let count: i64 = self.db_conn.query_row(
+            "
+                SELECT COALESCE(SUM(profile_count), 0)
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2
+            ",
+            [site.id(), sounding_type.id()],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as u64)
+    }
+
+    /// Get the number of files stored in the archive.
+    pub fn count(&self) -> Result<i64> {
+        let num_records: i64 =
+            self.db_conn
+                .query_row("SELECT COUNT(*) FROM files", NO_PARAMS, |row| row.get(0))?;
+
+        Ok(num_records)
+    }
+
+    /// Get the number of files stored in the archive for a `Site` and `SoundingType`.
+    pub fn count_for(&self, site: &Site, sounding_type: &SoundingType) -> Result<i64> {
+        let num_records: i64 = self.db_conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE site_id = ?1 AND type_id = ?2",
+            [site.id(), sounding_type.id()],
+            |row| row.get(0),
+        )?;
+
+        Ok(num_records)
+    }
+
+    /// Get the number of files stored in the archive for each `SoundingType` at a `Site`.
+    ///
+    /// Only types with at least one file at the site are included, sorted by source. This is
+    /// cheaper than building an `Inventory` when only the counts are needed.
+    pub fn counts_by_type(&self, site: &Site) -> Result<Vec<(SoundingType, i64)>> {
+        crate::sounding_type::file_counts_for_site(&self.db_conn, site)
+    }
+
+    /// Get a summary of the archive: how many files, sites, sounding types, and locations are in
+    /// the index, how much space the stored files take, and the range of initialization times.
+    pub fn stats(&self) -> Result<ArchiveStats> {
+        let (file_count, site_count, sounding_type_count, location_count, first, last) =
+            self.db_conn.query_row(
+                "
+                    SELECT
+                        (SELECT COUNT(*) FROM files),
+                        (SELECT COUNT(*) FROM sites),
+                        (SELECT COUNT(*) FROM types),
+                        (SELECT COUNT(*) FROM locations),
+                        (SELECT MIN(init_time) FROM files),
+                        (SELECT MAX(init_time) FROM files)
+                ",
+                NO_PARAMS,
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, Option<NaiveDateTime>>(4)?,
+                        row.get::<_, Option<NaiveDateTime>>(5)?,
+                    ))
+                },
+            )?;
+
+        let mut stored_bytes = 0;
+        for name in self.blobs.list()? {
+            stored_bytes += self.blobs.size(&name)?;
+        }
+
+        Ok(ArchiveStats {
+            file_count: file_count as usize,
+            site_count: site_count as usize,
+            sounding_type_count: sounding_type_count as usize,
+            location_count: location_count as usize,
+            stored_bytes,
+            init_time_range: first.and_then(|first| last.map(|last| (first, last))),
+        })
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Add, remove, and retrieve files from the archive
+    // ---------------------------------------------------------------------------------------------
+
+    /// Add a file to the archive.
+    ///
+    /// File names only have the initialization time to the minute, so the seconds are dropped
+    /// from `init_time` before it is stored in the index. Two files for the same site and sounding
+    /// type that are initialized in the same minute are the same file, and the second one replaces
+    /// the first.
+    ///
+    /// The current time is recorded as the time the file was received, see
+    /// `add_file_received_at` to supply it.
+    pub fn add_file(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        file_name: &str,
+    ) -> Result<()> {
+        self.add_file_received_at(
+            site,
+            sounding_type,
+            location,
+            init_time,
+            end_time,
+            file_name,
+            &Utc::now().naive_utc(),
+        )
+    }
+
+    /// Add a file to the archive, with a policy for when it is already in the archive at a
+    /// different location.
+    ///
+    /// This is the same as `add_file`, except `on_conflict` is used instead of the policy set with
+    /// `set_on_conflict`, so callers that need different policies don't have to change the
+    /// archive.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_file_with_policy(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        file_name: &str,
+        on_conflict: OnConflict,
+    ) -> Result<()> {
+        debug_assert!(site.is_valid());
+        debug_assert!(sounding_type.is_valid());
+        debug_assert!(location.is_valid());
+
+        let mut in_file = File::open(file_name)?;
+        self.add_data(
+            site,
+            sounding_type,
+            location,
+            init_time,
+            end_time,
+            &Utc::now().naive_utc(),
+            on_conflict,
+            &mut in_file,
+        )
+    }
+
+    /// Add a file to the archive along with the time it was published or downloaded.
+    ///
+    /// This is the same as `add_file`, except the `received_time` is used to measure how long
+    /// after initialization the data was available, see `latency`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_file_received_at(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        file_name: &str,
+        received_time: &NaiveDateTime,
+    ) -> Result<()> {
+        debug_assert!(site.is_valid());
+        debug_assert!(sounding_type.is_valid());
+        debug_assert!(location.is_valid());
+
+        let mut in_file = File::open(file_name)?;
+        self.add_data(
+            site,
+            sounding_type,
+            location,
+            init_time,
+            end_time,
+            received_time,
+            self.on_conflict,
+            &mut in_file,
+        )
+    }
+
+    /// Add a file to the archive from a reader, such as an HTTP download, instead of a file on disk.
+    ///
+    /// This is the same as `add_file`, the data is compressed as it is read and stored with the
+    /// same name and index entry. If reading fails part way through, any file already in the
+    /// archive for this run is left as it was.
+    pub fn add_file_from_reader<R: Read>(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        mut src: R,
+    ) -> Result<()> {
+        debug_assert!(site.is_valid());
+        debug_assert!(sounding_type.is_valid());
+        debug_assert!(location.is_valid());
+
+        self.add_data(
+            site,
+            sounding_type,
+            location,
+            init_time,
+            end_time,
+            &Utc::now().naive_utc(),
+            self.on_conflict,
+            &mut src,
+        )
+    }
+
+    /// Add a file to the archive from the uncompressed data already in memory.
+    ///
+    /// This is the same as `add_file`, without the round trip through the file system.
+    pub fn add_file_from_bytes(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        data: &[u8],
+    ) -> Result<()> {
+        self.add_file_from_reader(site, sounding_type, location, init_time, end_time, data)
+    }
+
+    /// Add a BUFKIT file that bundles several stations to the archive.
+    ///
+    /// The file is split into one file per station, and each is stored under the `Site` returned
+    /// by `site_for_station` for its station number, at the location given in the file. Sites and
+    /// locations not already in the index are added. Returns the sites in the order the stations
+    /// appear in the file.
+    ///
+    /// This only works for sounding types with a `FileType::BUFKIT` file type.
+    pub fn add_multi_station_file<F>(
+        &self,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        file_name: &str,
+        site_for_station: F,
+    ) -> Result<Vec<Site>>
+    where
+        F: Fn(i32) -> Result<Site>,
+    {
+        debug_assert!(sounding_type.is_valid());
+
+        if sounding_type.file_type() != FileType::BUFKIT {
+            return Err(BufkitDataErr::UnknownFileType);
+        }
+
+        let text = std::fs::read_to_string(file_name)?;
+
+        let mut sites = vec![];
+        for station_text in split_bufkit_stations(&text) {
+            let anals = self.decode_data(station_text.as_bytes(), file_name, FileType::BUFKIT)?;
+
+            let not_enough = |what| {
+                BufkitDataErr::NotEnoughData(format!("no {} for a station in {}", what, file_name))
+            };
+            let station_num =
+                Self::station_num(&anals).ok_or_else(|| not_enough("station number"))?;
+            let (lat, lon, elev_m, end_time) = Self::location_and_end_time(&anals)
+                .ok_or_else(|| not_enough("location or valid time"))?;
+
+            let site = self.validate_or_add_site(site_for_station(station_num)?)?;
+            let location = self.retrieve_or_add_location(lat, lon, elev_m)?;
+
+            self.add_data(
+                &site,
+                sounding_type,
+                &location,
+                init_time,
+                &end_time,
+                &Utc::now().naive_utc(),
+                self.on_conflict,
+                &mut station_text.as_bytes(),
+            )?;
+
+            sites.push(site);
+        }
+
+        Ok(sites)
+    }
+
+    /// Try to fill in every run missing from the archive with a fetcher.
+    ///
+    /// The missing runs are the gaps reported by `Inventory::missing` for each `Site` and
+    /// `SoundingType`, along with the runs after the most recent one up to `now`. So only types
+    /// with a known number of hours between initializations are filled in. The fetcher returns
+    /// `Ok(None)` if it doesn't have a run, otherwise the file is added at the location given in it,
+    /// just like with a fetch hook. An error for one run doesn't stop the others, it is included in
+    /// the report instead.
+    pub fn backfill_missing(
+        &self,
+        now: &NaiveDateTime,
+        fetcher: &BackfillFn,
+    ) -> Result<BackfillReport> {
+        let mut report = BackfillReport::default();
+
+        for site in self.sites()? {
+            let inv = self.inventory(&site)?;
+
+            let mut sounding_types: Vec<&SoundingType> = inv.sounding_types().collect();
+            sounding_types.sort_by(|a, b| a.source().cmp(b.source()));
+
+            for sounding_type in sounding_types {
+                for init_time in Self::missing_runs(&inv, sounding_type, now) {
+                    let fetched = fetcher(&site, sounding_type, &init_time).and_then(|data| {
+                        data.map(|data| self.add_fetched(&site, sounding_type, &init_time, data))
+                            .transpose()
+                    });
+
+                    let run = (site.clone(), sounding_type.clone(), init_time);
+                    match fetched {
+                        Ok(Some(_)) => report.filled.push(run),
+                        Ok(None) => report.still_missing.push(run),
+                        Err(err) => report.errored.push((run.0, run.1, run.2, err)),
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Every initialization time missing from an inventory for a `SoundingType`, including those
+    /// after the last one in the archive up to `now`.
+    fn missing_runs(
+        inv: &Inventory,
+        sounding_type: &SoundingType,
+        now: &NaiveDateTime,
+    ) -> Vec<NaiveDateTime> {
+        let (delta_t, last) = match (
+            sounding_type.hours_between_initializations(),
+            inv.range(sounding_type),
+        ) {
+            (Some(hours), Some((_, last))) if hours > 0 => {
+                (Duration::hours(i64::from(hours)), last)
+            }
+            _ => return vec![],
+        };
+
+        let mut runs = vec![];
+        for &(start, end) in inv.missing(sounding_type) {
+            let mut init_time = start;
+            while init_time <= end {
+                runs.push(init_time);
+                init_time += delta_t;
+            }
+        }
+
+        let mut init_time = last + delta_t;
+        while init_time <= *now {
+            runs.push(init_time);
+            init_time += delta_t;
+        }
+
+        runs
+    }
+
+    /// Get the station number from the first sounding.
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn station_num(anals: &[Analysis]) -> Option<i32> {
+        anals
+            .first()?
+            .sounding()
+            .station_info()
+            .station_num()
+            .into_option()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_data<R: Read>(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        location: &Location,
+        init_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        received_time: &NaiveDateTime,
+        on_conflict: OnConflict,
+        src: &mut R,
+    ) -> Result<()> {
+        let init_time = &truncate_to_minute(init_time);
+
+        if let Some(max_lead) = self.max_init_time_lead {
+            let latest = Utc::now().naive_utc() + max_lead;
+            if *init_time > latest {
+                return Err(BufkitDataErr::InitTimeInFuture(*init_time, latest));
+            }
+        }
+
+        if !site.is_mobile() && on_conflict != OnConflict::Replace {
+            if let Some(existing) = self.existing_location(site, sounding_type, init_time)? {
+                if existing.id() != location.id() {
+                    match on_conflict {
+                        OnConflict::Reject => {
+                            return Err(BufkitDataErr::LocationConflict(existing, *location))
+                        }
+                        OnConflict::KeepExisting => return Ok(()),
+                        OnConflict::Replace => {}
+                    }
+                }
+            }
+        }
+
+        let fname: String = self.compressed_file_name(&site, &sounding_type, init_time);
+
+        let existing: Option<String> = match self.db_conn.query_row(
+            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
+            |row| row.get(0),
+        ) {
+            Ok(old_name) => Some(old_name),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(err) => return Err(BufkitDataErr::from(err)),
+        };
+
+        // Writing over a file with the same name would change it before the index is updated, so
+        // it is staged under another name until the new row is in. A file added before the codec
+        // changed has a different name, and must be removed after it is replaced.
+        let (put_name, replaced) = match existing {
+            Some(old_name) if old_name == fname => {
+                (format!("{}{}", fname, Archive::STAGED_EXT), None)
+            }
+            old_name => (fname.clone(), old_name),
+        };
+
+        // Checking the valid time needs the whole file, so read it into memory first.
+        let mut buffered;
+        let src: &mut dyn Read = match self.valid_time_tolerance {
+            Some(tolerance) => {
+                let mut buf = vec![];
+                src.read_to_end(&mut buf)?;
+                self.check_valid_time(&buf, &fname, sounding_type, init_time, tolerance)?;
+                buffered = std::io::Cursor::new(buf);
+                &mut buffered
+            }
+            None => src,
+        };
+
+        let mut src = CrcReader::new(src);
+        let mut encoded: Box<dyn Read> = match self.codec {
+            Codec::Gzip => Box::new(GzEncoder::new(&mut src, self.compression)),
+            Codec::None => Box::new(&mut src),
+        };
+        self.blobs.put(&put_name, &mut encoded)?;
+        drop(encoded);
+        let checksum = i64::from(src.crc().sum());
+
+        let insert = || -> Result<()> {
+            self.db_conn.execute(
+                "
+                    INSERT OR REPLACE INTO files 
+                        (type_id, site_id, location_id, init_time, end_time, file_name, codec,
+                         last_accessed, checksum, received_time)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ",
+                &[
+                    &sounding_type.id(),
+                    &site.id(),
+                    &location.id(),
+                    &init_time as &dyn ToSql,
+                    &end_time as &dyn ToSql,
+                    &fname,
+                    &self.codec.as_static(),
+                    &self.access_time() as &dyn ToSql,
+                    &checksum,
+                    received_time as &dyn ToSql,
+                ],
+            )?;
+
+            Ok(())
+        };
+
+        if put_name == fname {
+            if let Err(err) = insert() {
+                let _ = self.blobs.delete(&fname);
+                return Err(err);
+            }
+        } else {
+            // Only write over the old file once the new row is in, and keep the old row if that
+            // fails.
+            self.db_conn.execute_batch("BEGIN")?;
+            let moved =
+                insert().and_then(|_| self.blobs.put(&fname, &mut self.blobs.get(&put_name)?));
+            let _ = self.blobs.delete(&put_name);
+
+            match moved {
+                Ok(()) => self.db_conn.execute_batch("COMMIT")?,
+                Err(err) => {
+                    self.db_conn.execute_batch("ROLLBACK")?;
+                    return Err(err);
+                }
+            }
+        }
+
+        // The new file is in, so failing to clean up the old one isn't an error. It is left for
+        // `check` to report as not in the index.
+        if let Some(old_name) = replaced {
+            let _ = self.blobs.delete(&old_name);
+        }
+
+        Ok(())
+    }
+
+    /// Decode a file about to be added and check that its first valid time is within `tolerance`
+    /// of `init_time`.
+    fn check_valid_time(
+        &self,
+        buf: &[u8],
+        file_name: &str,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        tolerance: Duration,
+    ) -> Result<()> {
+        let anals = self.decode_data(buf, file_name, sounding_type.file_type())?;
+        let valid_time = Self::first_valid_time(&anals).ok_or_else(|| {
+            BufkitDataErr::NotEnoughData(format!("no valid time in {}", file_name))
+        })?;
+
+        if (valid_time - *init_time).abs() > tolerance {
+            return Err(BufkitDataErr::ValidTimeMismatch(*init_time, valid_time));
+        }
+
+        Ok(())
+    }
+
+    /// Get the valid time of the first sounding.
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn first_valid_time(anals: &[Analysis]) -> Option<NaiveDateTime> {
+        anals.first()?.sounding().valid_time()
+    }
+
+    /// Get the location of a file already in the index, `None` if there isn't one.
+    fn existing_location(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Option<Location>> {
+        let location_id: i64 = match self.db_conn.query_row(
+            "
+                SELECT location_id
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3
+            ",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
+            |row| row.get(0),
+        ) {
+            Ok(location_id) => location_id,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(err) => return Err(BufkitDataErr::from(err)),
+        };
+
+        crate::location::retrieve_location_by_id(&self.db_conn, location_id).map(Some)
+    }
+
+    fn get_file_name_for(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<(String, Codec)> {
+        debug_assert!(site.id() > 0, "Site not checked or added in index");
+        debug_assert!(
+            sounding_type.id() > 0,
+            "Sounding type not checked or added in index."
+        );
+
+        let init_time = &self.match_init_time(site, sounding_type, init_time)?;
+        let (file_name, codec): (String, String) = self.db_conn.query_row(
+            "
+                SELECT file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3
+            ",
+            &[&site.id(), &sounding_type.id(), init_time as &dyn ToSql],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok((file_name, Codec::from_str(&codec)?))
+    }
+
+    /// Find the initialization time of the file matching `init_time` under the current
+    /// `InitTimeMatch`, or `init_time` itself if there isn't a match.
+    fn match_init_time(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<NaiveDateTime> {
+        let init_time = &truncate_to_minute(init_time);
+        let tolerance = match self.init_time_match {
+            InitTimeMatch::Exact => return Ok(*init_time),
+            InitTimeMatch::Within(tolerance) => tolerance,
+        };
+
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT init_time
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time BETWEEN ?3 AND ?4
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let candidates: std::result::Result<Vec<NaiveDateTime>, _> = stmt
+            .query_map(
+                &[
+                    &site.id(),
+                    &sounding_type.id(),
+                    &(*init_time - tolerance) as &dyn ToSql,
+                    &(*init_time + tolerance) as &dyn ToSql,
+                ],
+                |row| row.get(0),
+            )?
+            .collect();
+
+        Ok(candidates?
+            .into_iter()
+            .min_by_key(|candidate| (*candidate - *init_time).num_seconds().abs())
+            .unwrap_or(*init_time))
+    }
+
+    fn access_time(&self) -> Option<NaiveDateTime> {
+        if self.track_access {
+            Some(Utc::now().naive_utc())
+        } else {
+            None
+        }
+    }
+
+    fn touch(&self, file_name: &str) -> Result<()> {
+        if let Some(now) = self.access_time() {
+            self.db_conn.execute(
+                "UPDATE files SET last_accessed = ?1 WHERE file_name = ?2",
+                &[&now as &dyn ToSql, &file_name],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn load_data(&self, file_name: &str, codec: Codec) -> Result<Vec<u8>> {
+        self.touch(file_name)?;
+        self.read_file(file_name, codec)
+    }
+
+    /// Read and decompress a file without counting it as an access.
+    fn read_file(&self, file_name: &str, codec: Codec) -> Result<Vec<u8>> {
+        let file = self.blobs.get(file_name)?;
+        let mut decoder = codec.decoder(file);
+        let mut buf: Vec<u8> = vec![];
+        let _bytes_read = decoder.read_to_end(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Compute the CRC32 of a file after decoding without holding it all in memory.
+    fn content_checksum(&self, file_name: &str, codec: Codec) -> Result<u32> {
+        let file = self.blobs.get(file_name)?;
+        let mut decoder = CrcReader::new(codec.decoder(file));
+        std::io::copy(&mut decoder, &mut std::io::sink())?;
+
+        Ok(decoder.crc().sum())
+    }
+
+    /// Count the bytes in a file after decoding without holding it all in memory.
+    fn uncompressed_size(&self, file_name: &str, codec: Codec) -> Result<u64> {
+        let file = self.blobs.get(file_name)?;
+        let mut decoder = codec.decoder(file);
+        let size = std::io::copy(&mut decoder, &mut std::io::sink())?;
+
+        Ok(size)
+    }
+
+    /// Decode a file with the decoder for its declared type.
+    ///
+    /// If that fails and the contents look like a different type, the decoder for that type is
+    /// tried instead. If it fails too, or there isn't one, the error names both types.
+    fn decode_data(&self, buf: &[u8], description: &str, ftype: FileType) -> Result<Vec<Analysis>> {
+        let decode = |ftype| {
+            self.decoders
+                .get(ftype)
+                .ok_or(BufkitDataErr::UnknownFileType)?
+                .decode(buf, description)
+        };
+
+        decode(ftype).or_else(|err| {
+            let detected = FileType::detect(buf);
+            if detected == ftype || detected == FileType::UNKNOWN {
+                return Err(err);
+            }
+
+            decode(detected).map_err(|_| BufkitDataErr::WrongFileType(ftype, detected))
+        })
+    }
+
+    /// Retrieve an analysis from the archive.
+    pub fn retrieve(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Vec<Analysis>> {
+        let (file_name, codec) = match self.get_file_name_for(site, sounding_type, init_time) {
+            Ok(found) => found,
+            Err(BufkitDataErr::Database(rusqlite::Error::QueryReturnedNoRows))
+                if self.fetch_hook.is_some() =>
+            {
+                return self.fetch(site, sounding_type, init_time);
+            }
+            Err(err) => return Err(err),
+        };
+        let data = self.load_data(&file_name, codec)?;
+        self.decode_data(&data, &file_name, sounding_type.file_type())
+    }
+
+    /// Retrieve only the surface values for each valid time in a file.
+    ///
+    /// The file is still decoded, but only the temperature, dew point, wind, and pressure at the
+    /// surface are kept. Soundings without a valid time are skipped.
+    pub fn retrieve_surface(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Vec<(NaiveDateTime, SurfaceData)>> {
+        let anals = self.retrieve(site, sounding_type, init_time)?;
+
+        Ok(anals
+            .iter()
+            .filter_map(crate::surface::surface_data)
+            .collect())
+    }
+
+    /// Use the fetch hook to get a file missing from the archive, add it, and decode it.
+    fn fetch(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<Vec<Analysis>> {
+        let hook = self
+            .fetch_hook
+            .as_ref()
+            .ok_or_else(|| BufkitDataErr::NotEnoughData("no fetch hook set".to_owned()))?;
+        let data = (hook.0)(site, sounding_type, init_time)?;
+
+        self.add_fetched(site, sounding_type, init_time, data)
+    }
+
+    /// Decode a fetched file, and add it to the archive at the location given in the file.
+    fn add_fetched(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        data: Vec<u8>,
+    ) -> Result<Vec<Analysis>> {
+        let description = self.compressed_file_name(site, sounding_type, init_time);
+        let anals = self.decode_data(&data, &description, sounding_type.file_type())?;
+
+        let (lat, lon, elev_m, end_time) =
+            Self::location_and_end_time(&anals).ok_or_else(|| {
+                BufkitDataErr::NotEnoughData(format!(
+                    "no location or valid time in fetched file {}",
+                    description
+                ))
+            })?;
+        let location = self.retrieve_or_add_location(lat, lon, elev_m)?;
+
+        self.add_file_from_bytes(site, sounding_type, &location, init_time, &end_time, &data)?;
+
+        Ok(anals)
+    }
+
+    /// Get the coordinates of the first sounding and the valid time of the last one.
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn location_and_end_time(anals: &[Analysis]) -> Option<(f64, f64, i32, NaiveDateTime)> {
+        let station = anals.first()?.sounding().station_info();
+        let (lat, lon) = station.location()?;
+        let elev_m = station.elevation().into_option()?.0 as i32;
+        let end_time = anals.last()?.sounding().valid_time()?;
+
+        Some((lat, lon, elev_m, end_time))
+    }
+
+    /// Retrieve the analyses for several initialization times at once.
+    ///
+    /// The file names are looked up a batch at a time instead of one query per file, and the
+    /// results are returned in the same order as `init_times`. The times are matched to files like
+    /// `retrieve` does, see `set_init_time_match`. A time that isn't in the archive is fetched
+    /// with the fetch hook if there is one, see `set_fetch_hook`. Otherwise this returns the same
+    /// error as `retrieve` would for that time.
+    pub fn retrieve_many(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_times: &[NaiveDateTime],
+    ) -> Result<Vec<(NaiveDateTime, Vec<Analysis>)>> {
+        debug_assert!(site.id() > 0);
+        debug_assert!(sounding_type.id() > 0);
+
+        if init_times.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let tolerance = match self.init_time_match {
+            InitTimeMatch::Exact => Duration::zero(),
+            InitTimeMatch::Within(tolerance) => tolerance,
+        };
+        let wanted: Vec<NaiveDateTime> = init_times.iter().map(truncate_to_minute).collect();
+
+        // Every file inside the tolerance of any of the times, each time is two parameters.
+        let site_id = site.id();
+        let type_id = sounding_type.id();
+        let mut candidates: BTreeMap<NaiveDateTime, (String, Codec)> = BTreeMap::new();
+        for chunk in wanted.chunks((Archive::MAX_QUERY_PARAMS - 2) / 2) {
+            let windows: Vec<(NaiveDateTime, NaiveDateTime)> = chunk
+                .iter()
+                .map(|init_time| (*init_time - tolerance, *init_time + tolerance))
+                .collect();
+            let clauses: Vec<String> = (0..windows.len())
+                .map(|i| format!("init_time BETWEEN ?{} AND ?{}", 2 * i + 3, 2 * i + 4))
+                .collect();
+            let mut stmt = self.db_conn.prepare(&format!(
+                "
+                    SELECT init_time, file_name, codec
+                    FROM files
+                    WHERE site_id = ?1 AND type_id = ?2 AND ({})
+                ",
+                clauses.join(" OR ")
+            ))?;
+
+            let mut params: Vec<&dyn ToSql> = vec![&site_id, &type_id];
+            for (start, end) in &windows {
+                params.push(start);
+                params.push(end);
+            }
+
+            let rows: std::result::Result<Vec<(NaiveDateTime, String, String)>, _> = stmt
+                .query_map(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect();
+            for (init_time, fname, codec) in rows? {
+                candidates.insert(init_time, (fname, Codec::from_str(&codec)?));
+            }
+        }
+
+        init_times
+            .iter()
+            .zip(&wanted)
+            .map(|(init_time, wanted)| {
+                // The closest file, and the earliest of two equally close, like match_init_time.
+                let found = candidates
+                    .range(*wanted - tolerance..=*wanted + tolerance)
+                    .min_by_key(|(candidate, _)| (**candidate - *wanted).num_seconds().abs());
+
+                let anals = match found {
+                    Some((_, (file_name, codec))) => {
+                        let data = self.load_data(file_name, *codec)?;
+                        self.decode_data(&data, file_name, sounding_type.file_type())?
+                    }
+                    None if self.fetch_hook.is_some() => {
+                        self.retrieve(site, sounding_type, init_time)?
+                    }
+                    None => {
+                        return Err(BufkitDataErr::Database(
+                            rusqlite::Error::QueryReturnedNoRows,
+                        ))
+                    }
+                };
+
+                Ok((*init_time, anals))
+            })
+            .collect()
+    }
+
+    /// Retrieve all analyses for a `Site` and `SoundingType` that have any data valid during
+    /// the specified period.
+    pub fn retrieve_all(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        start_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+    ) -> Result<Vec<Vec<Analysis>>> {
+        // Get a list of file names
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND end_time >= ?3 AND init_time <= ?4
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let vals: Result<Vec<Vec<Analysis>>> = stmt
+            .query_map(
+                &[
+                    &site.id(),
+                    &sounding_type.id(),
+                    &start_time as &ToSql,
+                    &end_time,
+                ],
+                |row: &Row| -> std::result::Result<(String, String), rusqlite::Error> {
+                    Ok((row.get(0)?, row.get(1)?))
+                },
+            )?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .map(|res| {
+                res.and_then(|(fname, codec)| {
+                    let codec = Codec::from_str(&codec)?;
+                    self.load_data(&fname, codec).map(|data| (fname, data))
+                })
+            })
+            .map(|res| {
+                res.and_then(|(fname, data)| {
+                    self.decode_data(&data, &fname, sounding_type.file_type())
+                })
+            })
+            .collect();
+
+        vals
+    }
+
+    /// Retrieve the analyses for every file of a `Site` and `SoundingType` initialized between
+    /// `start` and `end`, inclusive.
+    ///
+    /// The results are sorted by initialization time, and if there aren't any files in the range
+    /// the result is empty rather than an error.
+    pub fn retrieve_range(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+    ) -> Result<Vec<(NaiveDateTime, Vec<Analysis>)>> {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT init_time, file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time BETWEEN ?3 AND ?4
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let files: Result<Vec<(NaiveDateTime, String, String)>> = stmt
+            .query_map(
+                &[
+                    &site.id(),
+                    &sounding_type.id(),
+                    start as &dyn ToSql,
+                    end as &dyn ToSql,
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        files?
+            .into_iter()
+            .map(|(init_time, file_name, codec)| {
+                let data = self.load_data(&file_name, Codec::from_str(&codec)?)?;
+                let anals = self.decode_data(&data, &file_name, sounding_type.file_type())?;
+                Ok((init_time, anals))
+            })
+            .collect()
+    }
+
+    /// Fold over every analysis for a site and sounding type, in order of initialization time.
+    ///
+    /// Only one file is decoded at a time, so memory use stays flat no matter how long the series
+    /// is. This is handy for statistics over many years of data. An error reading or decoding any
+    /// file stops the fold and is returned as `BufkitDataErr::InFile` with the file name.
+    pub fn fold_series<B, F>(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init: B,
+        mut f: F,
+    ) -> Result<B>
+    where
+        F: FnMut(B, &Analysis) -> B,
+    {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let files: Result<Vec<(String, String)>> = stmt
+            .query_map([site.id(), sounding_type.id()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mut acc = init;
+        for (file_name, codec) in files? {
+            let anals = Codec::from_str(&codec)
+                .map_err(BufkitDataErr::from)
+                .and_then(|codec| self.load_data(&file_name, codec))
+                .and_then(|data| self.decode_data(&data, &file_name, sounding_type.file_type()))
+                .map_err(|err| BufkitDataErr::InFile(file_name.clone(), Box::new(err)))?;
+
+            acc = anals.iter().fold(acc, &mut f);
+        }
+
+        Ok(acc)
+    }
+
+    /// Iterate over every file for a site, of any sounding type, in order of initialization time.
+    ///
+    /// Each file is decoded as the iterator reaches it, so only one is held in memory at a time.
+    /// Files initialized at the same time are ordered by source. An error reading or decoding a
+    /// file is returned for that file as a `BufkitDataErr::InFile`, and the iteration carries on
+    /// with the next one.
+    pub fn iter_site<'a>(
+        &'a self,
+        site: &Site,
+    ) -> impl Iterator<Item = Result<(SoundingType, NaiveDateTime, Vec<Analysis>)>> + 'a {
+        let (files, err) = match self.files_for_site(site) {
+            Ok(files) => (files, None),
+            Err(err) => (vec![], Some(Err(err))),
+        };
+
+        let mut sounding_types: FnvHashMap<i64, SoundingType> = FnvHashMap::default();
+        err.into_iter().chain(files.into_iter().map(
+            move |(type_id, init_time, file_name, codec)| {
+                let sounding_type = match sounding_types.get(&type_id) {
+                    Some(sounding_type) => sounding_type.clone(),
+                    None => {
+                        let sounding_type = crate::sounding_type::retrieve_sounding_type_by_id(
+                            &self.db_conn,
+                            type_id,
+                        )?;
+                        sounding_types.insert(type_id, sounding_type.clone());
+                        sounding_type
+                    }
+                };
+
+                let anals = Codec::from_str(&codec)
+                    .map_err(BufkitDataErr::from)
+                    .and_then(|codec| self.load_data(&file_name, codec))
+                    .and_then(|data| self.decode_data(&data, &file_name, sounding_type.file_type()))
+                    .map_err(|err| BufkitDataErr::InFile(file_name, Box::new(err)))?;
+
+                Ok((sounding_type, init_time, anals))
+            },
+        ))
+    }
+
+    /// Get the type id, init time, file name, and codec of every file for a site, in the order
+    /// used by `iter_site`.
+    fn files_for_site(&self, site: &Site) -> Result<Vec<(i64, NaiveDateTime, String, String)>> {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT files.type_id, files.init_time, files.file_name, files.codec
+                FROM files JOIN types ON files.type_id = types.id
+                WHERE files.site_id = ?1
+                ORDER BY files.init_time ASC, types.type ASC
+            ",
+        )?;
+
+        let files: Result<Vec<_>> = stmt
+            .query_map([site.id()], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        files
+    }
+
+    /// Retrieve and uncompress a file.
+    pub fn export(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<impl Read> {
+        let (file_name, codec) = self.get_file_name_for(site, sounding_type, init_time)?;
+        self.touch(&file_name)?;
+        let file = self.blobs.get(&file_name)?;
+        Ok(codec.decoder(file))
+    }
+
+    /// Retrieve and uncompress a file, writing it to `dest`.
+    ///
+    /// Any missing parent directories of `dest` are created, and an existing file there is
+    /// replaced.
+    pub fn export_to_file(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        dest: &Path,
+    ) -> Result<()> {
+        let mut src = self.export(site, sounding_type, init_time)?;
+
+        if let Some(parent) = dest.parent() {
+            create_dir_all(parent)?;
+        }
+        std::io::copy(&mut src, &mut File::create(dest)?)?;
+
+        Ok(())
+    }
+
+    /// Retrieve a file as it is stored in the archive, without decoding it.
+    ///
+    /// The `Codec` tells how the bytes are compressed. This is useful for passing files along to
+    /// another system that understands the codec without decompressing and compressing them again.
+    pub fn export_compressed(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<(Codec, impl Read)> {
+        let (file_name, codec) = self.get_file_name_for(site, sounding_type, init_time)?;
+        self.touch(&file_name)?;
+        let file = self.blobs.get(&file_name)?;
+        Ok((codec, file))
+    }
+
+    /// Write every file for a site and sounding type initialized between `start` and `end`,
+    /// inclusive, one after the other into a single file.
+    ///
+    /// The files are decoded and written in order of initialization time, with a newline added
+    /// between files that don't end with one. BUFKIT files can be read back from the combined
+    /// file. Returns the number of files written.
+    pub fn export_series_concat<W: Write>(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+        mut writer: W,
+    ) -> Result<usize> {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time >= ?3 AND init_time <= ?4
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let files: Result<Vec<(String, String)>> = stmt
+            .query_map(
+                &[&site.id(), &sounding_type.id(), start as &dyn ToSql, end],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+        let files = files?;
+
+        for (file_name, codec) in &files {
+            let data = self.load_data(file_name, Codec::from_str(codec)?)?;
+            writer.write_all(&data)?;
+            if !data.ends_with(b"\n") {
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(files.len())
+    }
+
+    /// Retrieve the  most recent file as a sounding.
+    ///
+    /// If there are no files for this `site` and `sounding_type` and a fetch hook is set, the hook
+    /// is asked for the latest run scheduled at or before now, see `set_fetch_hook`. That needs the
+    /// hours between initializations of the `sounding_type`.
+    pub fn most_recent_analysis(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+    ) -> Result<Vec<Analysis>> {
+        let init_time = match self.most_recent_init_time(site, sounding_type) {
+            Ok(init_time) => init_time,
+            Err(BufkitDataErr::Database(rusqlite::Error::QueryReturnedNoRows)) => {
+                if self.fetch_hook.is_some() {
+                    if let Some(init_time) = latest_scheduled_init_time(sounding_type) {
+                        return self.fetch(site, sounding_type, &init_time);
+                    }
+                }
+
+                return Err(BufkitDataErr::NotEnoughData(format!(
+                    "no {} files for {}",
+                    sounding_type.source(),
+                    site.short_name()
+                )));
+            }
+            Err(err) => return Err(err),
+        };
+        self.retrieve(site, sounding_type, &init_time)
+    }
+
+    /// Retrieve the most recent file initialized at or before `as_of` as a sounding.
+    ///
+    /// This is the latest run that would have been available at that time, ignoring any delay
+    /// before it was published, for reconstructing what was known then.
+    pub fn latest_analysis_before(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        as_of: NaiveDateTime,
+    ) -> Result<Vec<Analysis>> {
+        debug_assert!(site.id() > 0);
+        debug_assert!(sounding_type.id() > 0);
+
+        let init_time: NaiveDateTime = match self.db_conn.query_row(
+            "
+                SELECT init_time FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time <= ?3
+                ORDER BY init_time DESC
+                LIMIT 1
+            ",
+            &[&site.id(), &sounding_type.id(), &as_of as &dyn ToSql],
+            |row| row.get(0),
+        ) {
+            Ok(init_time) => init_time,
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(BufkitDataErr::NotEnoughData(format!(
+                    "no {} files for {} at or before {}",
+                    sounding_type.source(),
+                    site.short_name(),
+                    as_of
+                )))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        self.retrieve(site, sounding_type, &init_time)
+    }
+
+    /// Retrieve the forecast for `lead` after `init_time` from a model run.
+    ///
+    /// Returns `None` if the run doesn't have a sounding valid at exactly `init_time + lead`.
+    pub fn retrieve_lead_time(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+        lead: Duration,
+    ) -> Result<Option<Analysis>> {
+        let anals = self.retrieve(site, sounding_type, init_time)?;
+        Ok(Self::find_valid_time(anals, *init_time + lead))
+    }
+
+    /// Retrieve the forecast for `lead` after the initialization time from every run initialized
+    /// between `start_time` and `end_time`, inclusive.
+    ///
+    /// Each forecast is returned with the initialization time of its run. Runs without a sounding
+    /// valid at the lead time are skipped.
+    pub fn retrieve_lead_time_series(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        start_time: &NaiveDateTime,
+        end_time: &NaiveDateTime,
+        lead: Duration,
+    ) -> Result<Vec<(NaiveDateTime, Analysis)>> {
+        let mut stmt = self.db_conn.prepare(
+            "
+                SELECT init_time, file_name, codec
+                FROM files
+                WHERE site_id = ?1 AND type_id = ?2 AND init_time >= ?3 AND init_time <= ?4
+                ORDER BY init_time ASC
+            ",
+        )?;
+
+        let files: Result<Vec<(NaiveDateTime, String, String)>> = stmt
+            .query_map(
+                &[
+                    &site.id(),
+                    &sounding_type.id(),
+                    start_time as &dyn ToSql,
+                    end_time,
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mut series = vec![];
+        for (init_time, file_name, codec) in files? {
+            let data = self.load_data(&file_name, Codec::from_str(&codec)?)?;
+            let anals = self.decode_data(&data, &file_name, sounding_type.file_type())?;
+
+            if let Some(anal) = Self::find_valid_time(anals, init_time + lead) {
+                series.push((init_time, anal));
+            }
+        }
+
+        Ok(series)
+    }
+
+    /// Find the analysis valid at a time.
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn find_valid_time(anals: Vec<Analysis>, valid_time: NaiveDateTime) -> Option<Analysis> {
+        anals
+            .into_iter()
+            .find(|anal| anal.sounding().valid_time() == Some(valid_time))
+    }
+
+    fn compressed_file_name(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
         init_time: &NaiveDateTime,
     ) -> String {
-        let file_string = init_time.format("%Y-%m-%dT%H%MZ").to_string();
+        self.file_name_template.render(
+            init_time,
+            sounding_type.source(),
+            sounding_type.file_type().as_static(),
+            site.short_name(),
+            self.codec.extension(),
+        )
+    }
+
+    /// Remove a file from the archive.
+    ///
+    /// If the trash is enabled the file is moved there, see `set_trash_enabled`.
+    pub fn remove(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        init_time: &NaiveDateTime,
+    ) -> Result<()> {
+        let init_time = &truncate_to_minute(init_time);
+        let file_name: String = self.db_conn.query_row(
+            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
+            |row| row.get(0),
+        )?;
+
+        self.delete_file(&file_name)
+    }
+
+    /// Remove several files from the archive.
+    ///
+    /// Files that aren't in the archive are skipped, and the number of files actually removed is
+    /// returned. If the trash is enabled the files are moved there, see `set_trash_enabled`. Each
+    /// file is taken out of the index only after it is removed from storage, so a file that can't be
+    /// removed is skipped and stays in both. The rest are still removed, and then the first error is
+    /// returned.
+    pub fn remove_many(&self, keys: &[(Site, SoundingType, NaiveDateTime)]) -> Result<usize> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
+        )?;
+
+        let mut file_names: Vec<String> = vec![];
+        for (site, sounding_type, init_time) in keys {
+            match stmt.query_row(
+                &[
+                    &site.id(),
+                    &sounding_type.id(),
+                    &truncate_to_minute(init_time) as &dyn ToSql,
+                ],
+                |row| row.get(0),
+            ) {
+                Ok(file_name) => file_names.push(file_name),
+                Err(rusqlite::Error::QueryReturnedNoRows) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        self.delete_files(file_names)
+    }
+
+    /// Remove all the files for a `Site` and `SoundingType` initialized before `before`.
+    ///
+    /// This is handy for a retention policy. If the trash is enabled the files are moved there, see
+    /// `set_trash_enabled`. Each file is taken out of the index only after it is removed from
+    /// storage, so a file that can't be removed is skipped and stays in both, keeping them in sync.
+    /// The rest are still removed, and then the first error is returned. Otherwise returns the
+    /// number of files removed.
+    pub fn remove_range(
+        &self,
+        site: &Site,
+        sounding_type: &SoundingType,
+        before: &NaiveDateTime,
+    ) -> Result<usize> {
+        let mut stmt = self.db_conn.prepare(
+            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time < ?3",
+        )?;
+        let file_names: Result<Vec<String>> = stmt
+            .query_map(
+                &[&site.id(), &sounding_type.id(), before as &dyn ToSql],
+                |row| row.get(0),
+            )?
+            .map(|res| res.map_err(BufkitDataErr::Database))
+            .collect();
+
+        self.delete_files(file_names?)
+    }
+}
+
+/// Drop the seconds from a time, which is the precision of the times in file names.
+fn truncate_to_minute(time: &NaiveDateTime) -> NaiveDateTime {
+    time.with_second(0)
+        .and_then(|time| time.with_nanosecond(0))
+        .expect("Zero is always a valid second and nanosecond.")
+}
+
+/// The most recent initialization time at or before now for a `SoundingType` run every so many
+/// hours starting at 00Z, `None` if the hours between initializations isn't known.
+fn latest_scheduled_init_time(sounding_type: &SoundingType) -> Option<NaiveDateTime> {
+    let hours_between = u32::from(sounding_type.hours_between_initializations()?);
+    if hours_between == 0 {
+        return None;
+    }
+
+    let now = Utc::now().naive_utc();
+    let hour = now.hour() - now.hour() % hours_between;
+
+    now.date().and_hms_opt(hour, 0, 0)
+}
+
+/// One minus the relative standard error of the mean compression ratio of a sample of
+/// `(compressed, uncompressed)` sizes drawn from `population` files, limited to 0 through 1.
+fn sample_confidence(sizes: &[(u64, u64)], population: usize) -> f64 {
+    let n = sizes.len();
+    if n >= population {
+        return 1.0;
+    }
+    if n < 2 {
+        return 0.0;
+    }
+
+    let ratios: Vec<f64> = sizes
+        .iter()
+        .filter(|(compressed, _)| *compressed > 0)
+        .map(|&(compressed, uncompressed)| uncompressed as f64 / compressed as f64)
+        .collect();
+    if ratios.len() < 2 {
+        return 0.0;
+    }
+
+    let count = ratios.len() as f64;
+    let mean = ratios.iter().sum::<f64>() / count;
+    let variance = ratios.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (count - 1.0);
+
+    // Sampling without replacement, so the error shrinks to nothing as the sample nears the whole.
+    let correction = 1.0 - n as f64 / population as f64;
+    let relative_error = (variance / count * correction).sqrt() / mean;
+
+    (1.0 - relative_error).clamp(0.0, 1.0)
+}
+
+/// The start of a day and the start of the next day.
+fn day_range(date: NaiveDate) -> (NaiveDateTime, NaiveDateTime) {
+    let start = date
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is always a valid time.");
+    (start, start + Duration::days(1))
+}
+
+/// Move a file, copying it if it can't be renamed, e.g. because the destination is on another
+/// file system.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        remove_file(from)?;
+    }
+
+    Ok(())
+}
+
+/// Quote a field for a CSV file if it contains any special characters.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Split the text of a BUFKIT file into the sections for each station.
+///
+/// Each station starts with its own `SNPARM` header, so a file with several stations is just the
+/// single station files one after the other.
+fn split_bufkit_stations(text: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = text
+        .match_indices("SNPARM")
+        .map(|(idx, _)| idx)
+        .filter(|&idx| idx == 0 || text[..idx].ends_with('\n'))
+        .collect();
+
+    if starts.len() < 2 {
+        return vec![text];
+    }
+
+    // Keep anything before the first header, like comments, with the first station.
+    starts[0] = 0;
+    starts.push(text.len());
+    starts.windows(2).map(|w| &text[w[0]..w[1]]).collect()
+}
+
+/*--------------------------------------------------------------------------------------------------
+                                          Unit Tests
+--------------------------------------------------------------------------------------------------*/
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::{FileType, Location, StateProv};
+    use chrono::NaiveDate;
+    use metfor::{Meters, Quantity};
+    use sounding_bufkit::BufkitFile;
+    use std::fs::read_dir;
+    use tempdir::TempDir;
+
+    // struct to hold temporary data for tests.
+    struct TestArchive {
+        tmp: TempDir,
+        arch: Archive,
+    }
+
+    // Function to create a new archive to test.
+    fn create_test_archive() -> Result<TestArchive> {
+        let tmp = TempDir::new("bufkit-data-test-archive")?;
+        let arch = Archive::create(tmp.path())?;
+
+        Ok(TestArchive { tmp, arch })
+    }
+
+    // Function to fetch a list of test files.
+    fn get_test_data() -> Result<
+        Vec<(
+            Site,
+            SoundingType,
+            NaiveDateTime,
+            NaiveDateTime,
+            Location,
+            String,
+        )>,
+    > {
+        let path = PathBuf::new().join("example_data");
+
+        let files = read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                entry.file_type().ok().and_then(|ft| {
+                    if ft.is_file() {
+                        Some(entry.path())
+                    } else {
+                        None
+                    }
+                })
+            });
+
+        let mut to_return = vec![];
+
+        for path in files {
+            //
+            // FIXME: handle multiple file types, like BUFR and whatever else types we want to work
+            //
+            let bufkit_file = BufkitFile::load(&path)?;
+            let bufkit_data = bufkit_file.data()?;
+            let mut bufkit_iter = bufkit_data.into_iter();
+            let no_soundings =
+                || BufkitDataErr::NotEnoughData(format!("no soundings in {}", path.display()));
+            let anal = bufkit_iter.next().ok_or_else(no_soundings)?;
+            let snd = anal.sounding();
+
+            let model = if path.to_string_lossy().to_string().contains("gfs") {
+                SoundingType::new("GFS", false, FileType::BUFKIT, 6)
+            } else {
+                SoundingType::new("NAM", false, FileType::BUFKIT, 6)
+            };
+            let site = if path.to_string_lossy().to_string().contains("kmso") {
+                Site::new("kmso")
+            } else {
+                panic!("Unprepared for this test data!");
+            };
+
+            let init_time = snd.valid_time().expect("NO VALID TIME?!");
+
+            let (lat, lon) = snd.station_info().location().unwrap();
+            let elevation = snd.station_info().elevation().unwrap();
+            let loc = Location::from_quantities(lat, lon, elevation, None);
+
+            let anal = bufkit_iter.last().ok_or_else(no_soundings)?;
+            let snd = anal.sounding();
+            let end_time = snd.valid_time().expect("NO VALID TIME FOR THE LAST ONE!?");
+
+            to_return.push((
+                site.to_owned(),
+                model,
+                init_time,
+                end_time,
+                loc,
+                path.to_string_lossy().to_string(),
+            ))
+        }
+
+        Ok(to_return)
+    }
+
+    // Function to fill the archive with some example data.
+    fn fill_test_archive(arch: &mut Archive) -> Result<()> {
+        let test_data = get_test_data().expect("Error loading test data.");
+
+        for (site, sounding_type, init_time, end_time, loc, file_name) in test_data {
+            let site = arch.validate_or_add_site(site)?;
+            let sounding_type = arch.validate_or_add_sounding_type(sounding_type)?;
+            let loc = arch.validate_or_add_location(loc)?;
+            arch.add_file(
+                &site,
+                &sounding_type.clone(),
+                &loc,
+                &init_time,
+                &end_time,
+                &file_name,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Connecting, creating, and maintaining the archive.
+    // ---------------------------------------------------------------------------------------------
+    #[test]
+    fn test_archive_create_new() {
+        assert!(create_test_archive().is_ok());
+    }
+
+    #[test]
+    fn test_archive_connect() {
+        let TestArchive { tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+        drop(arch);
+
+        assert!(Archive::connect(tmp.path()).is_ok());
+        assert!(Archive::connect("unlikely_directory_in_my_project").is_err());
+    }
+
+    #[test]
+    fn test_open_or_create() -> Result<()> {
+        // Existing archive is opened, not replaced.
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+        fill_test_archive(&mut arch)?;
+        drop(arch);
+
+        let arch = Archive::open_or_create(tmp.path())?;
+        assert_eq!(arch.count()?, 7);
+        drop(arch);
+
+        // Missing archive is created.
+        let new_root = tmp.path().join("new_archive");
+        assert!(!Archive::exists(&new_root));
+        match Archive::connect(&new_root) {
+            Err(BufkitDataErr::ArchiveNotFound(_)) => {}
+            other => panic!("unexpected result connecting: {:?}", other),
+        }
+        let arch = Archive::open_or_create(&new_root)?;
+        assert_eq!(arch.count()?, 0);
+        drop(arch);
+        assert!(Archive::exists(&new_root));
+        assert!(Archive::connect(&new_root).is_ok());
+
+        // A corrupt index is an error, not a reason to create a new archive.
+        std::fs::write(new_root.join("index.sqlite"), b"this is not a database")?;
+        assert!(Archive::open_or_create(&new_root).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_config() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-archive")?;
+
+        let config = ArchiveConfig::default()
+            .with_file_dir("soundings")
+            .with_index("catalog.db");
+        let mut arch = Archive::create_with_config(tmp.path(), config)?;
+        assert!(tmp.path().join("soundings").is_dir());
+        assert!(tmp.path().join("catalog.db").is_file());
+        assert!(Archive::exists(tmp.path()));
+        assert!(!tmp.path().join("files").exists());
+        assert!(!tmp.path().join("index.sqlite").exists());
+
+        fill_test_archive(&mut arch)?;
+        drop(arch);
+
+        let arch = Archive::connect(tmp.path())?;
+        assert_eq!(arch.count()?, 7);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let bad = ArchiveConfig::default().with_file_dir("a/b");
+        assert!(Archive::create_with_config(tmp.path().join("bad"), bad).is_err());
+        let bad = ArchiveConfig::default()
+            .with_file_dir("data")
+            .with_index("data");
+        assert!(Archive::create_with_config(tmp.path().join("bad"), bad).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocate_blobs() -> Result<()> {
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch)?;
+
+        let new_dir = tmp.path().join("bigger_volume").join("blobs");
+        arch.relocate_blobs(&new_dir)?;
+
+        assert_eq!(read_dir(tmp.path().join("files"))?.count(), 0);
+        assert_eq!(read_dir(&new_dir)?.count(), 7);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        assert!(!arch.retrieve(&site, &gfs, &init_time)?.is_empty());
+        drop(arch);
+
+        // The new location is remembered, and new files go there too.
+        let arch = Archive::connect(tmp.path())?;
+        assert!(!arch.retrieve(&site, &gfs, &init_time)?.is_empty());
+        arch.remove(&site, &gfs, &init_time)?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        arch.add_file(
+            &site,
+            &gfs,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040106Z_gfs_kmso.buf",
+        )?;
+        assert_eq!(read_dir(tmp.path().join("files"))?.count(), 0);
+        assert_eq!(read_dir(&new_dir)?.count(), 7);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_write_keeps_existing_file() -> Result<()> {
+        // Gives up part way through, like a disk filling up.
+        struct FailingReader(usize);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                let n = buf.len().min(self.0);
+                buf[..n].iter_mut().for_each(|b| *b = b'x');
+                self.0 -= n;
+                Ok(n)
+            }
+        }
+
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch)?;
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        let original = arch.retrieve(&site, &gfs, &init_time)?.len();
+
+        assert!(arch
+            .add_data(
+                &site,
+                &gfs,
+                &loc,
+                &init_time,
+                &init_time,
+                &init_time,
+                OnConflict::Replace,
+                &mut FailingReader(100_000),
+            )
+            .is_err());
+
+        assert_eq!(arch.retrieve(&site, &gfs, &init_time)?.len(), original);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert!(arch.verify()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_blob_store() -> Result<()> {
+        use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+        #[derive(Debug, Default)]
+        struct MemoryBlobStore(Rc<RefCell<HashMap<String, Vec<u8>>>>);
+
+        impl BlobStore for MemoryBlobStore {
+            fn put(&self, name: &str, data: &mut dyn Read) -> Result<()> {
+                let mut buf = vec![];
+                data.read_to_end(&mut buf)?;
+                self.0.borrow_mut().insert(name.to_owned(), buf);
+                Ok(())
+            }
+
+            fn get(&self, name: &str) -> Result<Box<dyn Read>> {
+                let buf = self.0.borrow().get(name).cloned();
+                buf.map(|buf| Box::new(std::io::Cursor::new(buf)) as Box<dyn Read>)
+                    .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
+            }
+
+            fn delete(&self, name: &str) -> Result<()> {
+                self.0.borrow_mut().remove(name);
+                Ok(())
+            }
+
+            fn list(&self) -> Result<Vec<String>> {
+                Ok(self.0.borrow().keys().cloned().collect())
+            }
+        }
+
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let blobs = Rc::new(RefCell::new(HashMap::new()));
+        arch.set_blob_store(Box::new(MemoryBlobStore(Rc::clone(&blobs))));
+        fill_test_archive(&mut arch)?;
+
+        assert_eq!(blobs.borrow().len(), 7);
+        assert_eq!(read_dir(tmp.path().join("files"))?.count(), 0);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        assert!(!arch.retrieve(&site, &gfs, &init_time)?.is_empty());
+
+        arch.remove(&site, &gfs, &init_time)?;
+        assert_eq!(blobs.borrow().len(), 6);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_name_template() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-archive")?;
+
+        assert!(
+            Archive::create_with_file_name_template(tmp.path(), "{time}_{source}.buf").is_err()
+        );
+
+        let mut arch = Archive::create_with_file_name_template(
+            tmp.path().join("arch"),
+            "{site}-{source}-{time}{ext}",
+        )?;
+        assert_eq!(arch.file_name_template(), "{site}-{source}-{time}{ext}");
+        fill_test_archive(&mut arch)?;
+        drop(arch);
+
+        let arch = Archive::connect(tmp.path().join("arch"))?;
+        assert_eq!(arch.file_name_template(), "{site}-{source}-{time}{ext}");
+
+        let kmso = arch.site_info("kmso")?.expect("No such site.");
+        let gfs = arch
+            .sounding_type_info("GFS")?
+            .expect("No such sounding type.");
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        let (fname, _) = arch.get_file_name_for(&kmso, &gfs, &init_time)?;
+        assert_eq!(fname, "kmso-GFS-2017-04-01T1200Z.gz");
+        assert_eq!(
+            arch.compressed_file_name(&kmso, &gfs, &init_time),
+            "kmso-GFS-2017-04-01T1200Z.gz"
+        );
+        assert!(arch.retrieve(&kmso, &gfs, &init_time).is_ok());
+
+        let (missing, extra) = arch.check()?;
+        assert!(missing.is_empty());
+        assert!(extra.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect_original_index() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-archive")?;
+        create_dir(tmp.path().join("files"))?;
+
+        // An archive as the first release made it, with one file in it.
+        {
+            let db_conn = Connection::open(tmp.path().join("index.sqlite"))?;
+            db_conn.execute_batch(include_str!("original_index.sql"))?;
+            db_conn.execute_batch(
+                "
+                    INSERT INTO types (type, file_type, interval, observed)
+                    VALUES ('GFS', 'BUFKIT', 6, 0);
+                    INSERT INTO sites (short_name) VALUES ('kmso');
+                    INSERT INTO locations (latitude, longitude, elevation_meters)
+                    VALUES (46920000, -114080000, 972);
+                    INSERT INTO files (type_id, site_id, location_id, init_time, end_time, file_name)
+                    VALUES (1, 1, 1, '2017-04-01T00:00:00', '2017-04-01T00:00:00',
+                        '2017-04-01T0000Z_GFS_BUFKIT_kmso.gz');
+                ",
+            )?;
+
+            let mut encoder = GzEncoder::new(
+                File::open("example_data/2017040100Z_gfs_kmso.buf")?,
+                Compression::default(),
+            );
+            std::io::copy(
+                &mut encoder,
+                &mut File::create(
+                    tmp.path()
+                        .join("files")
+                        .join("2017-04-01T0000Z_GFS_BUFKIT_kmso.gz"),
+                )?,
+            )?;
+        }
+
+        let mut arch = Archive::connect(tmp.path())?;
+        arch.check_schema()?;
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        assert_eq!(gfs.source_url(), None);
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        assert!(!arch.retrieve(&site, &gfs, &hour(0))?.is_empty());
+        assert_eq!(arch.file_comment(&site, &gfs, &hour(0))?, None);
+
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        arch.add_file(
+            &site,
+            &gfs,
+            &loc,
+            &hour(6),
+            &hour(6),
+            "example_data/2017040106Z_gfs_kmso.buf",
+        )?;
+        assert_eq!(arch.count()?, 2);
+        assert_eq!(arch.all_locations()?.len(), 1);
+
+        arch.set_trash_enabled(true);
+        arch.remove(&site, &gfs, &hour(0))?;
+        arch.restore(&site, &gfs, &hour(0))?;
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        // Opening it again doesn't change anything.
+        drop(arch);
+        let arch = Archive::connect(tmp.path())?;
+        arch.check_schema()?;
+        assert_eq!(arch.count()?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_schema() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        arch.check_schema()?;
+
+        // Pretend the archive was made by a different version of the crate.
+        arch.db_conn.execute_batch(
+            "
+                ALTER TABLE files ADD COLUMN forecaster TEXT;
+                DROP TABLE trash;
+            ",
+        )?;
+
+        match arch.check_schema() {
+            Err(BufkitDataErr::SchemaMismatch(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "extra column files.forecaster".to_owned(),
+                    "missing table trash".to_owned(),
+                ]
+            ),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_created_by_version() -> Result<()> {
+        let TestArchive { tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        assert_eq!(
+            arch.created_by_version()?,
+            Some(env!("CARGO_PKG_VERSION").to_owned())
+        );
+
+        // Archives from before the version was recorded don't have one.
+        arch.db_conn.execute(
+            "DELETE FROM meta WHERE key = 'created_by_version'",
+            NO_PARAMS,
+        )?;
+        drop(arch);
+        let arch = Archive::connect(tmp.path())?;
+        assert_eq!(arch.created_by_version()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_index() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let num_files = arch.count()?;
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let original =
+            arch.retrieve(&kmso, &arch.sounding_type_info("NAM")?.unwrap(), &init_time)?;
+
+        // Lose the index, along with one of the sounding types.
+        arch.db_conn
+            .execute_batch("DELETE FROM files; DELETE FROM types WHERE type = 'NAM';")?;
+        // Not a name the archive would have made.
+        std::fs::write(arch.file_dir.join("notes.txt"), "not a sounding")?;
+
+        assert_eq!(arch.rebuild_index()?, num_files as usize);
+        assert_eq!(arch.count()?, num_files);
+        assert_eq!(arch.check()?, (vec![], vec!["notes.txt".to_owned()]));
+        assert!(arch.verify()?.is_empty());
+
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        assert_eq!(nam.file_type(), FileType::BUFKIT);
+        assert_eq!(nam.hours_between_initializations(), None);
+        let recovered = arch.retrieve(&kmso, &nam, &init_time)?;
+        assert_eq!(recovered.len(), original.len());
+        assert_eq!(
+            arch.locations_for_site_and_type(&kmso, &nam)?,
+            vec![Location::new(46.87, -114.16, 1335, None)]
+        );
+
+        // Nothing left to recover.
+        assert_eq!(arch.rebuild_index()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        // Nothing to do in rollback journal mode.
+        arch.checkpoint()?;
+
+        let mode: String = arch
+            .db_conn
+            .query_row("PRAGMA journal_mode=WAL", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(mode, "wal");
+
+        fill_test_archive(&mut arch)?;
+        let wal = tmp.path().join("index.sqlite-wal");
+        assert!(wal.metadata()?.len() > 0);
+
+        arch.checkpoint()?;
+        assert_eq!(wal.metadata()?.len(), 0);
+        assert_eq!(arch.count()?, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_paths() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.fsck_paths()?;
+
+        // A file in the index that isn't on disk.
+        let file_name: String =
+            arch.db_conn
+                .query_row("SELECT file_name FROM files LIMIT 1", NO_PARAMS, |row| {
+                    row.get(0)
+                })?;
+        remove_file(arch.file_dir.join(&file_name))?;
+        match arch.fsck_paths() {
+            Err(BufkitDataErr::BadArchivePath(path, reason)) => {
+                assert_eq!(path, arch.file_dir);
+                assert!(reason.contains(&file_name));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // The whole files directory is gone.
+        std::fs::remove_dir_all(&arch.file_dir)?;
+        assert!(matches!(
+            arch.fsck_paths(),
+            Err(BufkitDataErr::BadArchivePath(_, _))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_init_times() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        assert!(arch.check_init_times()?.is_empty());
+
+        // Give a file the name it would have if it was initialized an hour later.
+        let good_name = "2017-04-01T0600Z_GFS_BUFKIT_kmso.gz";
+        let bad_name = "2017-04-01T0700Z_GFS_BUFKIT_kmso.gz";
+        std::fs::rename(arch.file_dir.join(good_name), arch.file_dir.join(bad_name))?;
+        arch.db_conn.execute(
+            "UPDATE files SET file_name = ?2 WHERE file_name = ?1",
+            [good_name, bad_name],
+        )?;
+
+        // The names on disk and in the index agree, so check doesn't notice.
+        let (missing, not_indexed) = arch.check()?;
+        assert!(missing.is_empty() && not_indexed.is_empty());
+
+        assert_eq!(arch.check_init_times()?, vec![bad_name.to_owned()]);
+
+        let diagnosis = arch.diagnose(false)?;
+        assert_eq!(diagnosis.init_time_mismatches(), &[bad_name.to_owned()]);
+        assert!(!diagnosis.is_healthy());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // Rename all files with "NAM" in them
+        let files_dir = tmp.path().join("files");
+        std::fs::read_dir(files_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains("NAM"))
+            .for_each(|entry| {
+                let mut fname = entry.path().to_string_lossy().to_string();
+                let start = fname.find("NAM").unwrap();
+                let end = start + 3;
+                fname.replace_range(start..end, "NAMM");
+                std::fs::rename(entry.path(), fname).unwrap();
+            });
+
+        let (missing_files, extra_files) = dbg!(arch.check().unwrap());
+
+        assert_eq!(missing_files.len(), 3);
+        assert_eq!(missing_files.len(), extra_files.len());
+
+        for fname in missing_files {
+            assert!(fname.contains("_NAM_"));
+            assert!(!fname.contains("_NAMM_"));
+            assert!(!fname.contains("_GFS_"));
+        }
+
+        for fname in extra_files {
+            assert!(fname.contains("_NAMM_"));
+            assert!(!fname.contains("_NAM_"));
+            assert!(!fname.contains("_GFS_"));
+        }
+
+        // A file left by an interrupted write isn't a file in the archive.
+        File::create(
+            tmp.path()
+                .join("files")
+                .join("2017-04-01T0000Z_GFS_BUFKIT_kmso.gz.partial"),
+        )?;
+        assert_eq!(arch.check()?.1.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diagnose() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let diagnosis = arch.diagnose(true)?;
+        assert!(diagnosis.is_healthy());
+        assert_eq!(diagnosis.corrupt_files(), Some(&[][..]));
+
+        let mut records = arch.iter_records().collect::<Result<Vec<_>>>()?.into_iter();
+        let missing = records.next().unwrap();
+        let corrupt = records.next().unwrap();
+        let duplicate = records.next().unwrap();
+
+        // Delete one, corrupt another, and leave a stray file.
+        remove_file(arch.file_dir.join(missing.file_name()))?;
+        std::fs::write(arch.file_dir.join(corrupt.file_name()), b"not gzip data")?;
+        std::fs::write(arch.file_dir.join("stray.gz"), b"stray")?;
+
+        // Add an unused location.
+        arch.validate_or_add_location(Location::new(10.0, 10.0, 10, None))?;
+
+        // Index another file a few seconds from an existing one.
+        arch.db_conn.execute(
+            "
+                INSERT INTO files (type_id, site_id, location_id, init_time, end_time, file_name)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ",
+            &[
+                &duplicate.sounding_type().id() as &dyn ToSql,
+                &duplicate.site().id(),
+                &duplicate.location().id(),
+                &(duplicate.init_time() + chrono::Duration::seconds(30)),
+                &duplicate.end_time(),
+                &"duplicate.gz",
+            ],
+        )?;
+
+        let quick = arch.diagnose(false)?;
+        assert!(!quick.is_healthy());
+        assert_eq!(quick.corrupt_files(), None);
+        // The duplicate was only added to the index, so it is missing too.
+        assert_eq!(quick.missing_files().len(), 2);
+        assert!(quick
+            .missing_files()
+            .contains(&missing.file_name().to_owned()));
+        assert!(quick.missing_files().contains(&"duplicate.gz".to_owned()));
+        assert_eq!(quick.unindexed_files(), &["stray.gz".to_owned()]);
+        assert_eq!(quick.orphan_locations().len(), 1);
+        assert_eq!(quick.orphan_locations()[0].latitude(), 10.0);
+        assert_eq!(quick.duplicate_init_times().len(), 1);
+        assert!(quick.duplicate_init_times()[0].contains(&duplicate.file_name().to_owned()));
+        assert!(quick.duplicate_init_times()[0].contains(&"duplicate.gz".to_owned()));
+
+        assert_eq!(quick.checksum_mismatches(), None);
+
+        let full = arch.diagnose(true)?;
+        assert_eq!(
+            full.corrupt_files(),
+            Some(&[corrupt.file_name().to_owned()][..])
+        );
+        assert_eq!(full.checksum_mismatches(), Some(&[][..]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_and_backfill_checksums() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // Checksums are stored when files are added.
+        assert_eq!(arch.backfill_checksums()?, 0);
+        assert!(arch.verify()?.is_empty());
+
+        // Like an archive made before checksums were stored.
+        arch.db_conn
+            .execute("UPDATE files SET checksum = NULL", NO_PARAMS)?;
+        let tampered = arch.iter_records().next().unwrap()?;
+        arch.db_conn.execute(
+            "UPDATE files SET checksum = 0 WHERE file_name = ?1",
+            &[tampered.file_name()],
+        )?;
+
+        assert_eq!(arch.verify()?, vec![tampered.file_name().to_owned()]);
+        assert_eq!(arch.backfill_checksums()?, 6);
+        assert_eq!(arch.backfill_checksums()?, 0);
+
+        let diagnosis = arch.diagnose(true)?;
+        assert_eq!(
+            diagnosis.checksum_mismatches(),
+            Some(&[tampered.file_name().to_owned()][..])
+        );
+        assert!(!diagnosis.is_healthy());
+
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify site metadata
+    // ---------------------------------------------------------------------------------------------
+    #[test]
+    fn test_sites() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffe and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter_mut() {
+            *site = arch
+                .validate_or_add_site(site.clone())
+                .expect("Error adding site.");
+        }
+
+        let sites = dbg!(arch.sites())?;
+        let sites: Vec<_> = sites.iter().map(|s| s.short_name()).collect();
+
+        assert_eq!(sites.len(), 3);
+        assert!(sites.contains(&"kmso"));
+        assert!(sites.contains(&"ksea"));
+        assert!(sites.contains(&"kord"));
+        assert!(!sites.contains(&"xyz"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sites_paged() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        for name in &["ksea", "kord", "kmso", "katl", "kbtm"] {
+            arch.validate_or_add_site(Site::new(name))?;
+        }
+
+        let page = |limit, offset| -> Result<Vec<String>> {
+            Ok(arch
+                .sites_paged(limit, offset)?
+                .iter()
+                .map(|site| site.short_name().to_owned())
+                .collect())
+        };
+
+        assert_eq!(page(2, 0)?, vec!["katl", "kbtm"]);
+        assert_eq!(page(2, 2)?, vec!["kmso", "kord"]);
+        assert_eq!(page(2, 4)?, vec!["ksea"]);
+        assert!(page(2, 6)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sites_by_recency() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // One site with older data, and one without any.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        arch.validate_or_add_site(Site::new("kgpi"))?;
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1686, None))?;
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        arch.add_file(
+            &kbtm,
+            &nam,
+            &loc,
+            &hour(12),
+            &hour(12),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+
+        let recency: Vec<(String, NaiveDateTime)> = arch
+            .sites_by_recency()?
+            .into_iter()
+            .map(|(site, newest)| (site.short_name().to_owned(), newest))
+            .collect();
+        assert_eq!(
+            recency,
+            vec![("kmso".to_owned(), hour(18)), ("kbtm".to_owned(), hour(12))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_site_info() {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffe and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter_mut() {
+            assert!(!site.is_valid());
+
+            *site = arch
+                .validate_or_add_site(site.clone())
+                .expect("Error adding site.");
+
+            assert!(site.is_valid());
+        }
+
+        for site in test_sites.iter() {
+            let retr_site = arch.site_info(site.short_name()).unwrap().unwrap();
+
+            assert!(retr_site.is_valid());
+            assert_eq!(site.short_name(), retr_site.short_name());
+            assert_eq!(site.long_name(), retr_site.long_name());
+            assert_eq!(site.state_prov(), retr_site.state_prov());
+            assert_eq!(site.notes(), retr_site.notes());
+        }
+    }
+
+    #[test]
+    fn test_rename_site() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.validate_or_add_site(Site::new("kbtm"))?;
+        let num_files = arch.count()?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let mut before = vec![];
+        arch.export(&arch.site_info("kmso")?.unwrap(), &gfs, &init_time)?
+            .read_to_end(&mut before)?;
+
+        assert!(matches!(
+            arch.rename_site("kmso", "kbtm"),
+            Err(BufkitDataErr::SiteExists(_))
+        ));
+        assert!(matches!(
+            arch.rename_site("kxly", "kfoo"),
+            Err(BufkitDataErr::InvalidSite(_))
+        ));
+
+        let site = arch.rename_site("kmso", "kmsx")?;
+        assert_eq!(site.short_name(), "kmsx");
+        assert!(arch.site_info("kmso")?.is_none());
+        assert_eq!(arch.count()?, num_files);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let records = arch.query().site("kmsx").execute()?;
+        assert_eq!(records.len() as i64, num_files);
+        assert!(records
+            .iter()
+            .all(|record| record.file_name().contains("kmsx")));
+
+        let mut after = vec![];
+        arch.export(&site, &gfs, &init_time)?
+            .read_to_end(&mut after)?;
+        assert_eq!(before, after);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_sites() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let num_kmso = arch.count()?;
+
+        // A duplicate entry with a couple of GFS files, one of them also at kmso.
+        let dup = arch.validate_or_add_site(Site::new("KMSO"))?;
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let first = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let second = NaiveDate::from_ymd_opt(2017, 4, 2)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        for init_time in &[first, second] {
+            arch.add_file(
+                &dup,
+                &gfs,
+                &loc,
+                init_time,
+                init_time,
+                "example_data/2017040100Z_gfs_kmso.buf",
+            )?;
+        }
+
+        arch.merge_sites(&kmso, &kmso)?;
+        assert!(arch.site_info("kmso")?.is_some());
+
+        arch.merge_sites(&dup, &kmso)?;
+        assert!(arch.site_info("KMSO")?.is_none());
+        assert_eq!(arch.count()?, num_kmso + 1);
+        assert_eq!(arch.count_for(&kmso, &gfs)?, 5);
+        assert!(arch.file_exists(&kmso, &gfs, &second)?);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert!(arch
+            .query()
+            .site("kmso")
+            .execute()?
+            .iter()
+            .all(|record| record.file_name().ends_with("_kmso.gz")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_site_info() {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffe and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter_mut() {
+            *site = arch
+                .validate_or_add_site(site.clone())
+                .expect("Error adding site.");
+        }
+
+        let retr_site = arch.site_info("kmso").unwrap().unwrap();
+        assert_eq!(retr_site.short_name(), test_sites[2].short_name());
+        assert_eq!(retr_site.long_name(), test_sites[2].long_name());
+        assert_eq!(retr_site.notes(), test_sites[2].notes());
+        assert_eq!(retr_site.state_prov(), test_sites[2].state_prov());
+
+        let zootown = Site::new("kmso")
+            .with_long_name("Zootown".to_owned())
+            .with_notes("Mountains, not coast.".to_owned())
+            .with_state_prov(None)
+            .set_mobile(false);
+
+        arch.set_site_info(zootown.clone())
+            .expect("Error updating site.");
+
+        let retr_site = arch.site_info("kmso").unwrap().unwrap();
+        assert!(retr_site.is_valid());
+        assert_eq!(retr_site.short_name(), test_sites[2].short_name());
+        assert_ne!(retr_site.long_name(), test_sites[2].long_name());
+        assert_ne!(retr_site.notes(), test_sites[2].notes());
+        assert_eq!(retr_site.state_prov(), test_sites[2].state_prov());
+
+        assert_eq!(retr_site.short_name(), zootown.short_name());
+        assert_eq!(retr_site.long_name(), zootown.long_name());
+        assert_eq!(retr_site.notes(), zootown.notes());
+        assert_eq!(retr_site.state_prov(), zootown.state_prov());
+    }
+
+    #[test]
+    fn test_validate_site() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffe and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter() {
+            arch.validate_or_add_site(site.clone())?;
+        }
+
+        for site in test_sites.iter() {
+            let valid_site = arch.validate_site(site.clone())?;
+
+            assert!(valid_site.is_valid());
+            assert_eq!(valid_site.short_name(), site.short_name());
+        }
+
+        let bad_site = Site::new("kxyz")
+            .with_long_name("not real".to_owned())
+            .with_notes("I made this up, it may be real anyway.".to_owned())
+            .with_state_prov(None)
+            .set_mobile(false);
+
+        assert!(arch.validate_site(bad_site).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_after_external_edit() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+
+        // Someone renumbers the rows behind the archive's back.
+        arch.db_conn.execute_batch(
+            "
+                UPDATE sites SET id = id + 100;
+                UPDATE types SET id = id + 100;
+                UPDATE locations SET id = id + 100;
+            ",
+        )?;
+
+        let refreshed = arch.refresh_site(site.clone())?;
+        assert_eq!(refreshed.id(), site.id() + 100);
+        assert_eq!(refreshed.short_name(), "kmso");
+
+        let refreshed = arch.refresh_sounding_type(gfs.clone())?;
+        assert_eq!(refreshed.id(), gfs.id() + 100);
+
+        let refreshed = arch.refresh_location(loc)?;
+        assert_eq!(refreshed.id(), loc.id() + 100);
+
+        assert!(arch.refresh_site(Site::new("kxyz")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_or_add_site() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffe and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter_mut() {
+            *site = arch.validate_or_add_site(site.clone())?;
+
+            assert!(site.is_valid());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sites_round_trip() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sites = [
+            Site::new("kord")
+                .with_long_name("Chicago/O'Hare".to_owned())
+                .with_notes("Major air travel hub.".to_owned())
+                .with_state_prov(StateProv::IL)
+                .set_mobile(false),
+            Site::new("ksea")
+                .with_long_name("Seattle".to_owned())
+                .with_notes("A coastal city with coffee and rain".to_owned())
+                .with_state_prov(StateProv::WA)
+                .set_mobile(false),
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_notes("In a valley.".to_owned())
+                .with_state_prov(None)
+                .set_mobile(false),
+        ];
+
+        for site in test_sites.iter_mut() {
+            *site = arch.validate_or_add_site(site.clone())?;
+        }
+
+        assert_eq!(arch.site_info("ksea")?.unwrap().short_name(), "ksea");
+        assert_eq!(arch.site_info("kord")?.unwrap().short_name(), "kord");
+        assert_eq!(arch.site_info("xyz")?, None);
+
+        let retrieved_sites = arch.sites().expect("Error retrieving sites.");
+
+        for site in retrieved_sites {
+            println!("{:#?}", site);
+            assert!(test_sites
+                .iter()
+                .find(|st| st.short_name() == site.short_name())
+                .is_some());
+        }
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify sounding type metadata
+    // ---------------------------------------------------------------------------------------------
+
+    #[test]
+    fn test_sounding_types() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let types: Vec<String> = arch
+            .sounding_types()?
+            .iter()
+            .map(|t| t.source().to_owned())
+            .collect();
+
+        assert!(types.contains(&"GFS".to_owned()));
+        assert!(types.contains(&"NAM".to_owned()));
+        assert!(!types.contains(&"NAM4KM".to_owned()));
+        assert!(!types.contains(&"LocalWrf".to_owned()));
+        assert!(!types.contains(&"Other".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sounding_types_paged() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.validate_or_add_sounding_type(SoundingType::new_model("HRRR", FileType::BUFKIT, 1))?;
+
+        let sources = |types: Vec<SoundingType>| -> Vec<String> {
+            types.iter().map(|t| t.source().to_owned()).collect()
+        };
+
+        assert_eq!(
+            sources(arch.sounding_types_paged(2, 0)?),
+            vec!["GFS", "HRRR"]
+        );
+        assert_eq!(sources(arch.sounding_types_paged(2, 2)?), vec!["NAM"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sounding_type_info() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sts = [
+            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
+            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
+            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
+            SoundingType::new("Incident", true, FileType::BUFR, None),
+            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
+        ];
+
+        for st in test_sts.iter_mut() {
+            assert!(!st.is_valid());
+
+            *st = arch
+                .validate_or_add_sounding_type(st.clone())
+                .expect("Error adding sounding type.");
+
+            assert!(st.is_valid());
+        }
+
+        for st in test_sts.iter() {
+            let retr_st = arch.sounding_type_info(st.source()).unwrap().unwrap();
+
+            assert!(retr_st.is_valid());
+            assert_eq!(st.source(), retr_st.source());
+            assert_eq!(st.is_modeled(), retr_st.is_modeled());
+            assert_eq!(st.is_observed(), retr_st.is_observed());
+            assert_eq!(
+                st.hours_between_initializations(),
+                retr_st.hours_between_initializations()
+            );
+            assert_eq!(st.file_type(), retr_st.file_type());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sounding_type_info_ignores_case() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let nam_nest = SoundingType::new_model("NamNest", FileType::BUFKIT, 6);
+        assert_eq!(nam_nest.source(), "NAMNEST");
+        arch.validate_or_add_sounding_type(nam_nest)?;
+
+        for source in &["NamNest", "NAMNEST", "namnest"] {
+            let retr_st = arch
+                .sounding_type_info(source)?
+                .expect("Sounding type not found.");
+            assert_eq!(retr_st.source(), "NAMNEST");
+        }
+
+        // Adding it again with different casing finds the existing one.
+        let again = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "namNEST",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        assert_eq!(again.source(), "NAMNEST");
+        assert_eq!(arch.sounding_types()?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_sounding_type_info() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sts = [
+            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
+            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
+            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
+            SoundingType::new("Incident", true, FileType::BUFR, None),
+            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
+        ];
+
+        for st in test_sts.iter_mut() {
+            *st = arch
+                .validate_or_add_sounding_type(st.clone())
+                .expect("Error adding sounding type.");
+        }
+
+        let retr_st = arch.sounding_type_info("SREF").unwrap().unwrap();
+        assert_eq!(retr_st.source(), test_sts[4].source());
+        assert_eq!(retr_st.is_modeled(), test_sts[4].is_modeled());
+        assert_eq!(retr_st.is_observed(), test_sts[4].is_observed());
+        assert_eq!(
+            retr_st.hours_between_initializations(),
+            test_sts[4].hours_between_initializations()
+        );
+        assert_eq!(retr_st.file_type(), test_sts[4].file_type());
+
+        let sref = SoundingType::new("SREF", false, FileType::BUFKIT, None);
+
+        arch.set_sounding_type_info(sref.clone())
+            .expect("Error updating sounding type.");
+
+        let retr_st = arch.sounding_type_info("SREF").unwrap().unwrap();
+        assert!(retr_st.is_valid());
+        assert_eq!(retr_st.source(), test_sts[4].source());
+        assert_eq!(retr_st.is_modeled(), test_sts[4].is_modeled());
+        assert_eq!(retr_st.is_observed(), test_sts[4].is_observed());
+        assert_ne!(
+            retr_st.hours_between_initializations(),
+            test_sts[4].hours_between_initializations()
+        );
+        assert_eq!(retr_st.file_type(), test_sts[4].file_type());
+
+        assert_eq!(retr_st.source(), sref.source());
+        assert_eq!(retr_st.is_modeled(), sref.is_modeled());
+        assert_eq!(retr_st.is_observed(), sref.is_observed());
+        assert_eq!(
+            retr_st.hours_between_initializations(),
+            sref.hours_between_initializations()
+        );
+        assert_eq!(retr_st.file_type(), sref.file_type());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sounding_types_for_site() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.expect("No such site.");
+
+        let types: Vec<String> = arch
+            .sounding_types_for_site(&site)?
+            .iter()
+            .map(|t| t.source().to_owned())
+            .collect();
+
+        assert!(types.contains(&"GFS".to_owned()));
+        assert!(types.contains(&"NAM".to_owned()));
+        assert!(!types.contains(&"NAM4KM".to_owned()));
+        assert!(!types.contains(&"LocalWrf".to_owned()));
+        assert!(!types.contains(&"Other".to_owned()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sounding_type_site_counts() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // Put the NAM at a second site, and add a type without any files.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1686, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        arch.add_file(
+            &kbtm,
+            &nam,
+            &loc,
+            &init_time,
+            &(init_time + chrono::Duration::hours(84)),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+        arch.validate_or_add_sounding_type(SoundingType::new_model("WRF", FileType::BUFKIT, None))?;
+
+        let counts: Vec<(String, i64)> = arch
+            .sounding_type_site_counts()?
+            .into_iter()
+            .map(|(st, count)| (st.source().to_owned(), count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("NAM".to_owned(), 2),
+                ("GFS".to_owned(), 1),
+                ("WRF".to_owned(), 0)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_sounding_types() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // Keep the NAM going a couple of days longer than the GFS, and add a type without files.
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let day = |d, h| {
+            NaiveDate::from_ymd_opt(2017, 4, d)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        arch.add_file(
+            &kmso,
+            &nam,
+            &loc,
+            &day(3, 0),
+            &day(6, 12),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+        arch.validate_or_add_sounding_type(SoundingType::new_model("WRF", FileType::BUFKIT, None))?;
+
+        let stale: Vec<(String, Option<NaiveDateTime>)> = arch
+            .stale_sounding_types(&day(5, 0), Duration::days(3))?
+            .into_iter()
+            .map(|(st, newest)| (st.source().to_owned(), newest))
+            .collect();
+        assert_eq!(
+            stale,
+            vec![
+                ("GFS".to_owned(), Some(day(1, 18))),
+                ("WRF".to_owned(), None)
+            ]
+        );
+
+        let stale = arch.stale_sounding_types(&day(5, 0), Duration::days(5))?;
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].0.source(), "WRF");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_sounding_type() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sts = [
+            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
+            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
+            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
+            SoundingType::new("Incident", true, FileType::BUFR, None),
+            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
+        ];
+
+        for st in test_sts.iter_mut() {
+            *st = arch
+                .validate_or_add_sounding_type(st.clone())
+                .expect("Error adding sounding type.");
+        }
+
+        for st in test_sts.iter() {
+            arch.validate_or_add_sounding_type(st.clone())?;
+        }
+
+        for st in test_sts.iter() {
+            let valid_st = arch.validate_sounding_type(st.clone())?;
+
+            assert!(valid_st.is_valid());
+            assert_eq!(valid_st.source(), st.source());
+        }
+
+        let bad_st = SoundingType::new("drill into ground", false, FileType::BUFR, 1);
+
+        assert!(arch.validate_sounding_type(bad_st).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_or_add_sounding_type() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_sts = [
+            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
+            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
+            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
+            SoundingType::new("Incident", true, FileType::BUFR, None),
+            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
+        ];
+
+        for st in test_sts.iter_mut() {
+            *st = arch
+                .validate_or_add_sounding_type(st.clone())
+                .expect("Error adding sounding type.");
+
+            assert!(st.is_valid());
+        }
+
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query or modify location metadata
+    // ---------------------------------------------------------------------------------------------
+
+    fn populate_test_locations(arch: &Archive) -> [Location; 5] {
+        let mut test_locs = [
+            Location::new(43.0, -110.0, 599, None),
+            Location::new(45.0, -112.0, 699, None),
+            Location::new(47.0, -114.0, 799, None),
+            Location::new(49.0, -116.0, 999, None),
+            Location::new(49.0, -116.0, 999, None), // Duplicate!
+        ];
+
+        for loc in test_locs.iter_mut() {
+            assert!(!loc.is_valid());
+
+            *loc = arch
+                .validate_or_add_location(loc.clone())
+                .expect("Error adding location.");
+
+            assert!(loc.is_valid());
+        }
+
+        test_locs
+    }
+
+    #[test]
+    fn test_location_quantities() {
+        let loc = Location::from_quantities(46.92, -114.08, Meters(971.6), None);
+        assert_eq!(loc.elevation(), 972);
+        assert_eq!(loc.elevation_length().unpack(), 972.0);
+        assert_eq!(loc, Location::new(46.92, -114.08, 972, None));
+    }
+
+    #[test]
+    fn test_all_locations() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let _ = populate_test_locations(&arch);
+
+        let locs = dbg!(arch.all_locations())?;
+        let locs: Vec<_> = locs.iter().map(|s| s.elevation()).collect();
+
+        assert_eq!(locs.len(), 4);
+        assert!(locs.contains(&599));
+        assert!(locs.contains(&699));
+        assert!(locs.contains(&799));
+        assert!(locs.contains(&999));
+        assert!(!locs.contains(&899));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locations_paged() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let _ = populate_test_locations(&arch);
+
+        let elevations =
+            |locs: Vec<Location>| -> Vec<i32> { locs.iter().map(|loc| loc.elevation()).collect() };
+
+        assert_eq!(elevations(arch.locations_paged(3, 0)?), vec![599, 699, 799]);
+        assert_eq!(elevations(arch.locations_paged(3, 3)?), vec![999]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_location_info() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let test_locs = populate_test_locations(&arch);
+
+        for loc in test_locs.iter() {
+            let retr_loc = arch
+                .location_info(loc.latitude(), loc.longitude(), loc.elevation())
+                .unwrap()
+                .unwrap();
+
+            assert!(loc.is_valid());
+            assert!(retr_loc.is_valid());
+            assert_eq!(loc.latitude(), retr_loc.latitude());
+            assert_eq!(loc.longitude(), retr_loc.longitude());
+            assert_eq!(loc.elevation(), retr_loc.elevation());
+            assert_eq!(loc.tz_offset(), retr_loc.tz_offset());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieve_or_add_location() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let _ = populate_test_locations(&arch);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_location_info() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let test_locs = populate_test_locations(&arch);
+
+        let loc = test_locs[0];
+        assert!(loc.is_valid());
+        let loc = loc.with_tz_offset(-3600 * 6);
+
+        arch.set_location_info(loc)?;
+
+        let retr_loc = arch
+            .location_info(loc.latitude(), loc.longitude(), loc.elevation())?
+            .unwrap();
+
+        assert_eq!(retr_loc.tz_offset(), loc.tz_offset());
+        assert_ne!(retr_loc.tz_offset(), test_locs[0].tz_offset());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nearest_location() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        assert_eq!(arch.nearest_location(47.0, -114.0, 100.0)?, None);
+
+        let test_locs = populate_test_locations(&arch);
+
+        // Slightly different rounding of the same point.
+        let nearest = arch.nearest_location(47.000_04, -113.999_96, 0.1)?;
+        assert_eq!(nearest, Some(test_locs[2]));
+        assert!(nearest.unwrap().is_valid());
+
+        // Closer to one location than another.
+        assert_eq!(
+            arch.nearest_location(45.4, -112.6, 500.0)?,
+            Some(test_locs[1])
+        );
+
+        // Too far from all of them.
+        assert_eq!(arch.nearest_location(47.5, -114.0, 10.0)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_populate_tz_offsets() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let test_locs = populate_test_locations(&arch);
+        arch.set_location_info(test_locs[0].with_tz_offset(-3600 * 6))?;
+
+        assert_eq!(arch.populate_tz_offsets()?, 3);
+        assert_eq!(arch.populate_tz_offsets()?, 0);
+
+        let tz_offset = |loc: Location| -> Result<Option<i32>> {
+            Ok(arch
+                .location_info(loc.latitude(), loc.longitude(), loc.elevation())?
+                .and_then(|loc| loc.tz_offset()))
+        };
+        assert_eq!(tz_offset(test_locs[0])?, Some(-6 * 3600));
+        assert_eq!(tz_offset(test_locs[1])?, Some(-7 * 3600));
+        assert_eq!(tz_offset(test_locs[3])?, Some(-8 * 3600));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_location_count_for_site() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // The GFS and NAM files disagree about where the station is.
+        let kmso = arch.site_info("kmso")?.expect("No such site.");
+        assert_eq!(arch.location_count_for_site(&kmso)?, 2);
+
+        // A mobile unit that reported from two places, twice from the first.
+        let mobile = arch.validate_or_add_site(Site::new("mobile1").set_mobile(true))?;
+        let st = arch.validate_or_add_sounding_type(SoundingType::new_observed(
+            "MOBILE",
+            FileType::BUFKIT,
+            None,
+        ))?;
+        let here = arch.validate_or_add_location(Location::new(45.0, -110.0, 1500, None))?;
+        let there = arch.validate_or_add_location(Location::new(44.0, -111.0, 1800, None))?;
+        assert_eq!(arch.location_count_for_site(&mobile)?, 0);
+
+        for (hour, loc) in &[(0, here), (6, there), (12, here)] {
+            let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(*hour, 0, 0)
+                .unwrap();
+            arch.add_file(
+                &mobile,
+                &st,
+                loc,
+                &init_time,
+                &init_time,
+                "example_data/2017040100Z_gfs_kmso.buf",
+            )?;
+        }
+        assert_eq!(arch.location_count_for_site(&mobile)?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_location_csv_round_trip() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_locs = populate_test_locations(&arch).to_vec();
+        test_locs.dedup();
+        test_locs.push(arch.validate_or_add_location(Location::new(
+            -33.946_111,
+            151.177_222,
+            6,
+            36_000,
+        ))?);
+
+        let mut buf: Vec<u8> = vec![];
+        assert_eq!(arch.export_locations_csv(&mut buf)?, test_locs.len());
+
+        let TestArchive {
+            tmp: _tmp2,
+            arch: arch2,
+        } = create_test_archive().expect("Failed to create test archive.");
+        assert_eq!(arch2.import_locations(buf.as_slice())?, test_locs.len());
+
+        for loc in &test_locs {
+            let retr_loc = arch2
+                .location_info(loc.latitude(), loc.longitude(), loc.elevation())?
+                .expect("Location not imported.");
+            assert_eq!(retr_loc.latitude(), loc.latitude());
+            assert_eq!(retr_loc.longitude(), loc.longitude());
+            assert_eq!(retr_loc.tz_offset(), loc.tz_offset());
+        }
+
+        // Importing again doesn't add duplicates.
+        arch2.import_locations(buf.as_slice())?;
+        assert_eq!(arch2.all_locations()?.len(), test_locs.len());
+
+        assert!(arch2.import_locations("91.0,0.0,0,\n".as_bytes()).is_err());
+        assert!(arch2
+            .import_locations("45.0,-110.0,high,\n".as_bytes())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_locations_for_site_and_type() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.expect("No such site.");
+        let sounding_type = arch
+            .sounding_types_for_site(&site)?
+            .into_iter()
+            .filter(|st| st.source() == "GFS")
+            .nth(0)
+            .unwrap();
+
+        let locs: Vec<Location> = arch.locations_for_site_and_type(&site, &sounding_type)?;
+
+        assert_eq!(locs.len(), 1);
+        let loc = locs.into_iter().nth(0).unwrap();
+        assert_eq!(loc.latitude(), 46.92);
+        assert_eq!(loc.longitude(), -114.08);
+        assert_eq!(loc.elevation(), 972);
+        assert!(loc.tz_offset().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_location() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_locations = populate_test_locations(&arch);
+
+        for loc in test_locations.iter_mut() {
+            *loc = arch.validate_location(*loc)?;
+
+            assert!(loc.is_valid());
+        }
+
+        assert_eq!(test_locations[3].id(), test_locations[4].id());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_or_add_location() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let mut test_locations = populate_test_locations(&arch);
+
+        for loc in test_locations.iter_mut() {
+            *loc = arch
+                .validate_or_add_location(*loc)
+                .expect("Error adding location.");
+
+            assert!(loc.is_valid());
+        }
+
+        assert_eq!(test_locations[3].id(), test_locations[4].id());
+
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    // Query archive inventory
+    // ---------------------------------------------------------------------------------------------
+
+    #[test]
+    fn test_inventory() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.expect("No such site.");
+        let gfs = arch
+            .sounding_type_info("GFS")?
+            .expect("No such sounding type.");
+        let nam = arch
+            .sounding_type_info("NAM")?
+            .expect("No such sounding type.");
+
+        let first = NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0);
+        let last = NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0);
+
+        let inv = arch.inventory(&site)?;
+
+        assert_eq!(inv.range(&gfs).unwrap(), (first, last));
+        assert_eq!(inv.range(&nam).unwrap(), (first, last));
+
+        let gfs_locations = dbg!(inv.locations(&gfs));
+        assert_eq!(gfs_locations.len(), 1);
+        assert_eq!(gfs_locations[0].latitude(), 46.92);
+        assert_eq!(gfs_locations[0].longitude(), -114.08);
+        assert_eq!(gfs_locations[0].elevation(), 972);
+        assert!(gfs_locations[0].is_valid());
+
+        let nam_locations = inv.locations(&nam);
+        assert_eq!(nam_locations.len(), 1);
+        assert_eq!(nam_locations[0].latitude(), 46.87);
+        assert_eq!(nam_locations[0].longitude(), -114.16);
+        assert_eq!(nam_locations[0].elevation(), 1335);
+        assert!(nam_locations[0].is_valid());
+
+        let hour = |h| first + Duration::hours(h);
+        assert_eq!(
+            inv.init_times(&gfs),
+            &[hour(0), hour(6), hour(12), hour(18)]
+        );
+        assert_eq!(inv.init_times(&nam), &[hour(0), hour(12), hour(18)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inventories_for() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.expect("No such site.");
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+
+        // Another site with only two GFS runs and a gap between them.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1688, None))?;
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        for h in &[0, 18] {
+            arch.add_file(
+                &kbtm,
+                &gfs,
+                &loc,
+                &hour(*h),
+                &hour(*h),
+                &format!("example_data/20170401{:02}Z_gfs_kmso.buf", h),
+            )?;
+        }
+
+        let empty = arch.validate_or_add_site(Site::new("kxyz"))?;
+
+        let invs = arch.inventories_for(&[kbtm.clone(), empty, kmso.clone()])?;
+        let names: Vec<&str> = invs.iter().map(|inv| inv.site().short_name()).collect();
+        assert_eq!(names, vec!["kbtm", "kxyz", "kmso"]);
+
+        assert_eq!(invs[0].count(&gfs), 2);
+        assert_eq!(invs[0].count(&nam), 0);
+        assert_eq!(invs[0].range(&gfs), Some((hour(0), hour(18))));
+        assert_eq!(invs[0].missing(&gfs), &[(hour(6), hour(12))]);
+        assert_eq!(invs[0].locations(&gfs), &[loc]);
+
+        assert_eq!(invs[1].sounding_types().count(), 0);
+
+        assert_eq!(invs[2].count(&gfs), 4);
+        assert_eq!(invs[2].count(&nam), 3);
+        assert_eq!(invs[2].missing(&nam), &[(hour(6), hour(6))]);
+        assert!(invs[2].missing(&gfs).is_empty());
+
+        assert!(arch.inventories_for(&[])?.is_empty());
+
+        // More sites than are looked up in a single query.
+        let mut sites = vec![kmso];
+        for i in 0..1000 {
+            sites.push(arch.validate_or_add_site(Site::new(&format!("s{:03}", i)))?);
+        }
+        sites.push(kbtm);
+        let invs = arch.inventories_for(&sites)?;
+        assert_eq!(invs.len(), sites.len());
+        assert_eq!(invs[0].count(&gfs), 4);
+        assert!(invs[1..1001]
+            .iter()
+            .all(|inv| inv.sounding_types().count() == 0));
+        assert_eq!(invs[1001].site().short_name(), "kbtm");
+        assert_eq!(invs[1001].count(&gfs), 2);
+
+        Ok(())
+    }
+
+    // Compare inventories_for with calling inventory for each site, only the index is used so the
+    // files are put straight into it. Run with
+    // `cargo test --release bench_inventories_for -- --ignored --nocapture` to see the times.
+    #[test]
+    #[ignore]
+    fn bench_inventories_for() -> Result<()> {
+        use std::time::Instant;
+
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "NAM",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let start = NaiveDate::from_ymd_opt(2017, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+
+        // 200 sites with a year of runs each, and a few missing.
+        let mut sites = vec![];
+        arch.db_conn.execute_batch("BEGIN")?;
+        for i in 0..200 {
+            let site = arch.validate_or_add_site(Site::new(&format!("s{:03}", i)))?;
+            for run in 0..(4 * 365) {
+                if run % 97 == i % 97 {
+                    continue;
+                }
+
+                let init_time = start + Duration::hours(6 * run as i64);
+                for sounding_type in &[&gfs, &nam] {
+                    arch.db_conn.execute(
+                        "
+                            INSERT INTO files
+                                (type_id, site_id, location_id, init_time, end_time, file_name)
+                            VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+                        ",
+                        &[
+                            &sounding_type.id(),
+                            &site.id(),
+                            &loc.id(),
+                            &init_time as &dyn ToSql,
+                            &arch.compressed_file_name(&site, sounding_type, &init_time),
+                        ],
+                    )?;
+                }
+            }
+            sites.push(site);
+        }
+        arch.db_conn.execute_batch("COMMIT")?;
+
+        let timer = Instant::now();
+        let naive: Vec<Inventory> = sites
+            .iter()
+            .map(|site| arch.inventory(site))
+            .collect::<Result<_>>()?;
+        let naive_time = timer.elapsed();
+
+        let timer = Instant::now();
+        let batched = arch.inventories_for(&sites)?;
+        let batched_time = timer.elapsed();
+
+        println!(
+            "inventory for each of {} sites: {:?}, inventories_for: {:?}",
+            sites.len(),
+            naive_time,
+            batched_time
+        );
+
+        assert_eq!(naive.len(), batched.len());
+        for (a, b) in naive.iter().zip(&batched) {
+            assert_eq!(a.site(), b.site());
+            for sounding_type in &[&gfs, &nam] {
+                assert_eq!(a.count(sounding_type), b.count(sounding_type));
+                assert_eq!(a.range(sounding_type), b.range(sounding_type));
+                assert_eq!(a.missing(sounding_type), b.missing(sounding_type));
+                assert_eq!(a.init_times(sounding_type), b.init_times(sounding_type));
+                assert_eq!(a.locations(sounding_type), b.locations(sounding_type));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inventory_no_files() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+
+        let inv = arch.inventory(&site)?;
+        assert_eq!(inv.sounding_types().count(), 0);
+        assert_eq!(inv.range(&gfs), None);
+        assert_eq!(inv.count(&gfs), 0);
+        assert!(inv.missing(&gfs).is_empty());
+        assert!(inv.locations(&gfs).is_empty());
+
+        assert_eq!(arch.earliest_valid_time(&site, &gfs)?, None);
+        assert_eq!(arch.coverage_runs(&site, &gfs)?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inventory_to_table_string() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        assert_eq!(
+            arch.inventory(&site)?.to_table_string(),
+            "Inventory for kmso\n\
+             Type | First | Last | Count | Missing\n\
+             -----+-------+------+-------+--------\n\
+             no soundings in the archive\n"
+        );
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        // Without an interval the missing spans are unknown.
+        arch.set_sounding_type_info(SoundingType::new_model("NAM", FileType::BUFKIT, None))?;
+
+        assert_eq!(
+            arch.inventory(&site)?.to_table_string(),
+            "Inventory for kmso\n\
+             Type | First            | Last             | Count | Missing\n\
+             -----+------------------+------------------+-------+--------\n\
+             GFS  | 2017-04-01 0000Z | 2017-04-01 1800Z | 4     | 0\n\
+             NAM  | 2017-04-01 0000Z | 2017-04-01 1800Z | 3     | n/a\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inventory_mobile_site() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("mobile1").set_mobile(true))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new(
+            "GFS",
+            false,
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let spot_a = arch.validate_or_add_location(Location::new(45.0, -112.0, 1500, None))?;
+        let spot_b = arch.validate_or_add_location(Location::new(47.0, -114.0, 900, None))?;
+
+        let mut gfs_files: Vec<_> = get_test_data()?
+            .into_iter()
+            .filter(|(_, st, _, _, _, _)| st.source() == "GFS")
+            .map(|(_, _, init_time, end_time, _, fname)| (init_time, end_time, fname))
+            .collect();
+        gfs_files.sort();
+        gfs_files.dedup_by_key(|(init_time, _, _)| *init_time);
+        assert_eq!(gfs_files.len(), 4);
+
+        for (i, (init_time, end_time, fname)) in gfs_files.iter().enumerate() {
+            let loc = if i < 2 { &spot_a } else { &spot_b };
+            arch.add_file(&site, &gfs, loc, init_time, end_time, fname)?;
+        }
+
+        let inv = arch.inventory(&site)?;
+        assert_eq!(inv.range(&gfs), Some((gfs_files[0].0, gfs_files[3].0)));
+        assert_eq!(inv.locations(&gfs).len(), 2);
+        assert_eq!(
+            inv.range_at_location(&gfs, &spot_a),
+            Some((gfs_files[0].0, gfs_files[1].0))
+        );
+        assert_eq!(
+            inv.range_at_location(&gfs, &spot_b),
+            Some((gfs_files[2].0, gfs_files[3].0))
+        );
+
+        // Not tracked for fixed sites.
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let (init_time, end_time, fname) = &gfs_files[0];
+        arch.add_file(&kmso, &gfs, &spot_a, init_time, end_time, fname)?;
+        let inv = arch.inventory(&kmso)?;
+        assert!(inv.range(&gfs).is_some());
+        assert_eq!(inv.range_at_location(&gfs, &spot_a), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inventory_has_interval() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // Store one of the files again as an observed type with no regular interval.
+        let (site, _, init_time, end_time, loc, file_name) = get_test_data()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| BufkitDataErr::NotEnoughData("no test data".to_owned()))?;
+        let site = arch.validate_site(site)?;
+        let loc = arch.validate_location(loc)?;
+        let special = arch.validate_or_add_sounding_type(SoundingType::new_observed(
+            "SPECIAL",
+            FileType::BUFKIT,
+            None,
+        ))?;
+        arch.add_file(&site, &special, &loc, &init_time, &end_time, &file_name)?;
+
+        let gfs = arch
+            .sounding_type_info("GFS")?
+            .expect("No such sounding type.");
+
+        let inv = arch.inventory(&site)?;
+
+        // Interval known, and no gaps in the data.
+        assert!(inv.has_interval(&gfs));
+        assert!(inv.missing(&gfs).is_empty());
+
+        // No interval, so the gaps are unknown.
+        assert!(!inv.has_interval(&special));
+        assert!(inv.missing(&special).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_uncompressed_size() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        let estimate = arch.estimated_uncompressed_size(3)?;
+        assert_eq!(estimate.bytes(), 0);
+        assert_eq!(estimate.confidence(), 1.0);
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let mut exact = 0;
+        for record in arch.iter_records() {
+            let record = record?;
+            exact += arch.uncompressed_size(record.file_name(), record.codec())?;
+        }
+
+        // Sampling every file gives the exact answer, up to rounding.
+        let estimate = arch.estimated_uncompressed_size(100)?;
+        assert!((estimate.bytes() as i64 - exact as i64).abs() <= 1);
+        assert_eq!(estimate.sampled_files(), 7);
+        assert_eq!(estimate.confidence(), 1.0);
+        assert!(estimate.missing_files().is_empty());
+
+        // A smaller sample should still be in the right neighborhood, but is less certain.
+        let estimate = arch.estimated_uncompressed_size(3)?;
+        let bytes = estimate.bytes() as f64;
+        assert!(bytes > 0.5 * exact as f64 && bytes < 2.0 * exact as f64);
+        assert_eq!(estimate.sampled_files(), 3);
+        assert!(estimate.confidence() > 0.0 && estimate.confidence() < 1.0);
+        assert_eq!(arch.estimated_uncompressed_size(1)?.confidence(), 0.0);
+
+        // A file missing from storage is skipped and reported.
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let (missing, codec) = arch.get_file_name_for(&kmso, &gfs, &init_time)?;
+        let missing_size = arch.uncompressed_size(&missing, codec)?;
+        remove_file(arch.file_dir.join(&missing))?;
+
+        let estimate = arch.estimated_uncompressed_size(100)?;
+        assert_eq!(estimate.missing_files(), &[missing][..]);
+        assert_eq!(estimate.sampled_files(), 6);
+        assert!((estimate.bytes() as i64 - (exact - missing_size) as i64).abs() <= 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_stats() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+
+        let mut uncompressed = 0;
+        for record in arch.iter_records() {
+            let record = record?;
+            if record.sounding_type() == &gfs {
+                uncompressed += arch.uncompressed_size(record.file_name(), record.codec())?;
+            }
+        }
+
+        let stats = arch.compression_stats(&kmso, &gfs)?;
+        assert_eq!(stats.file_count(), 4);
+        assert_eq!(stats.uncompressed_bytes(), uncompressed);
+        assert!(stats.compressed_bytes() < stats.uncompressed_bytes());
+        assert!(stats.ratio() > 1.0);
+
+        let empty = arch.validate_or_add_sounding_type(SoundingType::new(
+            "RAP",
+            false,
+            FileType::BUFKIT,
+            1,
+        ))?;
+        let stats = arch.compression_stats(&kmso, &empty)?;
+        assert_eq!(stats, CompressionStats::default());
+        assert_eq!(stats.ratio(), 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_level() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new(
+            "NAM",
+            false,
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let end_time = init_time + Duration::hours(84);
+        let data = std::fs::read("example_data/2017040112Z_nam_kmso.buf")?;
+
+        // The same file stored for two sites at different levels.
+        let fast = arch.validate_or_add_site(Site::new("fast"))?;
+        arch.set_compression_level(0);
+        arch.add_file_from_bytes(&fast, &nam, &loc, &init_time, &end_time, &data)?;
+
+        let small = arch.validate_or_add_site(Site::new("small"))?;
+        arch.set_compression_level(9);
+        arch.add_file_from_bytes(&small, &nam, &loc, &init_time, &end_time, &data)?;
+
+        let fast_stats = arch.compression_stats(&fast, &nam)?;
+        let small_stats = arch.compression_stats(&small, &nam)?;
+        assert!(fast_stats.compressed_bytes() >= data.len() as u64);
+        assert!(small_stats.compressed_bytes() < fast_stats.compressed_bytes());
+
+        // Both read back the same.
+        assert_eq!(
+            arch.retrieve(&fast, &nam, &init_time)?.len(),
+            arch.retrieve(&small, &nam, &init_time)?.len()
+        );
+        assert!(arch.verify()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_count() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let uncounted = |arch: &Archive| -> Result<i64> {
+            Ok(arch.db_conn.query_row(
+                "SELECT COUNT(*) FROM files WHERE profile_count IS NULL",
+                NO_PARAMS,
+                |row| row.get(0),
+            )?)
+        };
+
+        let mut expected = 0;
+        for record in arch.iter_records() {
+            let record = record?;
+            if record.sounding_type() == &nam {
+                expected += arch.retrieve(&kmso, &nam, &record.init_time())?.len() as u64;
+            }
+        }
+        assert!(expected > 3);
+
+        assert_eq!(uncounted(&arch)?, 7);
+        assert_eq!(arch.profile_count(&kmso, &nam)?, expected);
+        assert_eq!(uncounted(&arch)?, 4);
+        assert_eq!(arch.profile_count(&kmso, &nam)?, expected);
+
+        assert_eq!(arch.backfill_profile_counts()?, 4);
+        assert_eq!(uncounted(&arch)?, 0);
+        assert_eq!(arch.backfill_profile_counts()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_coverage() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        let comparison = arch.compare_coverage(&site, &gfs, &nam)?;
+        assert_eq!(comparison.in_both(), &[hour(0), hour(12), hour(18)]);
+        assert_eq!(comparison.only_a(), &[hour(6)]);
+        assert!(comparison.only_b().is_empty());
+
+        let reversed = arch.compare_coverage(&site, &nam, &gfs)?;
+        assert_eq!(reversed.in_both(), comparison.in_both());
+        assert_eq!(reversed.only_b(), comparison.only_a());
+        assert!(reversed.only_a().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_between() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
+
+        // The gap in the middle and the runs after the last file.
+        assert_eq!(
+            arch.missing_between(&kmso, &nam, &hour(0), &hour(30))?,
+            vec![hour(6), hour(24), hour(30)]
+        );
+
+        // The window is rounded in to whole steps.
+        assert_eq!(
+            arch.missing_between(&kmso, &nam, &hour(1), &hour(29))?,
+            vec![hour(6), hour(24)]
+        );
+        assert!(arch
+            .missing_between(&kmso, &nam, &hour(12), &hour(18))?
+            .is_empty());
+
+        // No interval, so no expected times.
+        let special = arch.validate_or_add_sounding_type(SoundingType::new_observed(
+            "SPECIAL",
+            FileType::BUFKIT,
+            None,
+        ))?;
+        assert!(arch
+            .missing_between(&kmso, &special, &hour(0), &hour(30))?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage_runs() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        assert_eq!(arch.coverage_runs(&site, &gfs)?, vec![(hour(0), hour(18))]);
+        assert_eq!(
+            arch.coverage_runs(&site, &nam)?,
+            vec![(hour(0), hour(0)), (hour(12), hour(18))]
+        );
+
+        // The runs are the complement of the gaps in the inventory.
+        let inv = arch.inventory(&site)?;
+        assert_eq!(inv.missing(&nam), &[(hour(6), hour(6))]);
+
+        // Without an interval every time is its own run.
+        let nam =
+            arch.set_sounding_type_info(SoundingType::new_model("NAM", FileType::BUFKIT, None))?;
+        assert_eq!(
+            arch.coverage_runs(&site, &nam)?,
+            vec![
+                (hour(0), hour(0)),
+                (hour(12), hour(12)),
+                (hour(18), hour(18))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_infer_interval() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // GFS is 00/06/12/18, NAM is 00/12/18 so 12 and 6 tie and the shorter wins.
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        assert_eq!(arch.infer_interval(&gfs)?, Some(6));
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        assert_eq!(arch.infer_interval(&nam)?, Some(6));
+
+        // A single run at each of two sites isn't enough.
+        let wrf = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "WRF",
+            FileType::BUFKIT,
+            None,
+        ))?;
+        assert_eq!(arch.infer_interval(&wrf)?, None);
+
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        for (site, h) in &[("kmso", 0), ("kbtm", 12)] {
+            let site = arch.validate_or_add_site(Site::new(site))?;
+            arch.add_file(
+                &site,
+                &wrf,
+                &loc,
+                &hour(*h),
+                &hour(*h),
+                "example_data/2017040112Z_nam_kmso.buf",
+            )?;
+        }
+        assert_eq!(arch.infer_interval(&wrf)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_on() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let day = |d| NaiveDate::from_ymd_opt(2017, 4, d).unwrap();
+
+        // Only a GFS run on the 2nd, right at midnight.
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let midnight = day(2).and_hms_opt(0, 0, 0).unwrap();
+        arch.add_file(
+            &kmso,
+            &gfs,
+            &loc,
+            &midnight,
+            &midnight,
+            "example_data/2017040106Z_gfs_kmso.buf",
+        )?;
+
+        assert!(arch.has_data_on(&kmso, day(1))?);
+        assert!(arch.has_data_on(&kmso, day(2))?);
+        assert!(!arch.has_data_on(&kmso, day(3))?);
+
+        let sources = |date| -> Result<Vec<String>> {
+            Ok(arch
+                .types_with_data_on(&kmso, date)?
+                .into_iter()
+                .map(|st| st.source().to_owned())
+                .collect())
+        };
+        assert_eq!(sources(day(1))?, vec!["GFS", "NAM"]);
+        assert_eq!(sources(day(2))?, vec!["GFS"]);
+        assert!(sources(day(3))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_init_times() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        // A second site with only some of the GFS runs.
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1686, None))?;
+        for h in &[18, 6] {
+            arch.add_file(
+                &kbtm,
+                &gfs,
+                &loc,
+                &hour(*h),
+                &hour(*h),
+                "example_data/2017040106Z_gfs_kmso.buf",
+            )?;
+        }
+
+        assert_eq!(
+            arch.common_init_times(&gfs, &[kmso.clone(), kbtm.clone()])?,
+            vec![hour(6), hour(18)]
+        );
+        assert_eq!(arch.common_init_times(&gfs, &[])?, vec![hour(6), hour(18)]);
+        assert_eq!(
+            arch.common_init_times(&gfs, &[kmso])?,
+            vec![hour(0), hour(6), hour(12), hour(18)]
+        );
+
+        // Nothing in common with a site without any GFS runs.
+        let kord = arch.validate_or_add_site(Site::new("kord"))?;
+        assert!(arch.common_init_times(&gfs, &[kbtm, kord])?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_density() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        assert!(arch.data_density(&site)?.is_empty());
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // All the example data is from a single day.
+        let density: Vec<(String, f64)> = arch
+            .data_density(&site)?
+            .into_iter()
+            .map(|(st, density)| (st.source().to_owned(), density))
+            .collect();
+        assert_eq!(
+            density,
+            vec![("GFS".to_owned(), 4.0), ("NAM".to_owned(), 3.0)]
+        );
+
+        // Add a run two days later to spread the GFS over three days.
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 3)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        arch.add_file(
+            &site,
+            &gfs,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040100Z_gfs_kmso.buf",
+        )?;
+
+        let (_, gfs_density) = arch.data_density(&site)?.remove(0);
+        assert!((gfs_density - 5.0 / 3.0).abs() < 1.0e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_most_recent_init_time() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = dbg!(arch.site_info("kmso"))?.unwrap();
+        let sounding_type = dbg!(arch.sounding_type_info("GFS"))?.unwrap();
+        let most_recent = dbg!(arch.most_recent_init_time(&site, &sounding_type))?;
+
+        let most_recent_should_be = NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0);
+        assert_eq!(most_recent, most_recent_should_be);
+
+        let sounding_type = dbg!(arch.sounding_type_info("NAM"))?.unwrap();
+        let most_recent = dbg!(arch.most_recent_init_time(&site, &sounding_type))?;
+
+        assert_eq!(most_recent, most_recent_should_be);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_earliest_valid_time() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let site = arch.site_info("kmso")?.unwrap();
+        let earliest_should_be = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+
+        for source in &["GFS", "NAM"] {
+            let sounding_type = arch.sounding_type_info(source)?.unwrap();
+            let earliest = arch.earliest_valid_time(&site, &sounding_type)?;
+            assert_eq!(earliest, Some(earliest_should_be));
+        }
+
+        let sref = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "SREF",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        assert_eq!(arch.earliest_valid_time(&site, &sref)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_exists() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let snd_type = arch.sounding_type_info("GFS")?.unwrap();
+
+        println!("Checking for files that should exist.");
+        assert!(arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2017, 4, 1).and_hms(6, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2017, 4, 1).and_hms(12, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0)
+            )
+            .expect("Error checking for existence"));
+
+        println!("Checking for files that should NOT exist.");
+        assert!(!arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2018, 4, 1).and_hms(0, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(!arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2018, 4, 1).and_hms(6, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(!arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2018, 4, 1).and_hms(12, 0, 0)
+            )
+            .expect("Error checking for existence"));
+        assert!(!arch
+            .file_exists(
+                &kmso,
+                &snd_type,
+                &NaiveDate::from_ymd(2018, 4, 1).and_hms(18, 0, 0)
+            )
+            .expect("Error checking for existence"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_time_match() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let off_by_a_minute = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 1, 0)
+            .unwrap();
+
+        // Exact matching is the default.
+        assert!(!arch.file_exists(&kmso, &gfs, &off_by_a_minute)?);
+        assert!(arch.retrieve(&kmso, &gfs, &off_by_a_minute).is_err());
+
+        arch.set_init_time_match(InitTimeMatch::Within(Duration::minutes(30)));
+        assert!(arch.file_exists(&kmso, &gfs, &off_by_a_minute)?);
+        let anal = arch.retrieve(&kmso, &gfs, &off_by_a_minute)?;
+        #[allow(deprecated)]
+        let valid_time = anal[0].sounding().valid_time();
+        assert_eq!(valid_time, Some(off_by_a_minute - Duration::minutes(1)));
+
+        // Outside the tolerance is still a miss.
+        let off_by_hours = off_by_a_minute + Duration::hours(2);
+        assert!(!arch.file_exists(&kmso, &gfs, &off_by_hours)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count() {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        // 7 and not 10 because of duplicate GFS models in the input.
+        assert_eq!(arch.count().expect("db error"), 7);
+    }
+
+    #[test]
+    fn test_count_for() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+
+        assert_eq!(arch.count_for(&kmso, &gfs)?, 4);
+        assert_eq!(arch.count_for(&kmso, &nam)?, 3);
+        assert_eq!(arch.count_for(&kbtm, &gfs)?, 0);
+
+        assert_eq!(arch.counts_by_type(&kmso)?, vec![(gfs, 4), (nam, 3)]);
+        assert!(arch.counts_by_type(&kbtm)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        assert_eq!(arch.stats()?, ArchiveStats::default());
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let stats = arch.stats()?;
+        assert_eq!(stats.file_count() as i64, arch.count()?);
+        assert_eq!(stats.site_count(), 1);
+        assert_eq!(stats.sounding_type_count(), arch.sounding_types()?.len());
+        assert_eq!(stats.location_count(), arch.all_locations()?.len());
+
+        let mut stored_bytes = 0;
+        for entry in read_dir(&arch.file_dir)? {
+            stored_bytes += entry?.metadata()?.len();
+        }
+        assert_eq!(stats.stored_bytes(), stored_bytes);
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        assert_eq!(stats.init_time_range(), Some((hour(0), hour(18))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_records() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let records: Vec<FileRecord> = arch.iter_records().collect::<Result<_>>()?;
+        assert_eq!(records.len(), 7);
+
+        for record in records {
+            assert_eq!(record.site().short_name(), "kmso");
+            assert!(record.site().is_valid());
+            assert!(record.sounding_type().is_valid());
+            assert!(record.location().is_valid());
+            assert_eq!(record.codec(), Codec::Gzip);
+            assert!(arch.file_exists(
+                record.site(),
+                record.sounding_type(),
+                &record.init_time()
+            )?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_for_file() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let record = arch
+            .record_for_file("2017-04-01T1200Z_NAM_BUFKIT_kmso.gz")?
+            .expect("File not found.");
+        assert_eq!(record.site().short_name(), "kmso");
+        assert_eq!(record.sounding_type().source(), "NAM");
+        assert_eq!(
+            record.init_time(),
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        );
+        assert_eq!(record.location().elevation(), 1335);
+
+        assert!(arch.record_for_file("not_in_the_index.gz")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_blobs() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        assert!(arch.duplicate_blobs()?.is_empty());
+
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+
+        // The same file under another site by mistake.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1686, None))?;
+        arch.add_file(
+            &kbtm,
+            &nam,
+            &loc,
+            &init_time,
+            &(init_time + chrono::Duration::hours(84)),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+
+        let groups = arch.duplicate_blobs()?;
+        assert_eq!(groups.len(), 1);
+        let sites: Vec<&str> = groups[0].iter().map(|r| r.site().short_name()).collect();
+        assert_eq!(sites, vec!["kbtm", "kmso"]);
+        assert!(groups[0].iter().all(|r| r.init_time() == init_time));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_query() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        // Another site in a different state.
+        let kmso = arch.set_site_info(
+            arch.site_info("kmso")?
+                .unwrap()
+                .with_state_prov(StateProv::MT),
+        )?;
+        let kboi = arch.validate_or_add_site(Site::new("kboi").with_state_prov(StateProv::ID))?;
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(43.57, -116.22, 874, None))?;
+        arch.add_file(
+            &kboi,
+            &nam,
+            &loc,
+            &hour(12),
+            &(hour(12) + chrono::Duration::hours(84)),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+
+        let keys = |records: Vec<FileRecord>| -> Vec<(String, String, NaiveDateTime)> {
+            records
+                .iter()
+                .map(|r| {
+                    (
+                        r.site().short_name().to_owned(),
+                        r.sounding_type().source().to_owned(),
+                        r.init_time(),
+                    )
+                })
+                .collect()
+        };
+
+        // No filters is everything.
+        assert_eq!(arch.query().execute()?.len() as i64, arch.count()?);
+
+        // A single filter.
+        let mut by_type = arch
+            .iter_records()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|r| r.sounding_type() == &gfs)
+            .collect::<Vec<_>>();
+        by_type.sort_by_key(|r| r.init_time());
+        assert_eq!(
+            keys(arch.query().sounding_type("gfs").execute()?),
+            keys(by_type)
+        );
+
+        // The same selection as files_between, which doesn't sort by the sounding type.
+        let mut between = keys(arch.files_between(&hour(6), &hour(12))?);
+        between.sort_by(|a, b| (a.2, &a.0, &a.1).cmp(&(b.2, &b.0, &b.1)));
+        assert_eq!(
+            keys(arch.query().between(hour(6), hour(12)).execute()?),
+            between
+        );
+
+        // Combinations.
+        assert_eq!(
+            keys(arch.query().state(StateProv::ID).execute()?),
+            vec![("kboi".to_owned(), "NAM".to_owned(), hour(12))]
+        );
+        assert_eq!(
+            keys(
+                arch.query()
+                    .site(kmso.short_name())
+                    .sounding_type("NAM")
+                    .between(hour(6), hour(18))
+                    .state(StateProv::MT)
+                    .execute()?
+            ),
+            vec![
+                ("kmso".to_owned(), "NAM".to_owned(), hour(12)),
+                ("kmso".to_owned(), "NAM".to_owned(), hour(18)),
+            ]
+        );
+        assert!(arch
+            .query()
+            .site("kboi")
+            .state(StateProv::MT)
+            .execute()?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_between() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        // Another site, to check the ordering.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1686, None))?;
+        arch.add_file(
+            &kbtm,
+            &nam,
+            &loc,
+            &hour(12),
+            &(hour(12) + chrono::Duration::hours(84)),
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+
+        let records = arch.files_between(&hour(6), &hour(12))?;
+        let found: Vec<(NaiveDateTime, &str)> = records
+            .iter()
+            .map(|r| (r.init_time(), r.site().short_name()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (hour(6), "kmso"),
+                (hour(12), "kbtm"),
+                (hour(12), "kmso"),
+                (hour(12), "kmso"),
+            ]
+        );
+        assert_eq!(records[0].sounding_type().source(), "GFS");
+        assert_eq!(records[1].location(), &loc);
+
+        assert!(arch.files_between(&hour(19), &hour(23))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_files_csv() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        format!(
-            "{}_{}_{}_{}.gz",
-            file_string,
-            sounding_type.source(),
-            sounding_type.file_type().as_static(),
-            site.short_name(),
-        )
-        .into()
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let mut buf: Vec<u8> = vec![];
+        assert_eq!(arch.export_files_csv(&mut buf)?, 7);
+
+        let csv = std::str::from_utf8(&buf)?;
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("short_name,source,init_time,latitude,longitude,elevation,file_name")
+        );
+
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 7);
+        assert!(rows.iter().all(|row| row.len() == 7 && row[0] == "kmso"));
+        assert!(rows
+            .iter()
+            .any(|row| row[1] == "GFS" && row[2] == "2017-04-01T18:00:00" && row[5] == "972"));
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+        Ok(())
     }
 
-    /// Remove a file from the archive.
-    pub fn remove(
-        &self,
-        site: &Site,
-        sounding_type: &SoundingType,
-        init_time: &NaiveDateTime,
-    ) -> Result<()> {
-        let file_name: String = self.db_conn.query_row(
-            "SELECT file_name FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
-            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
-            |row| row.get(0),
-        )?;
+    // ---------------------------------------------------------------------------------------------
+    // Add, remove, and retrieve files from the archive
+    // ---------------------------------------------------------------------------------------------
+    #[test]
+    fn test_files_round_trip() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
 
-        remove_file(self.file_dir.join(file_name)).map_err(BufkitDataErr::Io)?;
+        let test_data = get_test_data().expect("Error loading test data.");
 
-        self.db_conn.execute(
-            "DELETE FROM files WHERE site_id = ?1 AND type_id = ?2 AND init_time = ?3",
-            &[&site.id(), &sounding_type.id(), init_time as &ToSql],
-        )?;
+        for (site, sounding_type, init_time, end_time, loc, file_name) in test_data {
+            let site = arch.validate_or_add_site(site)?;
+            let sounding_type = arch.validate_or_add_sounding_type(sounding_type)?;
+            let loc = arch.validate_or_add_location(loc)?;
+
+            arch.add_file(
+                &site,
+                &sounding_type.clone(),
+                &loc,
+                &init_time,
+                &end_time,
+                &file_name,
+            )
+            .expect("Failure to add.");
+
+            let site = arch
+                .site_info(site.short_name())
+                .expect("Error retrieving site.")
+                .expect("Site not in index.");
+            let sounding_type = arch
+                .sounding_type_info(sounding_type.source())
+                .expect("Error retrieving sounding_type")
+                .expect("Sounding type not in index.");
 
+            let recovered_anal = arch
+                .retrieve(&site, &sounding_type, &init_time)
+                .expect("Failure to load.");
+
+            assert_eq!(
+                recovered_anal[0].sounding().valid_time().unwrap(),
+                init_time
+            );
+        }
         Ok(())
     }
-}
 
-/*--------------------------------------------------------------------------------------------------
-                                          Unit Tests
---------------------------------------------------------------------------------------------------*/
-#[cfg(test)]
-mod unit {
-    use super::*;
-    use crate::{FileType, Location, StateProv};
-    use chrono::NaiveDate;
-    use metfor::Quantity;
-    use sounding_bufkit::BufkitFile;
-    use std::fs::read_dir;
-    use tempdir::TempDir;
+    #[test]
+    fn test_on_conflict() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-    // struct to hold temporary data for tests.
-    struct TestArchive {
-        tmp: TempDir,
-        arch: Archive,
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "NAM",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let original = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let moved = arch.validate_or_add_location(Location::new(46.88, -114.17, 1330, None))?;
+
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let end_time = init_time + chrono::Duration::hours(84);
+        let file_name = "example_data/2017040112Z_nam_kmso.buf";
+
+        let add = |arch: &Archive, loc: &Location| {
+            arch.add_file(&site, &nam, loc, &init_time, &end_time, file_name)
+        };
+        let location_of_file = |arch: &Archive| -> Result<Location> {
+            let record = arch.iter_records().next().unwrap()?;
+            Ok(*record.location())
+        };
+
+        add(&arch, &original)?;
+
+        arch.set_on_conflict(OnConflict::Reject);
+        match add(&arch, &moved) {
+            Err(BufkitDataErr::LocationConflict(existing, new)) => {
+                assert_eq!(existing, original);
+                assert_eq!(new, moved);
+            }
+            other => panic!("unexpected result adding: {:?}", other),
+        }
+        assert_eq!(location_of_file(&arch)?, original);
+        // Adding again at the same location is not a conflict.
+        add(&arch, &original)?;
+
+        arch.set_on_conflict(OnConflict::KeepExisting);
+        add(&arch, &moved)?;
+        assert_eq!(location_of_file(&arch)?, original);
+
+        arch.set_on_conflict(OnConflict::Replace);
+        add(&arch, &moved)?;
+        assert_eq!(location_of_file(&arch)?, moved);
+        assert_eq!(arch.count()?, 1);
+
+        // A policy for a single file overrides the archive's.
+        let add_with = |arch: &Archive, loc: &Location, on_conflict| {
+            arch.add_file_with_policy(
+                &site,
+                &nam,
+                loc,
+                &init_time,
+                &end_time,
+                file_name,
+                on_conflict,
+            )
+        };
+        assert!(add_with(&arch, &original, OnConflict::Reject).is_err());
+        add_with(&arch, &original, OnConflict::KeepExisting)?;
+        assert_eq!(location_of_file(&arch)?, moved);
+
+        arch.set_on_conflict(OnConflict::Reject);
+        add_with(&arch, &original, OnConflict::Replace)?;
+        assert_eq!(location_of_file(&arch)?, original);
+        assert!(add(&arch, &moved).is_err());
+
+        Ok(())
     }
 
-    // Function to create a new archive to test.
-    fn create_test_archive() -> Result<TestArchive> {
-        let tmp = TempDir::new("bufkit-data-test-archive")?;
-        let arch = Archive::create(tmp.path())?;
+    #[test]
+    fn test_add_file_from_reader() -> Result<()> {
+        // Like a dropped connection.
+        struct Disconnected;
 
-        Ok(TestArchive { tmp, arch })
+        impl Read for Disconnected {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("connection reset"))
+            }
+        }
+
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new(
+            "GFS",
+            false,
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        let end_time = init_time + Duration::hours(180);
+
+        let src = File::open("example_data/2017040106Z_gfs_kmso.buf")?;
+        arch.add_file_from_reader(&site, &gfs, &loc, &init_time, &end_time, src)?;
+
+        // Stored just the same as add_file would.
+        let record = arch.iter_records().next().unwrap()?;
+        assert_eq!(record.file_name(), "2017-04-01T0600Z_GFS_BUFKIT_kmso.gz");
+        assert_eq!(record.end_time(), end_time);
+        assert!(!arch.retrieve(&site, &gfs, &init_time)?.is_empty());
+        assert!(arch.verify()?.is_empty());
+
+        // A download cut off part way through doesn't leave a partial file or an index entry.
+        let cut_off = File::open("example_data/2017040118Z_gfs_kmso.buf")?
+            .take(1_000)
+            .chain(Disconnected);
+        let later = init_time + Duration::hours(12);
+        assert!(arch
+            .add_file_from_reader(&site, &gfs, &loc, &later, &later, cut_off)
+            .is_err());
+        assert!(!arch.file_exists(&site, &gfs, &later)?);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        // If the index can't be updated, a new file isn't left in storage, and a file it would have
+        // replaced is untouched.
+        arch.db_conn.execute_batch(
+            "
+                CREATE TEMP TRIGGER index_full BEFORE INSERT ON files
+                BEGIN
+                    SELECT RAISE(ABORT, 'database or disk is full');
+                END;
+            ",
+        )?;
+        let src = File::open("example_data/2017040118Z_gfs_kmso.buf")?;
+        assert!(arch
+            .add_file_from_reader(&site, &gfs, &loc, &later, &later, src)
+            .is_err());
+        assert!(!arch.file_exists(&site, &gfs, &later)?);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        let src = File::open("example_data/2017040118Z_gfs_kmso.buf")?;
+        assert!(arch
+            .add_file_from_reader(&site, &gfs, &loc, &init_time, &later, src)
+            .is_err());
+        assert_eq!(arch.iter_records().next().unwrap()?.end_time(), end_time);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert!(arch.verify()?.is_empty());
+
+        Ok(())
     }
 
-    // Function to fetch a list of test files.
-    fn get_test_data() -> Result<
-        Vec<(
-            Site,
-            SoundingType,
-            NaiveDateTime,
-            NaiveDateTime,
-            Location,
-            String,
-        )>,
-    > {
-        let path = PathBuf::new().join("example_data");
+    #[test]
+    fn test_add_file_from_bytes() -> Result<()> {
+        let TestArchive { tmp: _tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
 
-        let files = read_dir(path)?
-            .filter_map(|entry| entry.ok())
-            .filter_map(|entry| {
-                entry.file_type().ok().and_then(|ft| {
-                    if ft.is_file() {
-                        Some(entry.path())
-                    } else {
-                        None
-                    }
-                })
-            });
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new(
+            "NAM",
+            false,
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let end_time = init_time + Duration::hours(84);
 
-        let mut to_return = vec![];
+        let data = std::fs::read_to_string("example_data/2017040112Z_nam_kmso.buf")?;
+        arch.add_file_from_bytes(&site, &nam, &loc, &init_time, &end_time, data.as_bytes())?;
 
-        for path in files {
-            //
-            // FIXME: handle multiple file types, like BUFR and whatever else types we want to work
-            //
-            let bufkit_file = BufkitFile::load(&path)?;
-            let bufkit_data = bufkit_file.data()?;
-            let mut bufkit_iter = bufkit_data.into_iter();
-            let anal = bufkit_iter
-                .by_ref()
-                .nth(0)
-                .ok_or(BufkitDataErr::NotEnoughData)?;
-            let snd = anal.sounding();
+        assert!(arch.file_exists(&site, &nam, &init_time)?);
+        let mut exported = vec![];
+        arch.export(&site, &nam, &init_time)?
+            .read_to_end(&mut exported)?;
+        assert_eq!(exported, data.as_bytes());
+        assert!(arch.verify()?.is_empty());
 
-            let model = if path.to_string_lossy().to_string().contains("gfs") {
-                SoundingType::new("GFS", false, FileType::BUFKIT, 6)
-            } else {
-                SoundingType::new("NAM", false, FileType::BUFKIT, 6)
-            };
-            let site = if path.to_string_lossy().to_string().contains("kmso") {
-                Site::new("kmso")
-            } else {
-                panic!("Unprepared for this test data!");
-            };
+        Ok(())
+    }
 
-            let init_time = snd.valid_time().expect("NO VALID TIME?!");
+    #[test]
+    fn test_add_multi_station_file() -> Result<()> {
+        let TestArchive { tmp, arch } =
+            create_test_archive().expect("Failed to create test archive.");
 
-            let (lat, lon) = snd.station_info().location().unwrap();
-            let elev_m = snd.station_info().elevation().unwrap().unpack();
-            let loc = Location::new(lat, lon, elev_m as i32, None);
+        // Bundle the KMSO file with a copy made to look like a second station.
+        let kmso = std::fs::read_to_string("example_data/2017040112Z_nam_kmso.buf")?;
+        let kgpi = kmso
+            .replace("STID = KMSO STNM = 727730", "STID = KGPI STNM = 727790")
+            .replace(
+                "SLAT = 46.87 SLON = -114.16 SELV = 1335.0",
+                "SLAT = 48.30 SLON = -114.26 SELV = 900.0",
+            );
+        let bundle = tmp.path().join("bundle.buf");
+        std::fs::write(&bundle, format!("{}\n{}", kmso, kgpi))?;
+
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "NAM",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
 
-            let anal = bufkit_iter.last().ok_or(BufkitDataErr::NotEnoughData)?;
-            let snd = anal.sounding();
-            let end_time = snd.valid_time().expect("NO VALID TIME FOR THE LAST ONE!?");
+        let sites = arch.add_multi_station_file(
+            &nam,
+            &init_time,
+            &bundle.to_string_lossy(),
+            |station_num| match station_num {
+                727730 => Ok(Site::new("kmso")),
+                727790 => Ok(Site::new("kgpi")),
+                _ => Err(BufkitDataErr::NotEnoughData(format!(
+                    "unexpected station {}",
+                    station_num
+                ))),
+            },
+        )?;
 
-            to_return.push((
-                site.to_owned(),
-                model,
-                init_time,
-                end_time,
-                loc,
-                path.to_string_lossy().to_string(),
-            ))
+        let names: Vec<&str> = sites.iter().map(|s| s.short_name()).collect();
+        assert_eq!(names, vec!["kmso", "kgpi"]);
+        assert_eq!(arch.count()?, 2);
+
+        for site in &sites {
+            let inv = arch.inventory(site)?;
+            assert_eq!(inv.range(&nam), Some((init_time, init_time)));
+            assert!(!arch.retrieve(site, &nam, &init_time)?.is_empty());
         }
 
-        Ok(to_return)
+        let kgpi_locations = arch.locations_for_site_and_type(&sites[1], &nam)?;
+        assert_eq!(kgpi_locations.len(), 1);
+        assert_eq!(kgpi_locations[0].latitude(), 48.30);
+        assert_eq!(kgpi_locations[0].elevation(), 900);
+
+        Ok(())
     }
 
-    // Function to fill the archive with some example data.
-    fn fill_test_archive(arch: &mut Archive) -> Result<()> {
+    #[test]
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn test_files_round_trip_uncompressed() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        arch.set_codec(Codec::None);
+
         let test_data = get_test_data().expect("Error loading test data.");
 
         for (site, sounding_type, init_time, end_time, loc, file_name) in test_data {
             let site = arch.validate_or_add_site(site)?;
             let sounding_type = arch.validate_or_add_sounding_type(sounding_type)?;
             let loc = arch.validate_or_add_location(loc)?;
+
             arch.add_file(
                 &site,
-                &sounding_type.clone(),
+                &sounding_type,
                 &loc,
                 &init_time,
                 &end_time,
                 &file_name,
             )?;
+
+            let (stored_name, codec) = arch.get_file_name_for(&site, &sounding_type, &init_time)?;
+            assert_eq!(codec, Codec::None);
+            assert!(!stored_name.ends_with(".gz"));
+
+            // The stored file is just a copy of the original
+            let original = std::fs::read(&file_name)?;
+            let stored = std::fs::read(arch.file_dir.join(&stored_name))?;
+            assert_eq!(original, stored);
+
+            let recovered_anal = arch.retrieve(&site, &sounding_type, &init_time)?;
+            assert_eq!(
+                recovered_anal[0].sounding().valid_time().unwrap(),
+                init_time
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_file_again_after_codec_change() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let num_files = arch.count()?;
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let (gzip_name, _) = arch.get_file_name_for(&kmso, &gfs, &init_time)?;
+
+        arch.set_codec(Codec::None);
+        arch.add_file(
+            &kmso,
+            &gfs,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040100Z_gfs_kmso.buf",
+        )?;
+
+        let (plain_name, codec) = arch.get_file_name_for(&kmso, &gfs, &init_time)?;
+        assert_eq!(codec, Codec::None);
+        assert_ne!(plain_name, gzip_name);
+        assert!(!arch.file_dir.join(&gzip_name).exists());
+        assert!(arch.file_dir.join(&plain_name).exists());
+        assert_eq!(arch.count()?, num_files);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert!(!arch.retrieve(&kmso, &gfs, &init_time)?.is_empty());
+
+        // Failing to remove the old file doesn't fail the add, it is just left over.
+        #[derive(Debug)]
+        struct Undeletable(FileSystemBlobStore);
+
+        impl BlobStore for Undeletable {
+            fn put(&self, name: &str, data: &mut dyn Read) -> Result<()> {
+                self.0.put(name, data)
+            }
+
+            fn get(&self, name: &str) -> Result<Box<dyn Read>> {
+                self.0.get(name)
+            }
+
+            fn delete(&self, _name: &str) -> Result<()> {
+                Err(std::io::Error::other("permission denied").into())
+            }
+
+            fn list(&self) -> Result<Vec<String>> {
+                self.0.list()
+            }
+        }
+
+        arch.set_blob_store(Box::new(Undeletable(FileSystemBlobStore::new(
+            &arch.file_dir,
+        ))));
+        arch.set_codec(Codec::Gzip);
+        arch.add_file(
+            &kmso,
+            &gfs,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040100Z_gfs_kmso.buf",
+        )?;
+        assert_eq!(
+            arch.get_file_name_for(&kmso, &gfs, &init_time)?.0,
+            gzip_name
+        );
+        assert_eq!(arch.check()?, (vec![], vec![plain_name]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_decoder() -> Result<()> {
+        use std::{cell::Cell, rc::Rc};
+
+        // Pretend BUFKIT text is another format, and count the calls.
+        struct CountingDecoder(Rc<Cell<usize>>);
+
+        impl SoundingDecoder for CountingDecoder {
+            fn decode(&self, bytes: &[u8], description: &str) -> Result<Vec<Analysis>> {
+                self.0.set(self.0.get() + 1);
+                let text = std::str::from_utf8(bytes)?;
+                Ok(sounding_bufkit::BufkitData::init(text, description)?
+                    .into_iter()
+                    .collect())
+            }
         }
 
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let custom = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "CUSTOM",
+            FileType::BUFR,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        arch.add_file(
+            &site,
+            &custom,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+
+        // Without a BUFR decoder, it falls back to decoding the contents as BUFKIT.
+        assert!(!arch.retrieve(&site, &custom, &init_time)?.is_empty());
+
+        let calls = Rc::new(Cell::new(0));
+        arch.register_decoder(FileType::BUFR, Box::new(CountingDecoder(calls.clone())));
+
+        let anals = arch.retrieve(&site, &custom, &init_time)?;
+        assert!(!anals.is_empty());
+        assert_eq!(calls.get(), 1);
+
         Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Connecting, creating, and maintaining the archive.
-    // ---------------------------------------------------------------------------------------------
     #[test]
-    fn test_archive_create_new() {
-        assert!(create_test_archive().is_ok());
-    }
+    fn test_decode_falls_back_to_detected_file_type() -> Result<()> {
+        // Stands in for a real BUFR decoder, which can't make sense of BUFKIT text.
+        struct BufrDecoder;
+
+        impl SoundingDecoder for BufrDecoder {
+            fn decode(&self, _bytes: &[u8], description: &str) -> Result<Vec<Analysis>> {
+                Err(BufkitDataErr::NotEnoughData(format!(
+                    "no BUFR messages in {}",
+                    description
+                )))
+            }
+        }
 
-    #[test]
-    fn test_archive_connect() {
-        let TestArchive { tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
-        drop(arch);
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+        arch.register_decoder(FileType::BUFR, Box::new(BufrDecoder));
+
+        // A BUFKIT file tagged as BUFR.
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let mislabeled =
+            arch.validate_or_add_sounding_type(SoundingType::new_model("NAM", FileType::BUFR, 6))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        arch.add_file(
+            &site,
+            &mislabeled,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
+        assert!(!arch.retrieve(&site, &mislabeled, &init_time)?.is_empty());
 
-        assert!(Archive::connect(tmp.path()).is_ok());
-        assert!(Archive::connect("unlikely_directory_in_my_project").is_err());
+        // A BUFR file tagged as BUFKIT, when the BUFR decoder fails too.
+        match arch.decode_data(b"BUFR\x00\x01", "test", FileType::BUFKIT) {
+            Err(BufkitDataErr::WrongFileType(FileType::BUFKIT, FileType::BUFR)) => {}
+            other => panic!("unexpected result decoding: {:?}", other.map(|a| a.len())),
+        }
+
+        // Contents that don't look like anything keep the original error.
+        assert!(arch
+            .decode_data(b"garbage", "test", FileType::BUFKIT)
+            .is_err());
+
+        Ok(())
     }
 
     #[test]
-    fn test_check() -> Result<()> {
-        let TestArchive { tmp, mut arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_remove_sounding_type() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
         fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let site = arch.site_info("kmso")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let num_files = arch.count()?;
+        let num_nam = arch.count_for(&site, &nam)?;
+
+        assert_eq!(arch.remove_sounding_type(&nam)? as i64, num_nam);
+        assert!(arch.sounding_type_info("NAM")?.is_none());
+        assert!(arch.sounding_type_info("GFS")?.is_some());
+        assert_eq!(arch.count()?, num_files - num_nam);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        assert!(matches!(
+            arch.remove_sounding_type(&nam),
+            Err(BufkitDataErr::InvalidSoundingType(_))
+        ));
 
-        // Rename all files with "NAM" in them
-        let files_dir = tmp.path().join("files");
-        std::fs::read_dir(files_dir)?
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_name().to_string_lossy().contains("NAM"))
-            .for_each(|entry| {
-                let mut fname = entry.path().to_string_lossy().to_string();
-                let start = fname.find("NAM").unwrap();
-                let end = start + 3;
-                fname.replace_range(start..end, "NAMM");
-                std::fs::rename(entry.path(), fname).unwrap();
-            });
+        Ok(())
+    }
 
-        let (missing_files, extra_files) = dbg!(arch.check().unwrap());
+    #[test]
+    fn test_set_sounding_type_file_type() -> Result<()> {
+        // Stands in for a real BUFR decoder, which can't make sense of BUFKIT text.
+        struct BufrDecoder;
+
+        impl SoundingDecoder for BufrDecoder {
+            fn decode(&self, _bytes: &[u8], description: &str) -> Result<Vec<Analysis>> {
+                Err(BufkitDataErr::NotEnoughData(format!(
+                    "no BUFR messages in {}",
+                    description
+                )))
+            }
+        }
 
-        assert_eq!(missing_files.len(), 3);
-        assert_eq!(missing_files.len(), extra_files.len());
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        for fname in missing_files {
-            assert!(fname.contains("_NAM_"));
-            assert!(!fname.contains("_NAMM_"));
-            assert!(!fname.contains("_GFS_"));
-        }
+        // Registered with the wrong file type.
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let nam =
+            arch.validate_or_add_sounding_type(SoundingType::new_model("NAM", FileType::BUFR, 6))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        arch.add_file(
+            &site,
+            &nam,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040112Z_nam_kmso.buf",
+        )?;
 
-        for fname in extra_files {
-            assert!(fname.contains("_NAMM_"));
-            assert!(!fname.contains("_NAM_"));
-            assert!(!fname.contains("_GFS_"));
+        let nam = arch.set_sounding_type_file_type(&nam, FileType::BUFKIT)?;
+        assert_eq!(nam.file_type(), FileType::BUFKIT);
+        assert_eq!(
+            arch.sounding_type_info("NAM")?.unwrap().file_type(),
+            FileType::BUFKIT
+        );
+        assert!(!arch.retrieve(&site, &nam, &init_time)?.is_empty());
+
+        // There is no BUFR decoder to check the files with, and then the files don't decode.
+        assert!(arch
+            .set_sounding_type_file_type(&nam, FileType::BUFR)
+            .is_err());
+        arch.register_decoder(FileType::BUFR, Box::new(BufrDecoder));
+        match arch.set_sounding_type_file_type(&nam, FileType::BUFR) {
+            Err(BufkitDataErr::InFile(file_name, _)) => {
+                assert_eq!(file_name, "2017-04-01T1200Z_NAM_BUFR_kmso.gz")
+            }
+            other => panic!("unexpected result: {:?}", other),
         }
+        assert!(arch
+            .set_sounding_type_info(nam.clone().with_file_type(FileType::BUFR))
+            .is_err());
+        assert_eq!(
+            arch.sounding_type_info("NAM")?.unwrap().file_type(),
+            FileType::BUFKIT
+        );
 
         Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify site metadata
-    // ---------------------------------------------------------------------------------------------
     #[test]
-    fn test_sites() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
-
-        let mut test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffe and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn test_fetch_hook() -> Result<()> {
+        use std::{cell::Cell, rc::Rc};
 
-        for site in test_sites.iter_mut() {
-            *site = arch
-                .validate_or_add_site(site.clone())
-                .expect("Error adding site.");
-        }
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let sites = dbg!(arch.sites())?;
-        let sites: Vec<_> = sites.iter().map(|s| s.short_name()).collect();
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new(
+            "GFS",
+            false,
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
 
-        assert_eq!(sites.len(), 3);
-        assert!(sites.contains(&"kmso"));
-        assert!(sites.contains(&"ksea"));
-        assert!(sites.contains(&"kord"));
-        assert!(!sites.contains(&"xyz"));
+        // Without a hook, it's just missing.
+        assert!(arch.retrieve(&kmso, &gfs, &init_time).is_err());
+
+        let num_calls = Rc::new(Cell::new(0));
+        let hook_calls = Rc::clone(&num_calls);
+        arch.set_fetch_hook(Box::new(move |site, sounding_type, init_time| {
+            hook_calls.set(hook_calls.get() + 1);
+            let path = format!(
+                "example_data/{}_{}_{}.buf",
+                init_time.format("%Y%m%d%HZ"),
+                sounding_type.source().to_lowercase(),
+                site.short_name()
+            );
+            Ok(std::fs::read(path)?)
+        }));
+
+        let anal = arch.retrieve(&kmso, &gfs, &init_time)?;
+        assert_eq!(anal[0].sounding().valid_time().unwrap(), init_time);
+        assert_eq!(num_calls.get(), 1);
+        assert!(arch.file_exists(&kmso, &gfs, &init_time)?);
+
+        // Now it is cached in the archive.
+        arch.retrieve(&kmso, &gfs, &init_time)?;
+        assert_eq!(num_calls.get(), 1);
+        assert_eq!(arch.locations_for_site_and_type(&kmso, &gfs)?.len(), 1);
+
+        // Errors from the hook are passed along.
+        let missing = NaiveDate::from_ymd_opt(2018, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        assert!(arch.retrieve(&kmso, &gfs, &missing).is_err());
+        assert_eq!(num_calls.get(), 2);
+        assert!(!arch.file_exists(&kmso, &gfs, &missing)?);
 
         Ok(())
     }
 
     #[test]
-    fn test_site_info() {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_fetch_hook_most_recent_analysis() -> Result<()> {
+        use std::{cell::RefCell, rc::Rc};
 
-        let mut test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffe and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
-
-        for site in test_sites.iter_mut() {
-            assert!(!site.is_valid());
-
-            *site = arch
-                .validate_or_add_site(site.clone())
-                .expect("Error adding site.");
-
-            assert!(site.is_valid());
-        }
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        for site in test_sites.iter() {
-            let retr_site = arch.site_info(site.short_name()).unwrap().unwrap();
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let raob = arch.validate_or_add_sounding_type(SoundingType::new_observed(
+            "RAOB",
+            FileType::BUFKIT,
+            None,
+        ))?;
+
+        // Without a hook, there is nothing to retrieve.
+        assert!(arch.most_recent_analysis(&kmso, &gfs).is_err());
+
+        let requested = Rc::new(RefCell::new(vec![]));
+        let hook_requested = Rc::clone(&requested);
+        arch.set_fetch_hook(Box::new(move |_site, _sounding_type, init_time| {
+            hook_requested.borrow_mut().push(*init_time);
+            Ok(std::fs::read("example_data/2017040106Z_gfs_kmso.buf")?)
+        }));
+
+        // With an empty archive the latest scheduled run is fetched.
+        let before = Utc::now().naive_utc();
+        assert!(!arch.most_recent_analysis(&kmso, &gfs)?.is_empty());
+        let init_time = requested.borrow()[0];
+        assert_eq!(init_time.hour() % 6, 0);
+        assert_eq!((init_time.minute(), init_time.second()), (0, 0));
+        assert!(init_time <= Utc::now().naive_utc());
+        assert!(before - init_time < Duration::hours(6));
+        assert!(arch.file_exists(&kmso, &gfs, &init_time)?);
+
+        // Now it comes from the archive.
+        assert!(!arch.most_recent_analysis(&kmso, &gfs)?.is_empty());
+        assert_eq!(requested.borrow().len(), 1);
+
+        // Without a schedule there is no run to ask for.
+        assert!(arch.most_recent_analysis(&kmso, &raob).is_err());
+        assert_eq!(requested.borrow().len(), 1);
 
-            assert!(retr_site.is_valid());
-            assert_eq!(site.short_name(), retr_site.short_name());
-            assert_eq!(site.long_name(), retr_site.long_name());
-            assert_eq!(site.state_prov(), retr_site.state_prov());
-            assert_eq!(site.notes(), retr_site.notes());
-        }
+        Ok(())
     }
 
     #[test]
-    fn test_set_site_info() {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_backfill_missing() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffe and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for site in test_sites.iter_mut() {
-            *site = arch
-                .validate_or_add_site(site.clone())
-                .expect("Error adding site.");
-        }
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
 
-        let retr_site = arch.site_info("kmso").unwrap().unwrap();
-        assert_eq!(retr_site.short_name(), test_sites[2].short_name());
-        assert_eq!(retr_site.long_name(), test_sites[2].long_name());
-        assert_eq!(retr_site.notes(), test_sites[2].notes());
-        assert_eq!(retr_site.state_prov(), test_sites[2].state_prov());
+        // Take out a GFS run so there is a gap to fill, the NAM is missing its 06Z run already.
+        arch.remove(&kmso, &gfs, &hour(6))?;
 
-        let zootown = Site::new("kmso")
-            .with_long_name("Zootown".to_owned())
-            .with_notes("Mountains, not coast.".to_owned())
-            .with_state_prov(None)
-            .set_mobile(false);
+        let fetcher = |site: &Site, sounding_type: &SoundingType, init_time: &NaiveDateTime| {
+            if sounding_type.source() == "NAM" {
+                return Err(BufkitDataErr::NotEnoughData("server down".to_owned()));
+            }
 
-        arch.set_site_info(zootown.clone())
-            .expect("Error updating site.");
+            let path = format!(
+                "example_data/{}_{}_{}.buf",
+                init_time.format("%Y%m%d%HZ"),
+                sounding_type.source().to_lowercase(),
+                site.short_name()
+            );
+            match std::fs::read(path) {
+                Ok(data) => Ok(Some(data)),
+                Err(_) => Ok(None),
+            }
+        };
 
-        let retr_site = arch.site_info("kmso").unwrap().unwrap();
-        assert!(retr_site.is_valid());
-        assert_eq!(retr_site.short_name(), test_sites[2].short_name());
-        assert_ne!(retr_site.long_name(), test_sites[2].long_name());
-        assert_ne!(retr_site.notes(), test_sites[2].notes());
-        assert_eq!(retr_site.state_prov(), test_sites[2].state_prov());
+        let report = arch.backfill_missing(&hour(24), &fetcher)?;
+        assert!(!report.is_complete());
+        assert_eq!(report.filled(), &[(kmso.clone(), gfs.clone(), hour(6))]);
+        assert_eq!(
+            report.still_missing(),
+            &[(kmso.clone(), gfs.clone(), hour(24))]
+        );
+        let errored: Vec<_> = report
+            .errored()
+            .iter()
+            .map(|(_, sounding_type, init_time, _)| (sounding_type.clone(), *init_time))
+            .collect();
+        assert_eq!(
+            errored,
+            vec![(nam.clone(), hour(6)), (nam.clone(), hour(24))]
+        );
 
-        assert_eq!(retr_site.short_name(), zootown.short_name());
-        assert_eq!(retr_site.long_name(), zootown.long_name());
-        assert_eq!(retr_site.notes(), zootown.notes());
-        assert_eq!(retr_site.state_prov(), zootown.state_prov());
-    }
+        assert!(arch.file_exists(&kmso, &gfs, &hour(6))?);
+        assert_eq!(arch.inventory(&kmso)?.missing(&nam), &[(hour(6), hour(6))]);
 
-    #[test]
-    fn test_validate_site() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+        Ok(())
+    }
 
-        let test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffe and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
+    #[test]
+    fn test_export_series_concat() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        for site in test_sites.iter() {
-            arch.validate_or_add_site(site.clone())?;
-        }
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for site in test_sites.iter() {
-            let valid_site = arch.validate_site(site.clone())?;
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
 
-            assert!(valid_site.is_valid());
-            assert_eq!(valid_site.short_name(), site.short_name());
+        let mut buf: Vec<u8> = vec![];
+        let count = arch.export_series_concat(&kmso, &gfs, &hour(6), &hour(18), &mut buf)?;
+        assert_eq!(count, 3);
+
+        let mut expected: Vec<u8> = vec![];
+        for h in &[6, 12, 18] {
+            arch.export(&kmso, &gfs, &hour(*h))?
+                .read_to_end(&mut expected)?;
         }
+        assert_eq!(buf, expected);
 
-        let bad_site = Site::new("kxyz")
-            .with_long_name("not real".to_owned())
-            .with_notes("I made this up, it may be real anyway.".to_owned())
-            .with_state_prov(None)
-            .set_mobile(false);
+        // Each run is still there when the file is read back.
+        let text = std::str::from_utf8(&buf)?;
+        assert_eq!(split_bufkit_stations(text).len(), 3);
 
-        assert!(arch.validate_site(bad_site).is_err());
+        let count = arch.export_series_concat(&kmso, &gfs, &hour(7), &hour(11), &mut buf)?;
+        assert_eq!(count, 0);
 
         Ok(())
     }
 
     #[test]
-    fn test_validate_or_add_site() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
+    fn test_export() -> Result<()> {
+        let TestArchive { tmp, mut arch } =
             create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffe and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for site in test_sites.iter_mut() {
-            *site = arch.validate_or_add_site(site.clone())?;
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+        let original = std::fs::read("example_data/2017040106Z_gfs_kmso.buf")?;
 
-            assert!(site.is_valid());
-        }
+        let mut exported = vec![];
+        arch.export(&kmso, &gfs, &init_time)?
+            .read_to_end(&mut exported)?;
+        assert_eq!(exported, original);
+
+        // The parent directories don't exist yet.
+        let dest = tmp.path().join("exports").join("kmso").join("gfs.buf");
+        arch.export_to_file(&kmso, &gfs, &init_time, &dest)?;
+        assert_eq!(std::fs::read(&dest)?, original);
+
+        let missing = init_time + Duration::hours(1);
+        assert!(arch
+            .export_to_file(&kmso, &gfs, &missing, &tmp.path().join("missing.buf"))
+            .is_err());
+        assert!(!tmp.path().join("missing.buf").exists());
 
         Ok(())
     }
 
     #[test]
-    fn test_sites_round_trip() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_export_compressed() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sites = [
-            Site::new("kord")
-                .with_long_name("Chicago/O'Hare".to_owned())
-                .with_notes("Major air travel hub.".to_owned())
-                .with_state_prov(StateProv::IL)
-                .set_mobile(false),
-            Site::new("ksea")
-                .with_long_name("Seattle".to_owned())
-                .with_notes("A coastal city with coffee and rain".to_owned())
-                .with_state_prov(StateProv::WA)
-                .set_mobile(false),
-            Site::new("kmso")
-                .with_long_name("Missoula".to_owned())
-                .with_notes("In a valley.".to_owned())
-                .with_state_prov(None)
-                .set_mobile(false),
-        ];
+        let site = arch.validate_or_add_site(Site::new("kmso"))?;
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "NAM",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let file_name = "example_data/2017040112Z_nam_kmso.buf";
+        let original = std::fs::read(file_name)?;
 
-        for site in test_sites.iter_mut() {
-            *site = arch.validate_or_add_site(site.clone())?;
-        }
+        for &codec in &[Codec::Gzip, Codec::None] {
+            arch.set_codec(codec);
+            arch.add_file(&site, &nam, &loc, &init_time, &init_time, file_name)?;
 
-        assert_eq!(arch.site_info("ksea")?.unwrap().short_name(), "ksea");
-        assert_eq!(arch.site_info("kord")?.unwrap().short_name(), "kord");
-        assert_eq!(arch.site_info("xyz")?, None);
+            let (stored_codec, mut raw) = arch.export_compressed(&site, &nam, &init_time)?;
+            assert_eq!(stored_codec, codec);
 
-        let retrieved_sites = arch.sites().expect("Error retrieving sites.");
+            let mut compressed = vec![];
+            raw.read_to_end(&mut compressed)?;
 
-        for site in retrieved_sites {
-            println!("{:#?}", site);
-            assert!(test_sites
-                .iter()
-                .find(|st| st.short_name() == site.short_name())
-                .is_some());
+            let mut decoded = vec![];
+            codec
+                .decoder(std::io::Cursor::new(compressed.clone()))
+                .read_to_end(&mut decoded)?;
+            assert_eq!(decoded, original);
+
+            match codec {
+                Codec::Gzip => assert!(compressed.len() < original.len()),
+                Codec::None => assert_eq!(compressed, original),
+            }
         }
+
         Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify sounding type metadata
-    // ---------------------------------------------------------------------------------------------
-
     #[test]
-    fn test_sounding_types() -> Result<()> {
+    fn test_get_most_recent_analysis() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
@@ -1068,120 +8155,202 @@ mod unit {
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let types: Vec<String> = arch
-            .sounding_types()?
-            .iter()
-            .map(|t| t.source().to_owned())
-            .collect();
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let snd_type = arch
+            .sounding_type_info("GFS")?
+            .expect("Sounding type not in index");
 
-        assert!(types.contains(&"GFS".to_owned()));
-        assert!(types.contains(&"NAM".to_owned()));
-        assert!(!types.contains(&"NAM4KM".to_owned()));
-        assert!(!types.contains(&"LocalWrf".to_owned()));
-        assert!(!types.contains(&"Other".to_owned()));
+        let init_time = arch
+            .most_recent_init_time(&kmso, &snd_type)
+            .expect("Error getting valid time.");
+
+        assert_eq!(init_time, NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0));
+
+        arch.most_recent_analysis(&kmso, &snd_type)
+            .expect("Failed to retrieve sounding.");
+
+        // A site without any files says what was missing.
+        let kord = arch.validate_or_add_site(Site::new("kord"))?;
+        match arch.most_recent_analysis(&kord, &snd_type) {
+            Err(BufkitDataErr::NotEnoughData(what)) => assert_eq!(what, "no GFS files for kord"),
+            other => panic!("unexpected result: {:?}", other.map(|a| a.len())),
+        }
 
         Ok(())
     }
 
     #[test]
-    fn test_sounding_type_info() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn test_latest_analysis_before() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sts = [
-            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
-            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
-            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
-            SoundingType::new("Incident", true, FileType::BUFR, None),
-            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for st in test_sts.iter_mut() {
-            assert!(!st.is_valid());
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let first_valid_time = |anals: Vec<Analysis>| anals[0].sounding().valid_time();
+
+        let anals = arch.latest_analysis_before(&kmso, &gfs, hour(9))?;
+        assert_eq!(first_valid_time(anals), Some(hour(6)));
+
+        // At exactly an init time that run is included.
+        let anals = arch.latest_analysis_before(&kmso, &gfs, hour(12))?;
+        assert_eq!(first_valid_time(anals), Some(hour(12)));
+
+        let anals = arch.latest_analysis_before(&kmso, &gfs, hour(23))?;
+        assert_eq!(first_valid_time(anals), Some(hour(18)));
+
+        // Before any files.
+        let as_of = hour(0) - Duration::hours(1);
+        match arch.latest_analysis_before(&kmso, &gfs, as_of) {
+            Err(BufkitDataErr::NotEnoughData(what)) => {
+                assert_eq!(
+                    what,
+                    "no GFS files for kmso at or before 2017-03-31 23:00:00"
+                )
+            }
+            other => panic!("unexpected result: {:?}", other.map(|a| a.len())),
+        }
 
-            *st = arch
-                .validate_or_add_sounding_type(st.clone())
-                .expect("Error adding sounding type.");
+        Ok(())
+    }
 
-            assert!(st.is_valid());
-        }
+    #[test]
+    fn test_retrieve_all() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        for st in test_sts.iter() {
-            let retr_st = arch.sounding_type_info(st.source()).unwrap().unwrap();
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-            assert!(retr_st.is_valid());
-            assert_eq!(st.source(), retr_st.source());
-            assert_eq!(st.is_modeled(), retr_st.is_modeled());
-            assert_eq!(st.is_observed(), retr_st.is_observed());
-            assert_eq!(
-                st.hours_between_initializations(),
-                retr_st.hours_between_initializations()
-            );
-            assert_eq!(st.file_type(), retr_st.file_type());
-        }
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let snd_type = arch
+            .sounding_type_info("GFS")?
+            .expect("Sounding type not in index");
+
+        let start_time = NaiveDate::from_ymd(2017, 3, 1).and_hms(1, 0, 0);
+        let end_time = NaiveDate::from_ymd(2017, 4, 1).and_hms(12, 0, 0);
+
+        let all = arch.retrieve_all(&kmso, &snd_type, &start_time, &end_time)?;
+
+        assert_eq!(all.len(), 3);
 
         Ok(())
     }
 
     #[test]
-    fn test_set_sounding_type_info() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_iter_site() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sts = [
-            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
-            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
-            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
-            SoundingType::new("Incident", true, FileType::BUFR, None),
-            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for st in test_sts.iter_mut() {
-            *st = arch
-                .validate_or_add_sounding_type(st.clone())
-                .expect("Error adding sounding type.");
-        }
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
 
-        let retr_st = arch.sounding_type_info("SREF").unwrap().unwrap();
-        assert_eq!(retr_st.source(), test_sts[4].source());
-        assert_eq!(retr_st.is_modeled(), test_sts[4].is_modeled());
-        assert_eq!(retr_st.is_observed(), test_sts[4].is_observed());
+        let mut seen = vec![];
+        for item in arch.iter_site(&kmso) {
+            let (sounding_type, init_time, anals) = item?;
+            assert!(!anals.is_empty());
+            seen.push((sounding_type.source().to_owned(), init_time));
+        }
         assert_eq!(
-            retr_st.hours_between_initializations(),
-            test_sts[4].hours_between_initializations()
+            seen,
+            vec![
+                ("GFS".to_owned(), hour(0)),
+                ("NAM".to_owned(), hour(0)),
+                ("GFS".to_owned(), hour(6)),
+                ("GFS".to_owned(), hour(12)),
+                ("NAM".to_owned(), hour(12)),
+                ("GFS".to_owned(), hour(18)),
+                ("NAM".to_owned(), hour(18)),
+            ]
         );
-        assert_eq!(retr_st.file_type(), test_sts[4].file_type());
 
-        let sref = SoundingType::new("SREF", false, FileType::BUFKIT, None);
+        // A bad file is an error for that file only.
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let (bad_file, _) = arch.get_file_name_for(&kmso, &gfs, &hour(6))?;
+        std::fs::write(arch.file_dir.join(&bad_file), b"not gzip")?;
+        let results: Vec<_> = arch.iter_site(&kmso).collect();
+        assert_eq!(results.len(), 7);
+        match &results[2] {
+            Err(BufkitDataErr::InFile(file_name, _)) => assert_eq!(file_name, &bad_file),
+            other => panic!(
+                "unexpected result: {:?}",
+                other.as_ref().map(|(st, _, _)| st)
+            ),
+        }
+        assert_eq!(results.iter().filter(|res| res.is_ok()).count(), 6);
 
-        arch.set_sounding_type_info(sref.clone())
-            .expect("Error updating sounding type.");
+        // A site without any files.
+        let kord = arch.validate_or_add_site(Site::new("kord"))?;
+        assert_eq!(arch.iter_site(&kord).count(), 0);
 
-        let retr_st = arch.sounding_type_info("SREF").unwrap().unwrap();
-        assert!(retr_st.is_valid());
-        assert_eq!(retr_st.source(), test_sts[4].source());
-        assert_eq!(retr_st.is_modeled(), test_sts[4].is_modeled());
-        assert_eq!(retr_st.is_observed(), test_sts[4].is_observed());
-        assert_ne!(
-            retr_st.hours_between_initializations(),
-            test_sts[4].hours_between_initializations()
-        );
-        assert_eq!(retr_st.file_type(), test_sts[4].file_type());
+        Ok(())
+    }
 
-        assert_eq!(retr_st.source(), sref.source());
-        assert_eq!(retr_st.is_modeled(), sref.is_modeled());
-        assert_eq!(retr_st.is_observed(), sref.is_observed());
-        assert_eq!(
-            retr_st.hours_between_initializations(),
-            sref.hours_between_initializations()
-        );
-        assert_eq!(retr_st.file_type(), sref.file_type());
+    #[test]
+    fn test_fold_series() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let gfs = arch
+            .sounding_type_info("GFS")?
+            .expect("Sounding type not in index");
+
+        let start_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let end_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+        let expected: usize = arch
+            .retrieve_all(&kmso, &gfs, &start_time, &end_time)?
+            .iter()
+            .map(Vec::len)
+            .sum();
+
+        let count = arch.fold_series(&kmso, &gfs, 0, |count, _anal| count + 1)?;
+        assert_eq!(count, expected);
+
+        // A bad file stops the fold and says which file it was.
+        let (bad_file, _) = arch.get_file_name_for(&kmso, &gfs, &end_time)?;
+        std::fs::write(arch.file_dir.join(&bad_file), b"not a gzip file")?;
+        match arch.fold_series(&kmso, &gfs, 0, |count, _anal| count + 1) {
+            Err(BufkitDataErr::InFile(file_name, _)) => assert_eq!(file_name, bad_file),
+            other => panic!("Unexpected result: {:?}", other),
+        }
 
         Ok(())
     }
 
     #[test]
-    fn test_sounding_types_for_site() -> Result<()> {
+    fn test_retrieve_lead_time() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
@@ -1189,260 +8358,488 @@ mod unit {
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let site = arch.site_info("kmso")?.expect("No such site.");
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let gfs = arch
+            .sounding_type_info("GFS")?
+            .expect("Sounding type not in index");
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
 
-        let types: Vec<String> = arch
-            .sounding_types_for_site(&site)?
-            .iter()
-            .map(|t| t.source().to_owned())
-            .collect();
+        let anal = arch
+            .retrieve_lead_time(&kmso, &gfs, &init_time, Duration::hours(24))?
+            .expect("No 24 hour forecast.");
+        #[allow(deprecated)]
+        let valid_time = anal.sounding().valid_time();
+        assert_eq!(valid_time, Some(init_time + Duration::hours(24)));
 
-        assert!(types.contains(&"GFS".to_owned()));
-        assert!(types.contains(&"NAM".to_owned()));
-        assert!(!types.contains(&"NAM4KM".to_owned()));
-        assert!(!types.contains(&"LocalWrf".to_owned()));
-        assert!(!types.contains(&"Other".to_owned()));
+        // Beyond the end of the run, or between output times.
+        assert!(arch
+            .retrieve_lead_time(&kmso, &gfs, &init_time, Duration::days(30))?
+            .is_none());
+        assert!(arch
+            .retrieve_lead_time(&kmso, &gfs, &init_time, Duration::minutes(17))?
+            .is_none());
+
+        let end_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(23, 0, 0)
+            .unwrap();
+        let series = arch.retrieve_lead_time_series(
+            &kmso,
+            &gfs,
+            &init_time,
+            &end_time,
+            Duration::hours(24),
+        )?;
+        let init_times: Vec<NaiveDateTime> = series.iter().map(|(init, _)| *init).collect();
+        assert_eq!(
+            init_times,
+            vec![
+                init_time,
+                init_time + Duration::hours(6),
+                init_time + Duration::hours(12)
+            ]
+        );
 
         Ok(())
     }
 
     #[test]
-    fn test_validate_sounding_type() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
-
-        let mut test_sts = [
-            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
-            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
-            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
-            SoundingType::new("Incident", true, FileType::BUFR, None),
-            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
-        ];
-
-        for st in test_sts.iter_mut() {
-            *st = arch
-                .validate_or_add_sounding_type(st.clone())
-                .expect("Error adding sounding type.");
-        }
+    fn test_retrieve_surface() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        for st in test_sts.iter() {
-            arch.validate_or_add_sounding_type(st.clone())?;
-        }
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for st in test_sts.iter() {
-            let valid_st = arch.validate_sounding_type(st.clone())?;
+        let site = arch.site_info("kmso")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
 
-            assert!(valid_st.is_valid());
-            assert_eq!(valid_st.source(), st.source());
-        }
+        let anals = arch.retrieve(&site, &nam, &init_time)?;
+        let surface = arch.retrieve_surface(&site, &nam, &init_time)?;
+        assert_eq!(surface.len(), anals.len());
 
-        let bad_st = SoundingType::new("drill into ground", false, FileType::BUFR, 1);
+        // The first sounding is valid at the initialization time.
+        let (valid_time, sfc) = surface[0];
+        assert_eq!(valid_time, init_time);
+        assert!(sfc.temperature().is_some());
+        assert!(sfc.dew_point().is_some());
+        assert!(sfc.wind().is_some());
+        assert!(sfc.station_pressure().is_some());
+        assert!(sfc.dew_point() <= sfc.temperature());
 
-        assert!(arch.validate_sounding_type(bad_st).is_err());
+        assert!(surface.windows(2).all(|pair| pair[0].0 < pair[1].0));
 
         Ok(())
     }
 
     #[test]
-    fn test_validate_or_add_sounding_type() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_retrieve_range() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_sts = [
-            SoundingType::new("GFS", false, FileType::BUFKIT, 6),
-            SoundingType::new("NAM", false, FileType::BUFKIT, 6),
-            SoundingType::new("NamNest", false, FileType::BUFKIT, 6),
-            SoundingType::new("Incident", true, FileType::BUFR, None),
-            SoundingType::new("SREF", false, FileType::BUFKIT, 6),
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for st in test_sts.iter_mut() {
-            *st = arch
-                .validate_or_add_sounding_type(st.clone())
-                .expect("Error adding sounding type.");
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
 
-            assert!(st.is_valid());
-        }
+        // Both ends are included.
+        let anals = arch.retrieve_range(&kmso, &gfs, &hour(6), &hour(18))?;
+        let init_times: Vec<NaiveDateTime> =
+            anals.iter().map(|(init_time, _)| *init_time).collect();
+        assert_eq!(init_times, vec![hour(6), hour(12), hour(18)]);
+        assert!(anals.iter().all(|(_, anal)| !anal.is_empty()));
+
+        assert_eq!(
+            arch.retrieve_range(&kmso, &gfs, &hour(1), &hour(5))?.len(),
+            0
+        );
+        assert_eq!(
+            arch.retrieve_range(&kmso, &gfs, &hour(18), &hour(6))?.len(),
+            0
+        );
 
         Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Query or modify location metadata
-    // ---------------------------------------------------------------------------------------------
+    #[test]
+    // The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+    #[allow(deprecated)]
+    fn test_retrieve_many() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-    fn populate_test_locations(arch: &Archive) -> [Location; 5] {
-        let mut test_locs = [
-            Location::new(43.0, -110.0, 599, None),
-            Location::new(45.0, -112.0, 699, None),
-            Location::new(47.0, -114.0, 799, None),
-            Location::new(49.0, -116.0, 999, None),
-            Location::new(49.0, -116.0, 999, None), // Duplicate!
-        ];
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for loc in test_locs.iter_mut() {
-            assert!(!loc.is_valid());
+        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
+        let snd_type = arch
+            .sounding_type_info("GFS")?
+            .expect("Sounding type not in index");
 
-            *loc = arch
-                .validate_or_add_location(loc.clone())
-                .expect("Error adding location.");
+        let init_times = [
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(18, 0, 0)
+                .unwrap(),
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        ];
 
-            assert!(loc.is_valid());
+        let anals = arch.retrieve_many(&kmso, &snd_type, &init_times)?;
+        assert_eq!(anals.len(), 3);
+        for ((init_time, anal), requested) in anals.iter().zip(init_times.iter()) {
+            assert_eq!(init_time, requested);
+            assert_eq!(anal[0].sounding().valid_time().unwrap(), *requested);
         }
 
-        test_locs
-    }
+        let missing = NaiveDate::from_ymd_opt(2018, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert!(arch
+            .retrieve_many(&kmso, &snd_type, &[init_times[0], missing])
+            .is_err());
 
-    #[test]
-    fn test_all_locations() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+        assert!(arch.retrieve_many(&kmso, &snd_type, &[])?.is_empty());
 
-        let _ = populate_test_locations(&arch);
+        // Close times are matched to a run, like with retrieve.
+        let late = init_times[1] + Duration::minutes(20);
+        assert!(arch.retrieve_many(&kmso, &snd_type, &[late]).is_err());
+        arch.set_init_time_match(InitTimeMatch::Within(Duration::minutes(30)));
+        let anals = arch.retrieve_many(&kmso, &snd_type, &[late, init_times[0]])?;
+        assert_eq!(anals[0].0, late);
+        assert_eq!(
+            anals[0].1[0].sounding().valid_time().unwrap(),
+            init_times[1]
+        );
 
-        let locs = dbg!(arch.all_locations())?;
-        let locs: Vec<_> = locs.iter().map(|s| s.elevation()).collect();
+        // Missing times are fetched with the hook, like with retrieve.
+        arch.set_fetch_hook(Box::new(|_site, _sounding_type, _init_time| {
+            Ok(std::fs::read("example_data/2017040118Z_gfs_kmso.buf")?)
+        }));
+        let anals = arch.retrieve_many(&kmso, &snd_type, &[init_times[0], missing])?;
+        assert_eq!(anals[1].0, missing);
+        assert!(!anals[1].1.is_empty());
+        assert!(arch.file_exists(&kmso, &snd_type, &missing)?);
+
+        // More times than sqlite allows parameters in one query. Decoding that many files takes a
+        // while, and only the look up is being tested, so skip it.
+        struct SkipDecoder;
+
+        impl SoundingDecoder for SkipDecoder {
+            fn decode(&self, _bytes: &[u8], _description: &str) -> Result<Vec<Analysis>> {
+                Ok(vec![])
+            }
+        }
 
-        assert_eq!(locs.len(), 4);
-        assert!(locs.contains(&599));
-        assert!(locs.contains(&699));
-        assert!(locs.contains(&799));
-        assert!(locs.contains(&999));
-        assert!(!locs.contains(&899));
+        arch.register_decoder(FileType::BUFKIT, Box::new(SkipDecoder));
+        let many: Vec<NaiveDateTime> = init_times.iter().cycle().take(1000).cloned().collect();
+        let anals = arch.retrieve_many(&kmso, &snd_type, &many)?;
+        assert_eq!(anals.len(), 1000);
+        assert!(anals
+            .iter()
+            .zip(&many)
+            .all(|((init_time, _), requested)| init_time == requested));
 
         Ok(())
     }
 
     #[test]
-    fn test_location_info() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_remove_file() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let test_locs = populate_test_locations(&arch);
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for loc in test_locs.iter() {
-            let retr_loc = arch
-                .location_info(loc.latitude(), loc.longitude(), loc.elevation())
-                .unwrap()
-                .unwrap();
+        let init_time = NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0);
+        let kmso = arch.site_info("kmso")?.expect("No such site.");
+        let snd_type = arch
+            .sounding_type_info("GFS")?
+            .expect("No such sounding type.");
 
-            assert!(loc.is_valid());
-            assert!(retr_loc.is_valid());
-            assert_eq!(loc.latitude(), retr_loc.latitude());
-            assert_eq!(loc.longitude(), retr_loc.longitude());
-            assert_eq!(loc.elevation(), retr_loc.elevation());
-            assert_eq!(loc.tz_offset(), retr_loc.tz_offset());
-        }
+        assert!(arch
+            .file_exists(&kmso, &snd_type, &init_time)
+            .expect("Error checking db"));
+        arch.remove(&kmso, &snd_type, &init_time)
+            .expect("Error while removing.");
+        assert!(!arch
+            .file_exists(&kmso, &snd_type, &init_time)
+            .expect("Error checking db"));
 
         Ok(())
     }
 
     #[test]
-    fn test_retrieve_or_add_location() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_remove_many() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let _ = populate_test_locations(&arch);
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        let num_files = arch.count()?;
+        let keys = [
+            (kmso.clone(), gfs.clone(), hour(0)),
+            (kmso.clone(), nam.clone(), hour(12)),
+            // There is no 06Z NAM, so this is skipped.
+            (kmso.clone(), nam.clone(), hour(6)),
+        ];
+        assert_eq!(arch.remove_many(&keys)?, 2);
+        assert_eq!(arch.count()?, num_files - 2);
+        assert!(!arch.file_exists(&kmso, &gfs, &hour(0))?);
+        assert!(!arch.file_exists(&kmso, &nam, &hour(12))?);
+        assert!(arch.file_exists(&kmso, &nam, &hour(0))?);
+
+        let (missing, extra) = arch.check()?;
+        assert!(missing.is_empty() && extra.is_empty());
+
+        // They're all gone now.
+        assert_eq!(arch.remove_many(&keys)?, 0);
+
+        // A failure part way through still removes the others, and the index matches the disk.
+        let keys = [
+            (kmso.clone(), gfs.clone(), hour(6)),
+            (kmso.clone(), gfs.clone(), hour(12)),
+            (kmso.clone(), gfs.clone(), hour(18)),
+        ];
+        let (stuck, _) = arch.get_file_name_for(&kmso, &gfs, &hour(12))?;
+        remove_file(arch.file_dir.join(&stuck))?;
+        assert!(arch.remove_many(&keys).is_err());
+        assert!(!arch.file_exists(&kmso, &gfs, &hour(6))?);
+        assert!(arch.file_exists(&kmso, &gfs, &hour(12))?);
+        assert!(!arch.file_exists(&kmso, &gfs, &hour(18))?);
+        assert_eq!(arch.count()?, num_files - 4);
+        assert_eq!(arch.check()?, (vec![stuck], vec![]));
 
         Ok(())
     }
 
     #[test]
-    fn test_set_location_info() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
-
-        let test_locs = populate_test_locations(&arch);
-
-        let loc = test_locs[0];
-        assert!(loc.is_valid());
-        let loc = loc.with_tz_offset(-3600 * 6);
-
-        arch.set_location_info(loc)?;
+    fn test_remove_range() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let retr_loc = arch
-            .location_info(loc.latitude(), loc.longitude(), loc.elevation())?
-            .unwrap();
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        assert_eq!(retr_loc.tz_offset(), loc.tz_offset());
-        assert_ne!(retr_loc.tz_offset(), test_locs[0].tz_offset());
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let nam = arch.sounding_type_info("NAM")?.unwrap();
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let num_nam = arch.inventory(&kmso)?.count(&nam);
+
+        // The 00Z and 06Z GFS.
+        assert_eq!(arch.remove_range(&kmso, &gfs, &hour(12))?, 2);
+        let inv = arch.inventory(&kmso)?;
+        assert_eq!(inv.range(&gfs), Some((hour(12), hour(18))));
+        assert_eq!(inv.count(&nam), num_nam);
+        assert_eq!(arch.remove_range(&kmso, &gfs, &hour(12))?, 0);
+
+        // A file that can't be removed is skipped, and stays in the index.
+        let (stuck, _) = arch.get_file_name_for(&kmso, &nam, &hour(0))?;
+        remove_file(arch.file_dir.join(&stuck))?;
+        assert!(arch.remove_range(&kmso, &nam, &hour(18)).is_err());
+        assert_eq!(
+            arch.inventory(&kmso)?.range(&nam),
+            Some((hour(0), hour(18)))
+        );
+        assert_eq!(arch.inventory(&kmso)?.count(&nam), 2);
+        assert_eq!(arch.check()?, (vec![stuck], vec![]));
 
         Ok(())
     }
 
     #[test]
-    fn test_locations_for_site_and_type() -> Result<()> {
+    fn test_remove_and_restore() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
         } = create_test_archive().expect("Failed to create test archive.");
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.set_trash_enabled(true);
 
-        let site = arch.site_info("kmso")?.expect("No such site.");
-        let sounding_type = arch
-            .sounding_types_for_site(&site)?
-            .into_iter()
-            .filter(|st| st.source() == "GFS")
-            .nth(0)
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
             .unwrap();
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
 
-        let locs: Vec<Location> = arch.locations_for_site_and_type(&site, &sounding_type)?;
+        arch.remove(&kmso, &gfs, &init_time)?;
+        assert!(!arch.file_exists(&kmso, &gfs, &init_time)?);
+        assert_eq!(arch.count()?, 6);
+        assert_eq!(arch.check()?, (vec![], vec![]));
 
-        assert_eq!(locs.len(), 1);
-        let loc = locs.into_iter().nth(0).unwrap();
-        assert_eq!(loc.latitude(), 46.92);
-        assert_eq!(loc.longitude(), -114.08);
-        assert_eq!(loc.elevation(), 972);
-        assert!(loc.tz_offset().is_none());
+        arch.restore(&kmso, &gfs, &init_time)?;
+        assert!(arch.file_exists(&kmso, &gfs, &init_time)?);
+        assert_eq!(arch.count()?, 7);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert!(!arch.retrieve(&kmso, &gfs, &init_time)?.is_empty());
+
+        // It is no longer in the trash.
+        assert!(arch.restore(&kmso, &gfs, &init_time).is_err());
+        assert_eq!(arch.empty_trash()?, 0);
 
         Ok(())
     }
 
     #[test]
-    fn test_validate_location() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
+    fn test_remove_and_empty_trash() -> Result<()> {
+        let TestArchive { tmp, mut arch } =
             create_test_archive().expect("Failed to create test archive.");
 
-        let mut test_locations = populate_test_locations(&arch);
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.set_trash_enabled(true);
 
-        for loc in test_locations.iter_mut() {
-            *loc = arch.validate_location(*loc)?;
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
 
-            assert!(loc.is_valid());
-        }
+        arch.remove(&kmso, &gfs, &init_time)?;
+        assert_eq!(arch.prune_least_recently_used(0)?, 6);
+        assert_eq!(arch.count()?, 0);
+        assert_eq!(read_dir(tmp.path().join("trash"))?.count(), 7);
 
-        assert_eq!(test_locations[3].id(), test_locations[4].id());
+        assert_eq!(arch.empty_trash()?, 7);
+        assert_eq!(read_dir(tmp.path().join("trash"))?.count(), 0);
+        assert!(arch.restore(&kmso, &gfs, &init_time).is_err());
+
+        // With the trash off files are deleted right away.
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        arch.set_trash_enabled(false);
+        arch.remove(&kmso, &gfs, &init_time)?;
+        assert_eq!(read_dir(tmp.path().join("trash"))?.count(), 0);
+        assert!(arch.restore(&kmso, &gfs, &init_time).is_err());
 
         Ok(())
     }
 
     #[test]
-    fn test_validate_or_add_location() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
-
-        let mut test_locations = populate_test_locations(&arch);
+    fn test_remove_site() -> Result<()> {
+        // Refuses to delete one file, like one with the wrong permissions.
+        #[derive(Debug)]
+        struct StuckFile(FileSystemBlobStore, String);
+
+        impl BlobStore for StuckFile {
+            fn put(&self, name: &str, data: &mut dyn Read) -> Result<()> {
+                self.0.put(name, data)
+            }
 
-        for loc in test_locations.iter_mut() {
-            *loc = arch
-                .validate_or_add_location(*loc)
-                .expect("Error adding location.");
+            fn get(&self, name: &str) -> Result<Box<dyn Read>> {
+                self.0.get(name)
+            }
 
-            assert!(loc.is_valid());
+            fn delete(&self, name: &str) -> Result<()> {
+                if name == self.1 {
+                    return Err(std::io::Error::other("permission denied").into());
+                }
+                self.0.delete(name)
+            }
+
+            fn list(&self) -> Result<Vec<String>> {
+                self.0.list()
+            }
         }
 
-        assert_eq!(test_locations[3].id(), test_locations[4].id());
+        let TestArchive { tmp, mut arch } =
+            create_test_archive().expect("Failed to create test archive.");
+
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let num_kmso = arch.count()?;
+
+        // Put a copy of the GFS files at a test site.
+        let test_site = arch.validate_or_add_site(Site::new("test"))?;
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        for &h in &[0, 6, 18] {
+            arch.add_file(
+                &test_site,
+                &gfs,
+                &loc,
+                &hour(h),
+                &hour(h),
+                &format!("example_data/20170401{:02}Z_gfs_kmso.buf", h),
+            )?;
+        }
+        arch.set_trash_enabled(true);
+        arch.remove(&test_site, &gfs, &hour(0))?;
+
+        // A failure leaves the site in place, with the file it couldn't delete.
+        let (stuck, _) = arch.get_file_name_for(&test_site, &gfs, &hour(6))?;
+        arch.set_blob_store(Box::new(StuckFile(
+            FileSystemBlobStore::new(&arch.file_dir),
+            stuck.clone(),
+        )));
+        assert!(arch.remove_site(&test_site).is_err());
+        assert!(arch.site_info("test")?.is_some());
+        assert!(arch.file_exists(&test_site, &gfs, &hour(6))?);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        arch.set_blob_store(Box::new(FileSystemBlobStore::new(&arch.file_dir)));
+        let num_test = arch.count()? - num_kmso;
+        assert_eq!(arch.remove_site(&test_site)?, num_test as usize);
+        assert!(arch.site_info("test")?.is_none());
+        assert_eq!(arch.count()?, num_kmso);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+        assert_eq!(read_dir(tmp.path().join("trash"))?.count(), 0);
 
         Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Query archive inventory
-    // ---------------------------------------------------------------------------------------------
-
     #[test]
-    fn test_inventory() -> Result<()> {
+    fn test_prune_least_recently_used() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
@@ -1450,199 +8847,246 @@ mod unit {
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let site = arch.site_info("kmso")?.expect("No such site.");
-        let gfs = arch
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let kmso = arch.site_info("kmso")?.expect("No such site.");
+        let snd_type = arch
             .sounding_type_info("GFS")?
             .expect("No such sounding type.");
-        let nam = arch
-            .sounding_type_info("NAM")?
-            .expect("No such sounding type.");
-
-        let first = NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0);
-        let last = NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0);
 
-        let inv = arch.inventory(&site)?;
+        arch.retrieve(&kmso, &snd_type, &init_time)?;
 
-        assert_eq!(inv.range(&gfs).unwrap(), (first, last));
-        assert_eq!(inv.range(&nam).unwrap(), (first, last));
+        let (fname, _) = arch.get_file_name_for(&kmso, &snd_type, &init_time)?;
+        let keep_bytes = arch.file_dir.join(fname).metadata()?.len();
 
-        let gfs_locations = dbg!(inv.locations(&gfs));
-        assert_eq!(gfs_locations.len(), 1);
-        assert_eq!(gfs_locations[0].latitude(), 46.92);
-        assert_eq!(gfs_locations[0].longitude(), -114.08);
-        assert_eq!(gfs_locations[0].elevation(), 972);
-        assert!(gfs_locations[0].is_valid());
+        assert_eq!(arch.prune_least_recently_used(keep_bytes)?, 6);
+        assert_eq!(arch.count()?, 1);
+        assert!(arch.file_exists(&kmso, &snd_type, &init_time)?);
 
-        let nam_locations = inv.locations(&nam);
-        assert_eq!(nam_locations.len(), 1);
-        assert_eq!(nam_locations[0].latitude(), 46.87);
-        assert_eq!(nam_locations[0].longitude(), -114.16);
-        assert_eq!(nam_locations[0].elevation(), 1335);
-        assert!(nam_locations[0].is_valid());
+        // Nothing left to remove
+        assert_eq!(arch.prune_least_recently_used(keep_bytes)?, 0);
 
         Ok(())
     }
 
     #[test]
-    fn test_most_recent_init_time() -> Result<()> {
+    fn test_init_time_truncated_to_minute() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
         } = create_test_archive().expect("Failed to create test archive.");
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
+        assert_eq!(arch.count()?, 7);
 
-        let site = dbg!(arch.site_info("kmso"))?.unwrap();
-        let sounding_type = dbg!(arch.sounding_type_info("GFS"))?.unwrap();
-        let most_recent = dbg!(arch.most_recent_init_time(&site, &sounding_type))?;
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let raob = arch.validate_or_add_sounding_type(SoundingType::new_observed(
+            "RAOB",
+            FileType::BUFKIT,
+            12,
+        ))?;
+
+        let launch = |second| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, second)
+                .unwrap()
+        };
 
-        let most_recent_should_be = NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0);
-        assert_eq!(most_recent, most_recent_should_be);
+        // Same minute, different seconds, so it is the same file.
+        for second in &[15, 45] {
+            arch.add_file(
+                &kmso,
+                &raob,
+                &loc,
+                &launch(*second),
+                &launch(*second),
+                "example_data/2017040112Z_nam_kmso.buf",
+            )?;
+        }
+        assert_eq!(arch.count()?, 8);
 
-        let sounding_type = dbg!(arch.sounding_type_info("NAM"))?.unwrap();
-        let most_recent = dbg!(arch.most_recent_init_time(&site, &sounding_type))?;
+        let (missing, not_indexed) = arch.check()?;
+        assert!(missing.is_empty());
+        assert!(not_indexed.is_empty());
 
-        assert_eq!(most_recent, most_recent_should_be);
+        // Looking a file up by any time in the same minute finds it.
+        assert!(arch.file_exists(&kmso, &raob, &launch(0))?);
+        assert!(arch.file_exists(&kmso, &raob, &launch(45))?);
+        assert!(!arch.retrieve(&kmso, &raob, &launch(30))?.is_empty());
+        arch.set_file_comment(&kmso, &raob, &launch(15), "Launched late.")?;
+        assert_eq!(
+            arch.file_comment(&kmso, &raob, &launch(59))?.as_deref(),
+            Some("Launched late.")
+        );
+        assert!(arch.latency(&kmso, &raob, &launch(45))?.is_some());
+
+        arch.set_trash_enabled(true);
+        arch.remove(&kmso, &raob, &launch(45))?;
+        assert!(!arch.file_exists(&kmso, &raob, &launch(0))?);
+        arch.restore(&kmso, &raob, &launch(15))?;
+        assert_eq!(
+            arch.remove_many(&[(kmso.clone(), raob.clone(), launch(30))])?,
+            1
+        );
+        assert!(!arch.file_exists(&kmso, &raob, &launch(0))?);
 
         Ok(())
     }
 
     #[test]
-    fn test_file_exists() -> Result<()> {
+    fn test_valid_time_tolerance() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
         } = create_test_archive().expect("Failed to create test archive.");
 
-        fill_test_archive(&mut arch).expect("Error filling test archive.");
-
-        let kmso = arch.site_info("kmso")?.unwrap();
-        let snd_type = arch.sounding_type_info("GFS")?.unwrap();
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
 
-        println!("Checking for files that should exist.");
-        assert!(arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2017, 4, 1).and_hms(6, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2017, 4, 1).and_hms(12, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(arch
-            .file_exists(
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let add = |arch: &Archive, init_time| {
+            arch.add_file(
                 &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0)
+                &gfs,
+                &loc,
+                &init_time,
+                &init_time,
+                "example_data/2017040106Z_gfs_kmso.buf",
             )
-            .expect("Error checking for existence"));
+        };
 
-        println!("Checking for files that should NOT exist.");
-        assert!(!arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2018, 4, 1).and_hms(0, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(!arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2018, 4, 1).and_hms(6, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(!arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2018, 4, 1).and_hms(12, 0, 0)
-            )
-            .expect("Error checking for existence"));
-        assert!(!arch
-            .file_exists(
-                &kmso,
-                &snd_type,
-                &NaiveDate::from_ymd(2018, 4, 1).and_hms(18, 0, 0)
-            )
-            .expect("Error checking for existence"));
+        // Not checked by default, so the 06Z run can be stored as the 12Z run.
+        add(&arch, hour(12))?;
+        assert_eq!(arch.count()?, 1);
+
+        arch.set_valid_time_tolerance(Some(Duration::hours(1)));
+        match add(&arch, hour(18)) {
+            Err(BufkitDataErr::ValidTimeMismatch(init_time, valid_time)) => {
+                assert_eq!(init_time, hour(18));
+                assert_eq!(valid_time, hour(6));
+            }
+            other => panic!("Expected ValidTimeMismatch, got {:?}", other),
+        }
+        assert_eq!(arch.count()?, 1);
+
+        add(&arch, hour(6))?;
+        assert_eq!(arch.count()?, 2);
+        assert!(!arch.retrieve(&kmso, &gfs, &hour(6))?.is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn test_count() {
+    fn test_reject_init_time_in_future() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
         } = create_test_archive().expect("Failed to create test archive.");
 
-        fill_test_archive(&mut arch).expect("Error filling test archive.");
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let loc = arch.validate_or_add_location(Location::new(46.87, -114.16, 1335, None))?;
+        let nam = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "NAM",
+            FileType::BUFKIT,
+            6,
+        ))?;
+
+        let far_future = NaiveDate::from_ymd_opt(2099, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let add = |arch: &Archive| {
+            arch.add_file(
+                &kmso,
+                &nam,
+                &loc,
+                &far_future,
+                &far_future,
+                "example_data/2017040112Z_nam_kmso.buf",
+            )
+        };
 
-        // 7 and not 10 because of duplicate GFS models in the input.
-        assert_eq!(arch.count().expect("db error"), 7);
+        match add(&arch) {
+            Err(BufkitDataErr::InitTimeInFuture(init_time, _)) => {
+                assert_eq!(init_time, far_future)
+            }
+            other => panic!("Expected InitTimeInFuture, got {:?}", other),
+        }
+        assert_eq!(arch.count()?, 0);
+        assert_eq!(arch.check()?, (vec![], vec![]));
+
+        arch.set_max_init_time_lead(None);
+        add(&arch)?;
+        assert_eq!(arch.count()?, 1);
+
+        Ok(())
     }
 
-    // ---------------------------------------------------------------------------------------------
-    // Add, remove, and retrieve files from the archive
-    // ---------------------------------------------------------------------------------------------
     #[test]
-    fn test_files_round_trip() -> Result<()> {
-        let TestArchive { tmp: _tmp, arch } =
-            create_test_archive().expect("Failed to create test archive.");
+    fn test_latency() -> Result<()> {
+        let TestArchive {
+            tmp: _tmp,
+            mut arch,
+        } = create_test_archive().expect("Failed to create test archive.");
 
-        let test_data = get_test_data().expect("Error loading test data.");
+        fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        for (site, sounding_type, init_time, end_time, loc, file_name) in test_data {
-            let site = arch.validate_or_add_site(site)?;
-            let sounding_type = arch.validate_or_add_sounding_type(sounding_type)?;
-            let loc = arch.validate_or_add_location(loc)?;
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
 
-            arch.add_file(
-                &site,
-                &sounding_type.clone(),
-                &loc,
-                &init_time,
-                &end_time,
-                &file_name,
-            )
-            .expect("Failure to add.");
+        // Added now, long after it was initialized.
+        let latency = arch.latency(&kmso, &gfs, &init_time)?.unwrap();
+        assert!(latency > Duration::days(365));
+
+        let loc = arch.locations_for_site_and_type(&kmso, &gfs)?[0];
+        let received_time = init_time + Duration::minutes(215);
+        arch.add_file_received_at(
+            &kmso,
+            &gfs,
+            &loc,
+            &init_time,
+            &init_time,
+            "example_data/2017040106Z_gfs_kmso.buf",
+            &received_time,
+        )?;
+        assert_eq!(
+            arch.latency(&kmso, &gfs, &init_time)?,
+            Some(Duration::minutes(215))
+        );
 
-            let site = arch
-                .site_info(site.short_name())
-                .expect("Error retrieving site.")
-                .expect("Site not in index.");
-            let sounding_type = arch
-                .sounding_type_info(sounding_type.source())
-                .expect("Error retrieving sounding_type")
-                .expect("Sounding type not in index.");
+        // Like a file added before received times were stored.
+        arch.db_conn
+            .execute("UPDATE files SET received_time = NULL", NO_PARAMS)?;
+        assert_eq!(arch.latency(&kmso, &gfs, &init_time)?, None);
 
-            let recovered_anal = arch
-                .retrieve(&site, &sounding_type, &init_time)
-                .expect("Failure to load.");
+        // Not in the archive.
+        assert!(arch
+            .latency(&kmso, &gfs, &(init_time + Duration::hours(1)))
+            .is_err());
 
-            assert_eq!(
-                recovered_anal[0].sounding().valid_time().unwrap(),
-                init_time
-            );
-        }
         Ok(())
     }
 
     #[test]
-    fn test_get_most_recent_analysis() -> Result<()> {
+    fn test_file_comment() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
@@ -1650,25 +9094,43 @@ mod unit {
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
-        let snd_type = arch
-            .sounding_type_info("GFS")?
-            .expect("Sounding type not in index");
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
 
-        let init_time = arch
-            .most_recent_init_time(&kmso, &snd_type)
-            .expect("Error getting valid time.");
+        assert_eq!(arch.file_comment(&kmso, &gfs, &init_time)?, None);
 
-        assert_eq!(init_time, NaiveDate::from_ymd(2017, 4, 1).and_hms(18, 0, 0));
+        arch.set_file_comment(&kmso, &gfs, &init_time, "QC'd, dew point spike removed")?;
+        assert_eq!(
+            arch.file_comment(&kmso, &gfs, &init_time)?.as_deref(),
+            Some("QC'd, dew point spike removed")
+        );
 
-        arch.most_recent_analysis(&kmso, &snd_type)
-            .expect("Failed to retrieve sounding.");
+        // It goes in and out of the trash with the file.
+        arch.set_trash_enabled(true);
+        arch.remove(&kmso, &gfs, &init_time)?;
+        assert!(arch.file_comment(&kmso, &gfs, &init_time).is_err());
+        arch.restore(&kmso, &gfs, &init_time)?;
+        assert_eq!(
+            arch.file_comment(&kmso, &gfs, &init_time)?.as_deref(),
+            Some("QC'd, dew point spike removed")
+        );
+
+        // Not in the archive.
+        let missing = init_time + Duration::hours(1);
+        assert!(arch
+            .set_file_comment(&kmso, &gfs, &missing, "nope")
+            .is_err());
+        assert!(arch.file_comment(&kmso, &gfs, &missing).is_err());
 
         Ok(())
     }
 
     #[test]
-    fn test_retrieve_all() -> Result<()> {
+    fn test_prune_site_older_than() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
@@ -1676,44 +9138,84 @@ mod unit {
 
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let kmso = arch.site_info("kmso")?.expect("Site not in index.");
-        let snd_type = arch
-            .sounding_type_info("GFS")?
-            .expect("Sounding type not in index");
+        // Put a copy of the GFS files at another site.
+        let kbtm = arch.validate_or_add_site(Site::new("kbtm"))?;
+        let gfs = arch.sounding_type_info("GFS")?.unwrap();
+        let loc = arch.validate_or_add_location(Location::new(45.95, -112.50, 1688, None))?;
+        for hour in &[0, 6, 18] {
+            let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(*hour, 0, 0)
+                .unwrap();
+            arch.add_file(
+                &kbtm,
+                &gfs,
+                &loc,
+                &init_time,
+                &init_time,
+                &format!("example_data/20170401{:02}Z_gfs_kmso.buf", hour),
+            )?;
+        }
+        assert_eq!(arch.count()?, 10);
 
-        let start_time = NaiveDate::from_ymd(2017, 3, 1).and_hms(1, 0, 0);
-        let end_time = NaiveDate::from_ymd(2017, 4, 1).and_hms(12, 0, 0);
+        let kmso = arch.site_info("kmso")?.unwrap();
+        let cutoff = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
 
-        let all = arch.retrieve_all(&kmso, &snd_type, &start_time, &end_time)?;
+        // GFS and NAM at 00Z and GFS at 06Z
+        assert_eq!(arch.prune_site_older_than(&kmso, &cutoff)?, 3);
+        assert_eq!(arch.count()?, 7);
+        assert!(arch
+            .iter_records()
+            .filter_map(Result::ok)
+            .filter(|rec| rec.site() == &kmso)
+            .all(|rec| rec.init_time() >= cutoff));
 
-        assert_eq!(all.len(), 3);
+        // The other site still has all its files.
+        assert_eq!(arch.inventory(&kbtm)?.count(&gfs), 3);
+
+        // Nothing left to remove
+        assert_eq!(arch.prune_site_older_than(&kmso, &cutoff)?, 0);
+
+        // A file that can't be removed is skipped, and stays in the index.
+        let (stuck, _) = arch.get_file_name_for(&kbtm, &gfs, &cutoff.with_hour(0).unwrap())?;
+        remove_file(arch.file_dir.join(&stuck))?;
+        assert!(arch.prune_site_older_than(&kbtm, &cutoff).is_err());
+        assert_eq!(arch.inventory(&kbtm)?.count(&gfs), 2);
+        assert_eq!(arch.check()?, (vec![stuck], vec![]));
 
         Ok(())
     }
 
     #[test]
-    fn test_remove_file() -> Result<()> {
+    fn test_access_tracking_off() -> Result<()> {
         let TestArchive {
             tmp: _tmp,
             mut arch,
         } = create_test_archive().expect("Failed to create test archive.");
 
+        arch.set_access_tracking(false);
         fill_test_archive(&mut arch).expect("Error filling test archive.");
 
-        let init_time = NaiveDate::from_ymd(2017, 4, 1).and_hms(0, 0, 0);
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
         let kmso = arch.site_info("kmso")?.expect("No such site.");
         let snd_type = arch
             .sounding_type_info("GFS")?
             .expect("No such sounding type.");
 
-        assert!(arch
-            .file_exists(&kmso, &snd_type, &init_time)
-            .expect("Error checking db"));
-        arch.remove(&kmso, &snd_type, &init_time)
-            .expect("Error while removing.");
-        assert!(!arch
-            .file_exists(&kmso, &snd_type, &init_time)
-            .expect("Error checking db"));
+        arch.retrieve(&kmso, &snd_type, &init_time)?;
+
+        let num_tracked: i64 = arch.db_conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE last_accessed IS NOT NULL",
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        assert_eq!(num_tracked, 0);
 
         Ok(())
     }
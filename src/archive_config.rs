@@ -0,0 +1,155 @@
+//! Module for the settings used to lay out a new archive on disk.
+use crate::{
+    errors::{BufkitDataErr, Result},
+    file_name_template::FileNameTemplate,
+};
+use std::{fs::read_to_string, io::ErrorKind, path::Path};
+
+/// Settings for creating a new archive.
+///
+/// By default files are stored in a directory called `files` and the index is a file called
+/// `index.sqlite`, both in the root directory of the archive. These can be changed to match an
+/// existing directory layout. The names are saved in a `.archive-config` file in the root
+/// directory so `Archive::connect` can find them again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveConfig {
+    /// Name of the directory in the root that holds the files.
+    file_dir: String,
+    /// Name of the index file in the root.
+    index: String,
+    /// Template for naming the files, validated when the archive is created.
+    file_name_template: String,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            file_dir: Self::DEFAULT_FILE_DIR.to_owned(),
+            index: Self::DEFAULT_INDEX.to_owned(),
+            file_name_template: FileNameTemplate::DEFAULT.to_owned(),
+        }
+    }
+}
+
+impl ArchiveConfig {
+    const DEFAULT_FILE_DIR: &'static str = "files";
+    const DEFAULT_INDEX: &'static str = "index.sqlite";
+
+    /// The name of the file in the root directory the config is saved in.
+    pub(crate) const CONFIG_FILE: &'static str = ".archive-config";
+
+    /// Set the name of the directory the files are stored in.
+    #[inline]
+    pub fn with_file_dir(self, file_dir: &str) -> Self {
+        ArchiveConfig {
+            file_dir: file_dir.to_owned(),
+            ..self
+        }
+    }
+
+    /// Set the name of the index file.
+    #[inline]
+    pub fn with_index(self, index: &str) -> Self {
+        ArchiveConfig {
+            index: index.to_owned(),
+            ..self
+        }
+    }
+
+    /// Set the template used to name the files, see `Archive::create_with_file_name_template`.
+    #[inline]
+    pub fn with_file_name_template(self, template: &str) -> Self {
+        ArchiveConfig {
+            file_name_template: template.to_owned(),
+            ..self
+        }
+    }
+
+    /// The name of the directory the files are stored in.
+    #[inline]
+    pub fn file_dir(&self) -> &str {
+        &self.file_dir
+    }
+
+    /// The name of the index file.
+    #[inline]
+    pub fn index(&self) -> &str {
+        &self.index
+    }
+
+    /// The template used to name the files.
+    #[inline]
+    pub fn file_name_template(&self) -> &str {
+        &self.file_name_template
+    }
+
+    /// Check the names are usable, each must be a single, distinct entry in the root directory.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(BufkitDataErr::InvalidArchiveConfig(msg));
+
+        for name in [&self.file_dir, &self.index] {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                return invalid(format!(
+                    "'{}' is not a valid name in the root directory",
+                    name
+                ));
+            }
+
+            if name == Self::CONFIG_FILE {
+                return invalid(format!("'{}' is reserved", name));
+            }
+        }
+
+        if self.file_dir == self.index {
+            return invalid("the file directory and index must have different names".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Load the config saved in the root directory of an archive.
+    ///
+    /// Archives created before the config file existed don't have one, so the defaults are used
+    /// if it is not there.
+    pub(crate) fn load(root: &Path) -> Result<Self> {
+        let text = match read_to_string(root.join(Self::CONFIG_FILE)) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(BufkitDataErr::from(err)),
+        };
+
+        let mut config = Self::default();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| {
+                    BufkitDataErr::InvalidArchiveConfig(format!("malformed line '{}'", line))
+                })?;
+
+            match key {
+                "file_dir" => config.file_dir = value.to_owned(),
+                "index" => config.index = value.to_owned(),
+                _ => {
+                    return Err(BufkitDataErr::InvalidArchiveConfig(format!(
+                        "unknown key '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Save the names of the file directory and index in the root directory of an archive.
+    ///
+    /// The file name template is stored in the index, so it is not saved here.
+    pub(crate) fn save(&self, root: &Path) -> Result<()> {
+        let text = format!("file_dir = {}\nindex = {}\n", self.file_dir, self.index);
+        std::fs::write(root.join(Self::CONFIG_FILE), text)?;
+
+        Ok(())
+    }
+}
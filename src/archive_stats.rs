@@ -0,0 +1,59 @@
+//! Module for a quick summary of the size of the archive.
+use chrono::NaiveDateTime;
+
+/// A summary of what is in the archive.
+///
+/// Created by `Archive::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// The number of files in the index.
+    pub(crate) file_count: usize,
+    /// The number of sites in the index.
+    pub(crate) site_count: usize,
+    /// The number of sounding types in the index.
+    pub(crate) sounding_type_count: usize,
+    /// The number of locations in the index.
+    pub(crate) location_count: usize,
+    /// The total size of the stored files.
+    pub(crate) stored_bytes: u64,
+    /// The earliest and latest initialization time of any file.
+    pub(crate) init_time_range: Option<(NaiveDateTime, NaiveDateTime)>,
+}
+
+impl ArchiveStats {
+    /// The number of files in the index, the same as `Archive::count`.
+    #[inline]
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    /// The number of sites in the index, including any without files.
+    #[inline]
+    pub fn site_count(&self) -> usize {
+        self.site_count
+    }
+
+    /// The number of sounding types in the index, including any without files.
+    #[inline]
+    pub fn sounding_type_count(&self) -> usize {
+        self.sounding_type_count
+    }
+
+    /// The number of locations in the index, including any without files.
+    #[inline]
+    pub fn location_count(&self) -> usize {
+        self.location_count
+    }
+
+    /// The total size in bytes of everything in the blob store, as it is stored.
+    #[inline]
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes
+    }
+
+    /// The earliest and latest initialization time of any file, `None` if the archive is empty.
+    #[inline]
+    pub fn init_time_range(&self) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        self.init_time_range
+    }
+}
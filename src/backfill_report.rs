@@ -0,0 +1,43 @@
+//! Module for reporting the results of filling in the runs missing from the archive.
+use crate::{errors::BufkitDataErr, site::Site, sounding_type::SoundingType};
+use chrono::NaiveDateTime;
+
+/// The results of trying to fill in the runs missing from the archive.
+///
+/// Created by `Archive::backfill_missing`. Each run is identified by its `Site`, `SoundingType`,
+/// and initialization time.
+#[derive(Debug, Default)]
+pub struct BackfillReport {
+    /// Runs that were fetched and added to the archive.
+    pub(crate) filled: Vec<(Site, SoundingType, NaiveDateTime)>,
+    /// Runs the fetcher didn't have.
+    pub(crate) still_missing: Vec<(Site, SoundingType, NaiveDateTime)>,
+    /// Runs that failed to fetch or add, with the error.
+    pub(crate) errored: Vec<(Site, SoundingType, NaiveDateTime, BufkitDataErr)>,
+}
+
+impl BackfillReport {
+    /// The runs that were fetched and added to the archive.
+    #[inline]
+    pub fn filled(&self) -> &[(Site, SoundingType, NaiveDateTime)] {
+        &self.filled
+    }
+
+    /// The runs the fetcher didn't have, so they are still missing from the archive.
+    #[inline]
+    pub fn still_missing(&self) -> &[(Site, SoundingType, NaiveDateTime)] {
+        &self.still_missing
+    }
+
+    /// The runs where the fetcher returned an error, or the fetched data couldn't be added to the
+    /// archive.
+    #[inline]
+    pub fn errored(&self) -> &[(Site, SoundingType, NaiveDateTime, BufkitDataErr)] {
+        &self.errored
+    }
+
+    /// `true` if every missing run was filled.
+    pub fn is_complete(&self) -> bool {
+        self.still_missing.is_empty() && self.errored.is_empty()
+    }
+}
@@ -0,0 +1,104 @@
+//! Module for the storage backing the files in the archive.
+use crate::errors::Result;
+use std::{
+    fmt::Debug,
+    fs::{read_dir, remove_file, rename, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Somewhere to keep the files in the archive, stored by name as opaque blobs of bytes.
+///
+/// The archive only ever hands a store the compressed bytes and the file name, the index stays in
+/// the local sqlite database. The default is a `FileSystemBlobStore` in the files directory of the
+/// archive, use `Archive::set_blob_store` to replace it.
+pub trait BlobStore: Debug {
+    /// Store a blob, replacing any blob with the same name.
+    ///
+    /// If this returns an error, any blob already stored under the name should be left as it was.
+    fn put(&self, name: &str, data: &mut dyn Read) -> Result<()>;
+
+    /// Read a blob, it is an error if there isn't one with this name.
+    fn get(&self, name: &str) -> Result<Box<dyn Read>>;
+
+    /// Delete a blob, it is an error if there isn't one with this name.
+    fn delete(&self, name: &str) -> Result<()>;
+
+    /// The names of all the blobs in the store.
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// The size of a blob in bytes.
+    ///
+    /// The default reads the whole blob, stores that can look up the size directly should
+    /// override it.
+    fn size(&self, name: &str) -> Result<u64> {
+        Ok(std::io::copy(&mut self.get(name)?, &mut std::io::sink())?)
+    }
+}
+
+/// A `BlobStore` keeping each blob as a file in a directory.
+#[derive(Debug, Clone)]
+pub struct FileSystemBlobStore {
+    dir: PathBuf,
+}
+
+impl FileSystemBlobStore {
+    // Added to the name of a blob while it is being written.
+    const PARTIAL_EXT: &'static str = ".partial";
+
+    /// Create a store in a directory, which must already exist.
+    pub fn new<T: AsRef<Path>>(dir: T) -> Self {
+        FileSystemBlobStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The directory the blobs are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl BlobStore for FileSystemBlobStore {
+    /// The blob is written to a temporary file and synced to disk before it is renamed into
+    /// place, so running out of space part way through doesn't truncate an existing blob.
+    fn put(&self, name: &str, data: &mut dyn Read) -> Result<()> {
+        let partial = self.dir.join(format!("{}{}", name, Self::PARTIAL_EXT));
+
+        let written = File::create(&partial).and_then(|mut file| {
+            std::io::copy(data, &mut file)?;
+            file.sync_all()
+        });
+        if let Err(err) = written.and_then(|_| rename(&partial, self.dir.join(name))) {
+            let _ = remove_file(&partial);
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(self.dir.join(name))?))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        Ok(remove_file(self.dir.join(name))?)
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let names = read_dir(&self.dir)?
+            .filter_map(|de| de.ok())
+            .map(|de| de.path())
+            .filter(|p| p.is_file())
+            .filter_map(|p| p.file_name().map(|f| f.to_string_lossy().to_string()))
+            // Left behind by an interrupted put, not a blob.
+            .filter(|name| !name.ends_with(Self::PARTIAL_EXT))
+            .collect();
+
+        Ok(names)
+    }
+
+    fn size(&self, name: &str) -> Result<u64> {
+        Ok(self.dir.join(name).metadata()?.len())
+    }
+}
@@ -0,0 +1,33 @@
+//! Module for the codecs used to store files in the archive.
+use flate2::read::GzDecoder;
+use std::io::Read;
+use strum_macros::{AsStaticStr, EnumString};
+
+/// Flag for how a file is encoded when it is stored in the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, EnumString, AsStaticStr)]
+pub enum Codec {
+    /// Compressed with gzip, this is the default.
+    #[default]
+    Gzip,
+    /// Stored as is without any compression.
+    None,
+}
+
+impl Codec {
+    /// The extension, including the leading '.', for files stored with this codec.
+    #[inline]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => ".gz",
+            Codec::None => "",
+        }
+    }
+
+    /// Wrap a reader of data stored with this codec so that it reads the original bytes.
+    pub(crate) fn decoder<R: Read + 'static>(self, src: R) -> Box<dyn Read> {
+        match self {
+            Codec::Gzip => Box::new(GzDecoder::new(src)),
+            Codec::None => Box::new(src),
+        }
+    }
+}
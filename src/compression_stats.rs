@@ -0,0 +1,45 @@
+//! Module for reporting how well the files in the archive are compressed.
+
+/// The compressed and uncompressed sizes of a series of files.
+///
+/// Created by `Archive::compression_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The number of files in the series.
+    pub(crate) file_count: usize,
+    /// The total size of the files as stored in the archive.
+    pub(crate) compressed_bytes: u64,
+    /// The total size of the files once decoded.
+    pub(crate) uncompressed_bytes: u64,
+}
+
+impl CompressionStats {
+    /// The number of files in the series.
+    #[inline]
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    /// The total size in bytes of the files as stored in the archive.
+    #[inline]
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compressed_bytes
+    }
+
+    /// The total size in bytes of the files once decoded.
+    #[inline]
+    pub fn uncompressed_bytes(&self) -> u64 {
+        self.uncompressed_bytes
+    }
+
+    /// The uncompressed size divided by the compressed size, so larger is better.
+    ///
+    /// Files stored without compression have a ratio of about 1, as does an empty series.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Module for comparing which initialization times are available for two sounding types.
+use crate::{
+    errors::{BufkitDataErr, Result},
+    site::Site,
+    sounding_type::SoundingType,
+};
+use chrono::{Duration, NaiveDateTime};
+use rusqlite::Connection;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+};
+
+/// The initialization times available for a site from two sounding types, split into the times
+/// that both have and the times only one of them has.
+///
+/// This is useful for finding cases to verify a model against observations. All the lists are
+/// sorted in ascending order.
+#[derive(Debug)]
+pub struct CoverageComparison {
+    /// Init times present for both sounding types.
+    in_both: Vec<NaiveDateTime>,
+    /// Init times only present for the first sounding type.
+    only_a: Vec<NaiveDateTime>,
+    /// Init times only present for the second sounding type.
+    only_b: Vec<NaiveDateTime>,
+}
+
+impl CoverageComparison {
+    /// Init times present for both sounding types.
+    pub fn in_both(&self) -> &[NaiveDateTime] {
+        &self.in_both
+    }
+
+    /// Init times only present for the first sounding type.
+    pub fn only_a(&self) -> &[NaiveDateTime] {
+        &self.only_a
+    }
+
+    /// Init times only present for the second sounding type.
+    pub fn only_b(&self) -> &[NaiveDateTime] {
+        &self.only_b
+    }
+}
+
+/// Compare the initialization times in the index for two sounding types at a site.
+pub(crate) fn compare_coverage(
+    db: &Connection,
+    site: &Site,
+    type_a: &SoundingType,
+    type_b: &SoundingType,
+) -> Result<CoverageComparison> {
+    debug_assert!(site.id() > 0);
+    debug_assert!(type_a.id() > 0);
+    debug_assert!(type_b.id() > 0);
+
+    let times_a = init_times(db, site, type_a)?;
+    let times_b = init_times(db, site, type_b)?;
+
+    Ok(CoverageComparison {
+        in_both: times_a.intersection(&times_b).cloned().collect(),
+        only_a: times_a.difference(&times_b).cloned().collect(),
+        only_b: times_b.difference(&times_a).cloned().collect(),
+    })
+}
+
+/// Collapse the initialization times for a sounding type at a site into inclusive ranges of
+/// consecutive runs.
+///
+/// Without a known interval between initializations there is no way to tell which times are
+/// consecutive, so each time is returned as its own range.
+pub(crate) fn coverage_runs(
+    db: &Connection,
+    site: &Site,
+    sounding_type: &SoundingType,
+) -> Result<Vec<(NaiveDateTime, NaiveDateTime)>> {
+    debug_assert!(site.id() > 0);
+    debug_assert!(sounding_type.id() > 0);
+
+    let delta_t = sounding_type
+        .hours_between_initializations()
+        .map(|hours| Duration::hours(i64::from(hours)));
+
+    let mut runs: Vec<(NaiveDateTime, NaiveDateTime)> = vec![];
+    for init_time in init_times(db, site, sounding_type)? {
+        match (runs.last_mut(), delta_t) {
+            (Some((_, end)), Some(delta_t)) if *end + delta_t == init_time => *end = init_time,
+            _ => runs.push((init_time, init_time)),
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Get the average number of files per day over the period of record for each sounding type at a
+/// site.
+///
+/// The period of record counts calendar days, including the first and last days, so a series
+/// that is all on one day has one day in its range.
+pub(crate) fn data_density(db: &Connection, site: &Site) -> Result<Vec<(SoundingType, f64)>> {
+    debug_assert!(site.id() > 0);
+
+    let mut stmt = db.prepare(
+        "
+            SELECT type_id, COUNT(*), MIN(init_time), MAX(init_time)
+            FROM files
+            WHERE site_id = ?1
+            GROUP BY type_id
+        ",
+    )?;
+
+    let rows: Result<Vec<(i64, i64, NaiveDateTime, NaiveDateTime)>> = stmt
+        .query_map([site.id()], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    let mut densities = vec![];
+    for (type_id, count, first, last) in rows? {
+        let sounding_type = crate::sounding_type::retrieve_sounding_type_by_id(db, type_id)?;
+        let days = (last.date() - first.date()).num_days() + 1;
+        densities.push((sounding_type, count as f64 / days as f64));
+    }
+    densities.sort_by(|(a, _), (b, _)| a.source().cmp(b.source()));
+
+    Ok(densities)
+}
+
+/// Find the most common number of hours between consecutive initialization times of a sounding
+/// type, looking at each site separately.
+///
+/// Ties go to the shorter interval. Returns `None` if no site has at least two runs.
+pub(crate) fn infer_interval(db: &Connection, sounding_type: &SoundingType) -> Result<Option<u16>> {
+    debug_assert!(sounding_type.id() > 0);
+
+    let mut stmt = db.prepare(
+        "
+            SELECT site_id, init_time
+            FROM files
+            WHERE type_id = ?1
+            ORDER BY site_id ASC, init_time ASC
+        ",
+    )?;
+
+    let rows: Result<Vec<(i64, NaiveDateTime)>> = stmt
+        .query_map([sounding_type.id()], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    let mut spacing_counts: BTreeMap<i64, usize> = BTreeMap::new();
+    for pair in rows?.windows(2) {
+        let ((site_a, time_a), (site_b, time_b)) = (pair[0], pair[1]);
+        if site_a != site_b {
+            continue;
+        }
+
+        let hours = (time_b - time_a).num_hours();
+        if hours > 0 {
+            *spacing_counts.entry(hours).or_insert(0) += 1;
+        }
+    }
+
+    // Iterating in ascending order and only replacing on a strictly higher count breaks ties in
+    // favor of the shorter interval.
+    let mut mode: Option<(i64, usize)> = None;
+    for (hours, count) in spacing_counts {
+        if mode.map(|(_, max_count)| count > max_count).unwrap_or(true) {
+            mode = Some((hours, count));
+        }
+    }
+
+    Ok(mode.and_then(|(hours, _)| u16::try_from(hours).ok()))
+}
+
+/// Find the initialization times of a sounding type that every one of `sites` has, or every site
+/// with any files of the type if `sites` is empty, sorted in ascending order.
+pub(crate) fn common_init_times(
+    db: &Connection,
+    sounding_type: &SoundingType,
+    sites: &[Site],
+) -> Result<Vec<NaiveDateTime>> {
+    debug_assert!(sounding_type.id() > 0);
+
+    let site_ids: Vec<i64> = if sites.is_empty() {
+        let mut stmt = db.prepare("SELECT DISTINCT site_id FROM files WHERE type_id = ?1")?;
+        let site_ids: Result<Vec<i64>> = stmt
+            .query_map([sounding_type.id()], |row| row.get(0))?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+        site_ids?
+    } else {
+        sites.iter().map(Site::id).collect()
+    };
+
+    let mut stmt = db.prepare(
+        "
+            SELECT init_time
+            FROM files
+            WHERE site_id = ?1 AND type_id = ?2
+        ",
+    )?;
+
+    let mut common: Option<BTreeSet<NaiveDateTime>> = None;
+    for site_id in site_ids {
+        let times: Result<BTreeSet<NaiveDateTime>> = stmt
+            .query_map([site_id, sounding_type.id()], |row| row.get(0))?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+        let times = times?;
+
+        common = Some(match common {
+            Some(common) => common.intersection(&times).cloned().collect(),
+            None => times,
+        });
+    }
+
+    Ok(common.unwrap_or_default().into_iter().collect())
+}
+
+/// Get every initialization time expected from the cadence of a sounding type in the inclusive
+/// window `start` to `end` that isn't in the index for a site.
+///
+/// The expected times are whole steps of the interval from 00Z, so the window is rounded in to the
+/// nearest steps. Types without an interval are empty.
+pub(crate) fn missing_between(
+    db: &Connection,
+    site: &Site,
+    sounding_type: &SoundingType,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+) -> Result<Vec<NaiveDateTime>> {
+    debug_assert!(site.id() > 0);
+    debug_assert!(sounding_type.id() > 0);
+
+    let delta_t = match sounding_type.hours_between_initializations() {
+        Some(hours) if hours > 0 => Duration::hours(i64::from(hours)),
+        _ => return Ok(vec![]),
+    };
+
+    let present = init_times(db, site, sounding_type)?;
+
+    // Round the start up to the next whole step from 00Z.
+    let midnight = start
+        .date()
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is always a valid time.");
+    let step = delta_t.num_seconds();
+    let steps = ((*start - midnight).num_seconds() + step - 1) / step;
+    let mut init_time = midnight + Duration::seconds(steps * step);
+
+    let mut missing = vec![];
+    while init_time <= *end {
+        if !present.contains(&init_time) {
+            missing.push(init_time);
+        }
+        init_time += delta_t;
+    }
+
+    Ok(missing)
+}
+
+fn init_times(
+    db: &Connection,
+    site: &Site,
+    sounding_type: &SoundingType,
+) -> Result<BTreeSet<NaiveDateTime>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT init_time
+            FROM files
+            WHERE site_id = ?1 AND type_id = ?2
+        ",
+    )?;
+
+    let times: Result<BTreeSet<NaiveDateTime>> = stmt
+        .query_map([site.id(), sounding_type.id()], |row| row.get(0))?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    times
+}
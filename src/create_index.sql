@@ -1,11 +1,12 @@
 BEGIN;
 
 CREATE TABLE types (
-    id        INTEGER PRIMARY KEY,  -- Used as foreign key in other tables
-    type      TEXT UNIQUE NOT NULL, -- GFS, NAM, NAM4KM, MOBIL, RAWINSONDE,
-    file_type TEXT        NOT NULL, -- BUFR, BUFKIT, etc.
-    interval  INTEGER,              -- Hours between model runs/launches/etc.
-    observed  INT NOT NULL          -- 0 if false (e.g. model data), 1 if observed
+    id         INTEGER PRIMARY KEY,  -- Used as foreign key in other tables
+    type       TEXT UNIQUE NOT NULL, -- GFS, NAM, NAM4KM, MOBIL, RAWINSONDE,
+    file_type  TEXT        NOT NULL, -- BUFR, BUFKIT, etc.
+    interval   INTEGER,              -- Hours between model runs/launches/etc.
+    observed   INT NOT NULL,         -- 0 if false (e.g. model data), 1 if observed
+    source_url TEXT DEFAULT NULL     -- Where the data comes from, e.g. a provider URL pattern
 );
 
 CREATE TABLE sites (
@@ -19,24 +20,52 @@ CREATE TABLE sites (
 
 CREATE TABLE locations (
     id                INTEGER PRIMARY KEY,
-    latitude          INTEGER DEFAULT NULL, -- Decimal degrees * 1,000,000 and truncated
-    longitude         INTEGER DEFAULT NULL, -- Decimal degrees * 1,000,000 and truncated
+    latitude          INTEGER DEFAULT NULL, -- Decimal degrees * 1,000,000 and rounded
+    longitude         INTEGER DEFAULT NULL, -- Decimal degrees * 1,000,000 and rounded
     elevation_meters  INT     DEFAULT NULL, 
     tz_offset_seconds INT     DEFAULT NULL  -- Offset from UTC in seconds
 );
 
 CREATE TABLE files (
-    type_id     INTEGER     NOT NULL,
-    site_id     INTEGER     NOT NULL,
-    location_id INTEGER     NOT NULL,
-    init_time   TEXT        NOT NULL,
-    end_time    TEXT        NOT NULL,
-    file_name   TEXT UNIQUE NOT NULL,
+    type_id       INTEGER     NOT NULL,
+    site_id       INTEGER     NOT NULL,
+    location_id   INTEGER     NOT NULL,
+    init_time     TEXT        NOT NULL,
+    end_time      TEXT        NOT NULL,
+    file_name     TEXT UNIQUE NOT NULL,
+    codec         TEXT        NOT NULL DEFAULT 'Gzip', -- How the file is compressed
+    last_accessed TEXT        DEFAULT NULL,    -- Last time the file was read, NULL if never tracked
+    checksum      INTEGER     DEFAULT NULL,    -- CRC32 of the uncompressed file, NULL if unknown
+    received_time TEXT        DEFAULT NULL,    -- When the file was published or downloaded
+    profile_count INTEGER     DEFAULT NULL,    -- Soundings in the file, NULL until counted
+    comment       TEXT        DEFAULT NULL,    -- A freeform note about the file
     FOREIGN KEY (type_id)     REFERENCES types(id),
     FOREIGN KEY (site_id)     REFERENCES sites(id),
     FOREIGN KEY (location_id) REFERENCES locations(id)
 );
 
+-- Files removed while the trash is enabled, with the same columns as files.
+CREATE TABLE trash (
+    type_id       INTEGER     NOT NULL,
+    site_id       INTEGER     NOT NULL,
+    location_id   INTEGER     NOT NULL,
+    init_time     TEXT        NOT NULL,
+    end_time      TEXT        NOT NULL,
+    file_name     TEXT UNIQUE NOT NULL,
+    codec         TEXT        NOT NULL,
+    last_accessed TEXT        DEFAULT NULL,
+    checksum      INTEGER     DEFAULT NULL,
+    received_time TEXT        DEFAULT NULL,
+    profile_count INTEGER     DEFAULT NULL,
+    comment       TEXT        DEFAULT NULL,
+    deleted_at    TEXT        NOT NULL     -- When the file was moved to the trash
+);
+
+CREATE TABLE meta (
+    key   TEXT PRIMARY KEY, -- Name of the setting, e.g. file_name_template
+    value TEXT NOT NULL
+);
+
 -- For fast searches by file name.
 CREATE UNIQUE INDEX fname ON files(file_name);  
 
@@ -46,4 +75,7 @@ CREATE UNIQUE INDEX no_dups_files ON files(type_id, site_id, init_time);
 -- Force unique locations
 CREATE UNIQUE INDEX no_dups_locations ON locations(latitude, longitude, elevation_meters);
 
+-- Indexes without this truncated the coordinates instead of rounding them.
+INSERT INTO meta (key, value) VALUES ('coordinates', 'rounded');
+
 COMMIT;
\ No newline at end of file
@@ -0,0 +1,55 @@
+//! Module for decoding the files stored in the archive into soundings.
+use crate::{errors::Result, sounding_type::FileType};
+use fnv::FnvHashMap;
+use sounding_analysis::Analysis;
+use sounding_bufkit::BufkitData;
+use std::str::from_utf8;
+
+/// Decodes the uncompressed contents of a file into soundings.
+///
+/// Register an implementation with `Archive::register_decoder` to store and retrieve a `FileType`
+/// the crate doesn't know how to decode.
+pub trait SoundingDecoder {
+    /// Decode the bytes of a file, `description` is used as the source description of the
+    /// soundings.
+    fn decode(&self, bytes: &[u8], description: &str) -> Result<Vec<Analysis>>;
+}
+
+/// The decoder for BUFKIT files.
+struct BufkitDecoder;
+
+impl SoundingDecoder for BufkitDecoder {
+    fn decode(&self, bytes: &[u8], description: &str) -> Result<Vec<Analysis>> {
+        let bufkit_str = from_utf8(bytes)?;
+        let bufkit_data = BufkitData::init(bufkit_str, description)?;
+        let bufkit_anals: Vec<Analysis> = bufkit_data.into_iter().collect();
+        Ok(bufkit_anals)
+    }
+}
+
+/// The decoders for each `FileType`.
+pub(crate) struct Decoders(FnvHashMap<FileType, Box<dyn SoundingDecoder>>);
+
+impl Decoders {
+    pub(crate) fn insert(&mut self, file_type: FileType, decoder: Box<dyn SoundingDecoder>) {
+        self.0.insert(file_type, decoder);
+    }
+
+    pub(crate) fn get(&self, file_type: FileType) -> Option<&dyn SoundingDecoder> {
+        self.0.get(&file_type).map(|decoder| decoder.as_ref())
+    }
+}
+
+impl Default for Decoders {
+    fn default() -> Self {
+        let mut decoders = Decoders(FnvHashMap::default());
+        decoders.insert(FileType::BUFKIT, Box::new(BufkitDecoder));
+        decoders
+    }
+}
+
+impl std::fmt::Debug for Decoders {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
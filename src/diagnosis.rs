@@ -0,0 +1,81 @@
+//! Module for reporting on the health of the archive.
+use crate::location::Location;
+
+/// A report of every inconsistency found in the archive.
+///
+/// Created by `Archive::diagnose`.
+#[derive(Debug, Default)]
+pub struct Diagnosis {
+    /// Files in the index that are not on the file system.
+    pub(crate) missing_files: Vec<String>,
+    /// Files on the file system that are not in the index.
+    pub(crate) unindexed_files: Vec<String>,
+    /// Files that could not be decompressed, `None` if they were not checked.
+    pub(crate) corrupt_files: Option<Vec<String>>,
+    /// Files that don't match their stored checksum, `None` if they were not checked.
+    pub(crate) checksum_mismatches: Option<Vec<String>>,
+    /// Locations that no file in the index refers to.
+    pub(crate) orphan_locations: Vec<Location>,
+    /// Groups of files for the same site and type with init times that only differ by less than a
+    /// minute, and so should be the same file.
+    pub(crate) duplicate_init_times: Vec<Vec<String>>,
+    /// Files whose name doesn't match the init time in the index.
+    pub(crate) init_time_mismatches: Vec<String>,
+}
+
+impl Diagnosis {
+    /// `true` if no problems were found.
+    pub fn is_healthy(&self) -> bool {
+        let none_or_empty =
+            |v: &Option<Vec<String>>| v.as_ref().map(|v| v.is_empty()).unwrap_or(true);
+
+        self.missing_files.is_empty()
+            && self.unindexed_files.is_empty()
+            && none_or_empty(&self.corrupt_files)
+            && none_or_empty(&self.checksum_mismatches)
+            && self.orphan_locations.is_empty()
+            && self.duplicate_init_times.is_empty()
+            && self.init_time_mismatches.is_empty()
+    }
+
+    /// Files in the index that are missing from the file system.
+    pub fn missing_files(&self) -> &[String] {
+        &self.missing_files
+    }
+
+    /// Files on the file system that are not in the index.
+    pub fn unindexed_files(&self) -> &[String] {
+        &self.unindexed_files
+    }
+
+    /// Files that could not be decompressed.
+    ///
+    /// This is `None` if the contents of the files were not checked.
+    pub fn corrupt_files(&self) -> Option<&[String]> {
+        self.corrupt_files.as_deref()
+    }
+
+    /// Files whose contents don't match the checksum stored when they were added.
+    ///
+    /// This is `None` if the contents of the files were not checked. Files without a stored
+    /// checksum are not included, see `Archive::backfill_checksums`.
+    pub fn checksum_mismatches(&self) -> Option<&[String]> {
+        self.checksum_mismatches.as_deref()
+    }
+
+    /// Locations in the index that no file refers to.
+    pub fn orphan_locations(&self) -> &[Location] {
+        &self.orphan_locations
+    }
+
+    /// Groups of file names for the same site and sounding type whose initialization times are in
+    /// the same minute, so they map to the same name on the file system.
+    pub fn duplicate_init_times(&self) -> &[Vec<String>] {
+        &self.duplicate_init_times
+    }
+
+    /// Files whose name can't be parsed or has a different initialization time than the index.
+    pub fn init_time_mismatches(&self) -> &[String] {
+        &self.init_time_mismatches
+    }
+}
@@ -1,7 +1,12 @@
 //! Module for errors.
-use crate::{location::Location, site::Site, sounding_type::SoundingType};
+use crate::{
+    location::Location,
+    site::Site,
+    sounding_type::{FileType, SoundingType},
+};
+use chrono::NaiveDateTime;
 use sounding_analysis::AnalysisError;
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, path::PathBuf};
 
 pub type Result<T> = std::result::Result<T, BufkitDataErr>;
 
@@ -36,9 +41,9 @@ pub enum BufkitDataErr {
     //
     // My own errors from this crate
     //
-    /// Not enough data to complete the task.
-    NotEnoughData,
-    /// No such site in the database.
+    /// Not enough data to complete the task, with a description of what was missing.
+    NotEnoughData(String),
+    /// No such site in the database, or a site that can't be added to it.
     InvalidSite(Site),
     /// No such sounding type in the index.
     InvalidSoundingType(SoundingType),
@@ -46,6 +51,32 @@ pub enum BufkitDataErr {
     InvalidLocation(Location),
     /// Unknown file type
     UnknownFileType,
+    /// A file name template that cannot be used, with the reason why.
+    InvalidFileNameTemplate(String, String),
+    /// The archive config is not valid, with the reason why.
+    InvalidArchiveConfig(String),
+    /// There is no archive in this directory.
+    ArchiveNotFound(PathBuf),
+    /// A file is already in the index for a fixed site at a different location, the existing
+    /// location is first.
+    LocationConflict(Location, Location),
+    /// The tables in the index don't match what this version of the crate expects, with a
+    /// description of each difference.
+    SchemaMismatch(Vec<String>),
+    /// A path the archive depends on can't be used, with the reason why.
+    BadArchivePath(PathBuf, String),
+    /// An error while working on a file in the archive, with the name of the file.
+    InFile(String, Box<BufkitDataErr>),
+    /// An init time further in the future than the archive allows, with the latest allowed time.
+    InitTimeInFuture(NaiveDateTime, NaiveDateTime),
+    /// A file could not be decoded as its declared type, which is first, and its contents look
+    /// like the second type.
+    WrongFileType(FileType, FileType),
+    /// The init time a file was added with, first, is too far from the valid time of the first
+    /// sounding in it, second.
+    ValidTimeMismatch(NaiveDateTime, NaiveDateTime),
+    /// A site with this short name is already in the index.
+    SiteExists(Site),
 }
 
 impl Display for BufkitDataErr {
@@ -62,7 +93,7 @@ impl Display for BufkitDataErr {
             StrumError(err) => write!(f, "error forwarded from strum crate: {}", err),
             GeneralError(msg) => write!(f, "general error forwarded: {}", msg),
 
-            NotEnoughData => write!(f, "not enough data to complete task"),
+            NotEnoughData(what) => write!(f, "not enough data to complete task: {}", what),
             InvalidSite(site) => write!(f, "no such site in the index: {}", site.short_name()),
             InvalidSoundingType(st) => {
                 write!(f, "no such sounding type in the index: {}", st.source())
@@ -75,6 +106,44 @@ impl Display for BufkitDataErr {
                 loc.elevation()
             ),
             UnknownFileType => write!(f, "unkown file type for"),
+            InvalidFileNameTemplate(template, reason) => {
+                write!(f, "invalid file name template '{}': {}", template, reason)
+            }
+            InvalidArchiveConfig(reason) => write!(f, "invalid archive config: {}", reason),
+            ArchiveNotFound(root) => write!(f, "no archive found at: {}", root.display()),
+            LocationConflict(existing, new) => write!(
+                f,
+                "file already in the index at a different location: ({}, {}) vs ({}, {})",
+                existing.latitude(),
+                existing.longitude(),
+                new.latitude(),
+                new.longitude()
+            ),
+            SchemaMismatch(problems) => {
+                write!(f, "unexpected index schema: {}", problems.join(", "))
+            }
+            BadArchivePath(path, reason) => write!(f, "{}: {}", path.display(), reason),
+            InFile(file_name, err) => write!(f, "error in file {}: {}", file_name, err),
+            InitTimeInFuture(init_time, latest) => write!(
+                f,
+                "init time {} is after the latest allowed time {}",
+                init_time, latest
+            ),
+            WrongFileType(declared, detected) => write!(
+                f,
+                "file declared as {:?} could not be decoded, it looks like {:?}",
+                declared, detected
+            ),
+            ValidTimeMismatch(init_time, valid_time) => write!(
+                f,
+                "init time {} doesn't match the first valid time in the file {}",
+                init_time, valid_time
+            ),
+            SiteExists(site) => write!(
+                f,
+                "a site is already in the index with short name: {}",
+                site.short_name()
+            ),
         }
     }
 }
@@ -90,11 +159,22 @@ impl Error for BufkitDataErr {
             Database(err) => Some(err),
             StrumError(err) => Some(err),
             GeneralError(_) => None,
-            NotEnoughData => None,
+            NotEnoughData(_) => None,
             InvalidSite(_) => None,
             InvalidSoundingType(_) => None,
             InvalidLocation(_) => None,
             UnknownFileType => None,
+            InvalidFileNameTemplate(_, _) => None,
+            InvalidArchiveConfig(_) => None,
+            ArchiveNotFound(_) => None,
+            LocationConflict(_, _) => None,
+            SchemaMismatch(_) => None,
+            BadArchivePath(_, _) => None,
+            InFile(_, err) => Some(err.as_ref()),
+            InitTimeInFuture(_, _) => None,
+            WrongFileType(_, _) => None,
+            ValidTimeMismatch(_, _) => None,
+            SiteExists(_) => None,
         }
     }
 }
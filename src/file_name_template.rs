@@ -0,0 +1,323 @@
+//! Module for the template used to name the files stored in the archive.
+use crate::{
+    codec::Codec,
+    errors::{BufkitDataErr, Result},
+};
+use chrono::NaiveDateTime;
+
+/// A parsed template for naming files in the archive.
+///
+/// The template is plain text with placeholders that are replaced when naming a file:
+///
+///  - `{time}` the initialization time formatted as `%Y-%m-%dT%H%MZ`,
+///  - `{source}` the `SoundingType` source, e.g. "GFS",
+///  - `{file_type}` the `FileType`, e.g. "BUFKIT",
+///  - `{site}` the `Site` short name,
+///  - `{ext}` the extension for the codec the file is stored with, e.g. ".gz".
+///
+/// The `{time}`, `{source}`, and `{site}` placeholders are required so distinct files get distinct
+/// names, and placeholders must be separated by some text, except for `{ext}`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FileNameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+/// The values recovered from a file name by `FileNameTemplate::parse`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ParsedFileName {
+    pub(crate) init_time: NaiveDateTime,
+    pub(crate) source: String,
+    pub(crate) file_type: Option<String>,
+    pub(crate) site: String,
+    pub(crate) ext: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Time,
+    Source,
+    FileType,
+    Site,
+    Ext,
+}
+
+impl FileNameTemplate {
+    /// The template that matches the original naming scheme of the archive.
+    pub(crate) const DEFAULT: &'static str = "{time}_{source}_{file_type}_{site}{ext}";
+
+    /// Parse and validate a template.
+    pub(crate) fn new(template: &str) -> Result<Self> {
+        let invalid =
+            |msg: &str| BufkitDataErr::InvalidFileNameTemplate(template.to_owned(), msg.to_owned());
+
+        if template.contains(['/', '\\']) {
+            return Err(invalid("path separators are not allowed"));
+        }
+
+        let mut segments = vec![];
+        let mut remaining = template;
+        while !remaining.is_empty() {
+            if let Some(rest) = remaining.strip_prefix('{') {
+                let end = rest.find('}').ok_or_else(|| invalid("unclosed '{'"))?;
+                let segment = match &rest[..end] {
+                    "time" => Segment::Time,
+                    "source" => Segment::Source,
+                    "file_type" => Segment::FileType,
+                    "site" => Segment::Site,
+                    "ext" => Segment::Ext,
+                    _ => return Err(invalid("unknown placeholder")),
+                };
+
+                if segments.contains(&segment) {
+                    return Err(invalid("placeholders may only be used once"));
+                }
+
+                let follows_placeholder = match segments.last() {
+                    Some(Segment::Text(_)) | None => false,
+                    Some(_) => true,
+                };
+                if follows_placeholder && segment != Segment::Ext {
+                    return Err(invalid("placeholders must be separated by text"));
+                }
+
+                segments.push(segment);
+                remaining = &rest[end + 1..];
+            } else {
+                let end = remaining.find('{').unwrap_or(remaining.len());
+                if remaining[..end].contains('}') {
+                    return Err(invalid("unmatched '}'"));
+                }
+
+                segments.push(Segment::Text(remaining[..end].to_owned()));
+                remaining = &remaining[end..];
+            }
+        }
+
+        for required in &[Segment::Time, Segment::Source, Segment::Site] {
+            if !segments.contains(required) {
+                return Err(invalid(
+                    "the {time}, {source}, and {site} placeholders are required",
+                ));
+            }
+        }
+
+        Ok(FileNameTemplate {
+            template: template.to_owned(),
+            segments,
+        })
+    }
+
+    /// The template as it was originally written.
+    pub(crate) fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Fill in the template to make a file name.
+    pub(crate) fn render(
+        &self,
+        init_time: &NaiveDateTime,
+        source: &str,
+        file_type: &str,
+        site: &str,
+        ext: &str,
+    ) -> String {
+        let mut file_name = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => file_name.push_str(text),
+                Segment::Time => {
+                    file_name.push_str(&init_time.format("%Y-%m-%dT%H%MZ").to_string())
+                }
+                Segment::Source => file_name.push_str(source),
+                Segment::FileType => file_name.push_str(file_type),
+                Segment::Site => file_name.push_str(site),
+                Segment::Ext => file_name.push_str(ext),
+            }
+        }
+
+        file_name
+    }
+
+    /// Recover the values used to `render` a file name, `None` if the name doesn't fit the
+    /// template.
+    ///
+    /// Each placeholder extends up to the first occurrence of the text that follows it, so a
+    /// source or site containing that text can't be parsed.
+    pub(crate) fn parse(&self, file_name: &str) -> Option<ParsedFileName> {
+        const TIME_FORMAT: &str = "%Y-%m-%dT%H%MZ";
+        const TIME_LEN: usize = "YYYY-mm-ddTHHMMZ".len();
+
+        let mut init_time = None;
+        let mut source = None;
+        let mut file_type = None;
+        let mut site = None;
+        let mut ext = String::new();
+
+        let mut remaining = file_name;
+        for (i, segment) in self.segments.iter().enumerate() {
+            if let Segment::Text(text) = segment {
+                remaining = remaining.strip_prefix(text.as_str())?;
+                continue;
+            }
+
+            // The value runs up to the next bit of text, or the end of the name.
+            let next_text = self.segments[i + 1..].iter().find_map(|seg| match seg {
+                Segment::Text(text) => Some(text.as_str()),
+                _ => None,
+            });
+            let mut end = match (segment, next_text) {
+                (Segment::Time, _) => TIME_LEN.min(remaining.len()),
+                (_, Some(text)) => remaining.find(text)?,
+                (_, None) => remaining.len(),
+            };
+
+            // Only the extension may come right after another placeholder, so leave any known
+            // extension on the end for it.
+            if segment != &Segment::Ext && self.segments.get(i + 1) == Some(&Segment::Ext) {
+                let value = remaining.get(..end)?;
+                end -= [Codec::Gzip, Codec::None]
+                    .iter()
+                    .map(|codec| codec.extension())
+                    .find(|ext| !ext.is_empty() && value.ends_with(ext))
+                    .map(str::len)
+                    .unwrap_or(0);
+            }
+
+            // The fixed length of the time may split a character in a name that doesn't fit.
+            let value = remaining.get(..end)?;
+            match segment {
+                Segment::Time => {
+                    init_time = Some(NaiveDateTime::parse_from_str(value, TIME_FORMAT).ok()?)
+                }
+                Segment::Source => source = Some(value.to_owned()),
+                Segment::FileType => file_type = Some(value.to_owned()),
+                Segment::Site => site = Some(value.to_owned()),
+                Segment::Ext => ext = value.to_owned(),
+                Segment::Text(_) => unreachable!(),
+            }
+
+            remaining = &remaining[end..];
+        }
+
+        if !remaining.is_empty() {
+            return None;
+        }
+
+        Some(ParsedFileName {
+            init_time: init_time?,
+            source: source?,
+            file_type,
+            site: site?,
+            ext,
+        })
+    }
+}
+
+impl Default for FileNameTemplate {
+    fn default() -> Self {
+        FileNameTemplate::new(Self::DEFAULT).expect("The default template is valid.")
+    }
+}
+
+/*--------------------------------------------------------------------------------------------------
+                                          Unit Tests
+--------------------------------------------------------------------------------------------------*/
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_default_template_matches_original_names() {
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+
+        let name = FileNameTemplate::default().render(&init_time, "GFS", "BUFKIT", "kmso", ".gz");
+        assert_eq!(name, "2017-04-01T1800Z_GFS_BUFKIT_kmso.gz");
+    }
+
+    #[test]
+    fn test_custom_template() -> Result<()> {
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+
+        let template = FileNameTemplate::new("{site}.{source}.{time}.buf{ext}")?;
+        let name = template.render(&init_time, "NAM", "BUFKIT", "kmso", "");
+        assert_eq!(name, "kmso.NAM.2017-04-01T0600Z.buf");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_file_names() -> Result<()> {
+        let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(6, 0, 0)
+            .unwrap();
+
+        let templates = [
+            FileNameTemplate::DEFAULT,
+            "{site}.{source}.{time}.buf{ext}",
+            "{source}-{site}-{time}{ext}",
+        ];
+        for template in &templates {
+            let template = FileNameTemplate::new(template)?;
+            for ext in &[".gz", ""] {
+                let name = template.render(&init_time, "NAM", "BUFKIT", "kmso", ext);
+                let parsed = template.parse(&name).expect("Failed to parse name.");
+
+                assert_eq!(parsed.init_time, init_time);
+                assert_eq!(parsed.source, "NAM");
+                assert_eq!(parsed.site, "kmso");
+                assert_eq!(&parsed.ext, ext);
+            }
+        }
+
+        let template = FileNameTemplate::default();
+        assert_eq!(
+            template
+                .parse("2017-04-01T0600Z_GFS_BUFKIT_kmso.gz")
+                .and_then(|parsed| parsed.file_type),
+            Some("BUFKIT".to_owned())
+        );
+        assert!(template
+            .parse("2017-04-01T06Z_GFS_BUFKIT_kmso.gz")
+            .is_none());
+        assert!(template.parse("kmso.gz").is_none());
+
+        // Cutting the time out of these would split the last character.
+        assert!(template
+            .parse("2017-04-01T0600é_GFS_BUFKIT_kmso.gz")
+            .is_none());
+        assert!(template.parse("2017-04-01T06é").is_none());
+
+        // Other values may be any text.
+        let parsed = template
+            .parse("2017-04-01T0600Z_GFS_BUFKIT_Zürich.gz")
+            .expect("Failed to parse name.");
+        assert_eq!(parsed.site, "Zürich");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        // Missing a required placeholder, so names would not be unique.
+        assert!(FileNameTemplate::new("{time}_{source}{ext}").is_err());
+        // Ambiguous where one value ends and the next starts.
+        assert!(FileNameTemplate::new("{time}_{source}{site}").is_err());
+        // Repeated, unknown, or malformed placeholders.
+        assert!(FileNameTemplate::new("{time}_{source}_{site}_{site}").is_err());
+        assert!(FileNameTemplate::new("{time}_{source}_{site}_{model}").is_err());
+        assert!(FileNameTemplate::new("{time}_{source}_{site").is_err());
+        assert!(FileNameTemplate::new("{time}_{source}_site}").is_err());
+        // Must stay in the files directory.
+        assert!(FileNameTemplate::new("{time}/{source}_{site}").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Module for building queries that select files from the archive.
+use crate::{
+    errors::{BufkitDataErr, Result},
+    file_record::{FileRecord, RecordResolver, FILE_ROW_COLUMNS},
+    site::StateProv,
+};
+use chrono::NaiveDateTime;
+use rusqlite::{types::ToSql, Connection};
+use strum::AsStaticRef;
+
+/// A query selecting files from the archive, with each filter applied in the index.
+///
+/// Created by `Archive::query`. Each method narrows the selection, and calling one again replaces
+/// the earlier value. Without any filters every file is selected. For example
+/// `arch.query().sounding_type("GFS").state(StateProv::MT).execute()` gets every GFS file for
+/// sites in Montana.
+#[derive(Clone, Debug)]
+pub struct FileQuery<'a> {
+    db: &'a Connection,
+    site: Option<String>,
+    sounding_type: Option<String>,
+    between: Option<(NaiveDateTime, NaiveDateTime)>,
+    state: Option<StateProv>,
+}
+
+impl<'a> FileQuery<'a> {
+    pub(crate) fn new(db: &'a Connection) -> Self {
+        FileQuery {
+            db,
+            site: None,
+            sounding_type: None,
+            between: None,
+            state: None,
+        }
+    }
+
+    /// Only select files for the site with this short name.
+    pub fn site(mut self, short_name: &str) -> Self {
+        self.site = Some(short_name.to_owned());
+        self
+    }
+
+    /// Only select files of the sounding type with this source, ignoring case.
+    pub fn sounding_type(mut self, source: &str) -> Self {
+        self.sounding_type = Some(source.to_uppercase());
+        self
+    }
+
+    /// Only select files with an initialization time in the inclusive range `start` to `end`.
+    pub fn between(mut self, start: NaiveDateTime, end: NaiveDateTime) -> Self {
+        self.between = Some((start, end));
+        self
+    }
+
+    /// Only select files for sites in this state or province.
+    pub fn state(mut self, state: StateProv) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Run the query, the records are sorted by initialization time, then site short name, then
+    /// sounding type source.
+    pub fn execute(&self) -> Result<Vec<FileRecord>> {
+        let state = self.state.map(|state| state.as_static());
+
+        let mut conditions: Vec<&str> = vec![];
+        let mut params: Vec<&dyn ToSql> = vec![];
+        if let Some(short_name) = &self.site {
+            conditions.push("sites.short_name = ?");
+            params.push(short_name);
+        }
+        if let Some(source) = &self.sounding_type {
+            conditions.push("types.type = ?");
+            params.push(source);
+        }
+        if let Some((start, end)) = &self.between {
+            conditions.push("files.init_time BETWEEN ? AND ?");
+            params.push(start);
+            params.push(end);
+        }
+        if let Some(state) = &state {
+            conditions.push("sites.state = ?");
+            params.push(state);
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let mut stmt = self.db.prepare(&format!(
+            "
+                SELECT {}
+                FROM files
+                    JOIN sites ON files.site_id = sites.id
+                    JOIN types ON files.type_id = types.id
+                {}
+                ORDER BY files.init_time ASC, sites.short_name ASC, types.type ASC
+            ",
+            FILE_ROW_COLUMNS, where_clause
+        ))?;
+
+        let rows: Result<Vec<_>> = stmt
+            .query_map(params, |row| {
+                crate::file_record::parse_row_to_file_row(row, 0)
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+
+        let mut resolver = RecordResolver::default();
+        rows?
+            .into_iter()
+            .map(|row| resolver.resolve(self.db, row))
+            .collect()
+    }
+}
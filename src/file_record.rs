@@ -0,0 +1,222 @@
+//! Module for records describing the files stored in the archive.
+use crate::{
+    codec::Codec,
+    errors::{BufkitDataErr, Result},
+    location::Location,
+    site::Site,
+    sounding_type::SoundingType,
+};
+use chrono::NaiveDateTime;
+use fnv::FnvHashMap;
+use rusqlite::{Connection, Row};
+use std::{collections::VecDeque, str::FromStr};
+
+/// A description of a single file in the archive, along with the metadata it is indexed by.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileRecord {
+    site: Site,
+    sounding_type: SoundingType,
+    location: Location,
+    init_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    file_name: String,
+    codec: Codec,
+}
+
+impl FileRecord {
+    /// The `Site` the file belongs to.
+    #[inline]
+    pub fn site(&self) -> &Site {
+        &self.site
+    }
+
+    /// The `SoundingType` of the file.
+    #[inline]
+    pub fn sounding_type(&self) -> &SoundingType {
+        &self.sounding_type
+    }
+
+    /// The `Location` of the site when the file was created.
+    #[inline]
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// The initialization time of the model run or launch time of the sounding.
+    #[inline]
+    pub fn init_time(&self) -> NaiveDateTime {
+        self.init_time
+    }
+
+    /// The last valid time of any sounding in the file.
+    #[inline]
+    pub fn end_time(&self) -> NaiveDateTime {
+        self.end_time
+    }
+
+    /// The name of the file in the archive.
+    #[inline]
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// The codec used to store the file in the archive.
+    #[inline]
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+}
+
+/// The columns of the files table needed to build a `FileRecord`, in the order expected by
+/// `parse_row_to_file_row`.
+pub(crate) const FILE_ROW_COLUMNS: &str =
+    "files.site_id, files.type_id, files.location_id, files.init_time, files.end_time, \
+     files.file_name, files.codec";
+
+/// A row from the files table before the ids are resolved into objects.
+pub(crate) struct FileRow {
+    site_id: i64,
+    type_id: i64,
+    location_id: i64,
+    init_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    file_name: String,
+    codec: String,
+}
+
+/// Parse the columns listed in `FILE_ROW_COLUMNS`, starting at column `offset`.
+pub(crate) fn parse_row_to_file_row(
+    row: &Row,
+    offset: usize,
+) -> std::result::Result<FileRow, rusqlite::Error> {
+    Ok(FileRow {
+        site_id: row.get(offset)?,
+        type_id: row.get(offset + 1)?,
+        location_id: row.get(offset + 2)?,
+        init_time: row.get(offset + 3)?,
+        end_time: row.get(offset + 4)?,
+        file_name: row.get(offset + 5)?,
+        codec: row.get(offset + 6)?,
+    })
+}
+
+/// Resolves the ids in a `FileRow` into a `FileRecord`, caching the sites, sounding types, and
+/// locations so each one is only loaded from the index once.
+#[derive(Default)]
+pub(crate) struct RecordResolver {
+    sites: FnvHashMap<i64, Site>,
+    sounding_types: FnvHashMap<i64, SoundingType>,
+    locations: FnvHashMap<i64, Location>,
+}
+
+impl RecordResolver {
+    pub(crate) fn resolve(&mut self, db: &Connection, row: FileRow) -> Result<FileRecord> {
+        let site = match self.sites.get(&row.site_id) {
+            Some(site) => site.clone(),
+            None => {
+                let site = crate::site::retrieve_site_by_id(db, row.site_id)?;
+                self.sites.insert(row.site_id, site.clone());
+                site
+            }
+        };
+
+        let sounding_type = match self.sounding_types.get(&row.type_id) {
+            Some(sounding_type) => sounding_type.clone(),
+            None => {
+                let sounding_type =
+                    crate::sounding_type::retrieve_sounding_type_by_id(db, row.type_id)?;
+                self.sounding_types
+                    .insert(row.type_id, sounding_type.clone());
+                sounding_type
+            }
+        };
+
+        let location = match self.locations.get(&row.location_id) {
+            Some(location) => *location,
+            None => {
+                let location = crate::location::retrieve_location_by_id(db, row.location_id)?;
+                self.locations.insert(row.location_id, location);
+                location
+            }
+        };
+
+        Ok(FileRecord {
+            site,
+            sounding_type,
+            location,
+            init_time: row.init_time,
+            end_time: row.end_time,
+            file_name: row.file_name,
+            codec: Codec::from_str(&row.codec)?,
+        })
+    }
+}
+
+/// Iterate over every file in the index, loading them from the database a page at a time.
+pub(crate) struct FileRecordIter<'a> {
+    db: &'a Connection,
+    resolver: RecordResolver,
+    buffer: VecDeque<(i64, FileRow)>,
+    last_row_id: i64,
+    done: bool,
+}
+
+impl<'a> FileRecordIter<'a> {
+    const PAGE_SIZE: i64 = 1_000;
+
+    pub(crate) fn new(db: &'a Connection) -> Self {
+        FileRecordIter {
+            db,
+            resolver: RecordResolver::default(),
+            buffer: VecDeque::new(),
+            last_row_id: 0,
+            done: false,
+        }
+    }
+
+    fn load_page(&mut self) -> Result<()> {
+        let mut stmt = self.db.prepare(&format!(
+            "
+                SELECT files.rowid, {}
+                FROM files
+                WHERE files.rowid > ?1
+                ORDER BY files.rowid ASC
+                LIMIT ?2
+            ",
+            FILE_ROW_COLUMNS
+        ))?;
+
+        let page: Result<VecDeque<(i64, FileRow)>> = stmt
+            .query_map(&[&self.last_row_id, &Self::PAGE_SIZE], |row| {
+                Ok((row.get(0)?, parse_row_to_file_row(row, 1)?))
+            })?
+            .map(|res| res.map_err(BufkitDataErr::from))
+            .collect();
+        let page = page?;
+
+        self.done = (page.len() as i64) < Self::PAGE_SIZE;
+        if let Some((row_id, _)) = page.back() {
+            self.last_row_id = *row_id;
+        }
+        self.buffer = page;
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for FileRecordIter<'a> {
+    type Item = Result<FileRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            if let Err(err) = self.load_page() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+
+        self.buffer
+            .pop_front()
+            .map(|(_, row)| self.resolver.resolve(self.db, row))
+    }
+}
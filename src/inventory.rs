@@ -1,12 +1,12 @@
 use crate::{errors::Result, location::Location, site::Site, sounding_type::SoundingType};
 use chrono::{Duration, NaiveDateTime};
 use fnv::{FnvHashMap, FnvHashSet};
-use rusqlite::Connection;
+use rusqlite::{Connection, NO_PARAMS};
 
 /// Inventory lists first & last initialization times of the models in the archive for a site &
 /// model. It also contains a list of model initialization times that are missing between the first
 /// and last.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Inventory {
     /// The site this is an inventory for.
     site: Site,
@@ -14,13 +14,33 @@ pub struct Inventory {
     sounding_types: FnvHashSet<SoundingType>,
     /// The earliest and latest init_time in the archive.
     range: FnvHashMap<SoundingType, (NaiveDateTime, NaiveDateTime)>,
+    /// The number of files in the archive.
+    counts: FnvHashMap<SoundingType, usize>,
+    /// Every init_time in the archive, sorted.
+    init_times: FnvHashMap<SoundingType, Vec<NaiveDateTime>>,
     /// A list of start and end init times for missing model runs.
     missing: FnvHashMap<SoundingType, Vec<(NaiveDateTime, NaiveDateTime)>>,
     /// Locations
     locations: FnvHashMap<SoundingType, Vec<Location>>,
+    /// The earliest and latest init_time at each location, keyed by location id. Only filled in
+    /// for mobile sites.
+    location_range: FnvHashMap<(SoundingType, i64), (NaiveDateTime, NaiveDateTime)>,
 }
 
 impl Inventory {
+    fn empty(site: Site) -> Self {
+        Inventory {
+            site,
+            sounding_types: FnvHashSet::default(),
+            range: FnvHashMap::default(),
+            counts: FnvHashMap::default(),
+            init_times: FnvHashMap::default(),
+            missing: FnvHashMap::default(),
+            locations: FnvHashMap::default(),
+            location_range: FnvHashMap::default(),
+        }
+    }
+
     /// The with which this inventory is associated.
     pub fn site(&self) -> &Site {
         &self.site
@@ -38,7 +58,24 @@ impl Inventory {
             .map(|&(start, finish)| (start, finish))
     }
 
+    /// The number of files in the archive for a `SoundingType`.
+    pub fn count(&self, sounding_type: &SoundingType) -> usize {
+        self.counts.get(sounding_type).cloned().unwrap_or(0)
+    }
+
+    /// Every initialization time in the archive for a `SoundingType`, sorted in ascending order.
+    pub fn init_times(&self, sounding_type: &SoundingType) -> &[NaiveDateTime] {
+        self.init_times
+            .get(sounding_type)
+            .map(|v| v.as_slice())
+            .unwrap_or(&[])
+    }
+
     /// Get the inclusive ranges of dates for which we are missing data.
+    ///
+    /// Gaps can only be found for `SoundingType`s with a known number of hours between
+    /// initializations, for other types this is always empty. Use `has_interval` to tell the
+    /// difference between "no gaps" and "gaps unknown".
     pub fn missing(&self, sounding_type: &SoundingType) -> &[(NaiveDateTime, NaiveDateTime)] {
         self.missing
             .get(sounding_type)
@@ -46,6 +83,39 @@ impl Inventory {
             .unwrap_or(&[])
     }
 
+    /// The percentage of the expected initialization times between the first and last that are in
+    /// the archive, from 0 to 100.
+    ///
+    /// The expected times are spaced by the hours between initializations of the `SoundingType`,
+    /// so this is `None` for types without a regular interval, or with no files at this site. A
+    /// single file is 100% complete.
+    pub fn percent_complete(&self, sounding_type: &SoundingType) -> Option<f64> {
+        let delta_hours = sounding_type.hours_between_initializations()?;
+        let (first, last) = self.range(sounding_type)?;
+        if delta_hours == 0 || !self.has_interval(sounding_type) {
+            return None;
+        }
+        let delta_t = Duration::hours(i64::from(delta_hours));
+
+        let expected = (last - first).num_seconds() / delta_t.num_seconds() + 1;
+        let missing: i64 = self
+            .missing(sounding_type)
+            .iter()
+            .map(|(start, end)| (*end - *start).num_seconds() / delta_t.num_seconds() + 1)
+            .sum();
+
+        Some(100.0 * (expected - missing) as f64 / expected as f64)
+    }
+
+    /// `true` if the `SoundingType` has a regular interval between initializations, and so
+    /// `missing` can report the gaps in the data.
+    ///
+    /// If this is `false` an empty result from `missing` means the gaps are unknown, not that there
+    /// aren't any.
+    pub fn has_interval(&self, sounding_type: &SoundingType) -> bool {
+        self.missing.contains_key(sounding_type)
+    }
+
     /// Get the locations for which we have data at a given site.
     pub fn locations(&self, sounding_type: &SoundingType) -> &[Location] {
         self.locations
@@ -53,85 +123,422 @@ impl Inventory {
             .map(|v| v.as_slice())
             .unwrap_or(&[])
     }
+
+    /// The inclusive range of the initialization times of the soundings at a single location.
+    ///
+    /// This is only available for mobile sites, for fixed sites it is always `None` and `range`
+    /// covers all the soundings. The location must be one returned by `locations`.
+    pub fn range_at_location(
+        &self,
+        sounding_type: &SoundingType,
+        location: &Location,
+    ) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        self.location_range
+            .get(&(sounding_type.clone(), location.id()))
+            .map(|&(start, finish)| (start, finish))
+    }
+
+    /// Render the inventory as an aligned text table with a row for each `SoundingType`.
+    ///
+    /// The columns are the source, the first and last initialization times, the number of files,
+    /// and the number of missing spans. Types without a known interval between initializations
+    /// show "n/a" for the missing spans.
+    pub fn to_table_string(&self) -> String {
+        const HEADER: [&str; 5] = ["Type", "First", "Last", "Count", "Missing"];
+        const TIME_FORMAT: &str = "%Y-%m-%d %H%MZ";
+
+        let mut sounding_types: Vec<&SoundingType> = self.sounding_types.iter().collect();
+        sounding_types.sort_by(|a, b| a.source().cmp(b.source()));
+
+        let mut rows: Vec<[String; 5]> = vec![HEADER.map(String::from)];
+        for sounding_type in sounding_types {
+            let (first, last) = match self.range(sounding_type) {
+                Some((first, last)) => (
+                    first.format(TIME_FORMAT).to_string(),
+                    last.format(TIME_FORMAT).to_string(),
+                ),
+                None => ("n/a".to_owned(), "n/a".to_owned()),
+            };
+
+            let missing = if self.has_interval(sounding_type) {
+                self.missing(sounding_type).len().to_string()
+            } else {
+                "n/a".to_owned()
+            };
+
+            rows.push([
+                sounding_type.source().to_owned(),
+                first,
+                last,
+                self.count(sounding_type).to_string(),
+                missing,
+            ]);
+        }
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut table = format!("Inventory for {}\n", self.site.short_name());
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            table.push_str(cells.join(" | ").trim_end());
+            table.push('\n');
+
+            if i == 0 {
+                let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+                table.push_str(&rule.join("-+-"));
+                table.push('\n');
+            }
+        }
+
+        if rows.len() == 1 {
+            table.push_str("no soundings in the archive\n");
+        }
+
+        table
+    }
 }
 
 /// Get an inventory of models and dates for a sounding
 pub fn inventory(db: &Connection, site: Site) -> Result<Inventory> {
-    debug_assert!(site.id() > 0);
+    let mut inventories = inventories_for(db, &[site])?;
+    Ok(inventories
+        .pop()
+        .expect("There is an inventory for each site."))
+}
 
-    // Get all the sounding types for this site
-    let sounding_types: FnvHashSet<_> =
-        crate::sounding_type::all_sounding_types_for_site(db, &site)?
-            .into_iter()
-            .collect();
+/// The most sites to put in the `IN (...)` list of a single query.
+const MAX_SITES_PER_QUERY: usize = 500;
+
+/// Get the inventories for several sites at once, in the same order as `sites`.
+///
+/// Each part of the inventory is a single query restricted to a batch of these sites, instead of
+/// several queries for each site and sounding type.
+pub(crate) fn inventories_for(db: &Connection, sites: &[Site]) -> Result<Vec<Inventory>> {
+    if sites.is_empty() {
+        return Ok(vec![]);
+    }
 
-    let mut range = FnvHashMap::default();
-    let mut missing = FnvHashMap::default();
+    let mut inventories: FnvHashMap<i64, Inventory> = FnvHashMap::default();
+    for site in sites {
+        debug_assert!(site.id() > 0);
+        inventories
+            .entry(site.id())
+            .or_insert_with(|| Inventory::empty(site.clone()));
+    }
+
+    let mut sounding_types = FnvHashMap::default();
     let mut locations = FnvHashMap::default();
-    for sounding_type in sounding_types.iter() {
-        // Add locations
-        let locs_for_type =
-            crate::location::all_locations_for_site_and_type(db, &site, &sounding_type)?;
-        locations.insert(sounding_type.clone(), locs_for_type);
-
-        // Add the range
-        let mut stmt = db.prepare(
+
+    let all_ids: Vec<i64> = inventories.keys().cloned().collect();
+    for chunk in all_ids.chunks(MAX_SITES_PER_QUERY) {
+        // The ids are integers from the index, so they are safe to put straight into the queries.
+        let site_ids: Vec<String> = chunk.iter().map(|id| id.to_string()).collect();
+        let site_ids = site_ids.join(", ");
+
+        // Add the range and count
+        let mut stmt = db.prepare(&format!(
             "
-                SELECT MIN(init_time), MAX(init_time)
+                SELECT site_id, type_id, MIN(init_time), MAX(init_time), COUNT(*)
                 FROM files
-                WHERE site_id = ?1 AND type_id = ?2;
-            ",
-        )?;
-
-        let rng: (NaiveDateTime, NaiveDateTime) = stmt
-            .query_row(&[site.id(), sounding_type.id()], |row| {
-                Ok((row.get(0)?, row.get(1)?))
-            })?;
-        range.insert(sounding_type.clone(), rng);
-
-        // Add the missing values
-        if let Some(delta_hours) = sounding_type.hours_between_initializations() {
-            let mut missing_trs = vec![];
-            let delta_t = Duration::hours(delta_hours as i64);
-
-            let mut stmt = db.prepare(
-                "
-                    SELECT init_time
-                    FROM files
-                    WHERE site_id = ?1 AND type_id = ?2
-                    ORDER BY init_time ASC;
+                WHERE site_id IN ({})
+                GROUP BY site_id, type_id
             ",
-            )?;
+            site_ids
+        ))?;
+        // site_id, type_id, first, last, count
+        type RangeRow = (i64, i64, NaiveDateTime, NaiveDateTime, i64);
+        let rows: std::result::Result<Vec<RangeRow>, _> = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect();
+        for (site_id, type_id, first, last, count) in rows? {
+            let sounding_type = cached_sounding_type(db, &mut sounding_types, type_id)?;
+            let inv = inventories
+                .get_mut(&site_id)
+                .expect("Only queried these sites.");
+
+            inv.range.insert(sounding_type.clone(), (first, last));
+            inv.counts.insert(sounding_type.clone(), count as usize);
+            inv.sounding_types.insert(sounding_type);
+        }
 
-            let mut next_time = rng.0;
-            stmt.query_map(&[site.id(), sounding_type.id()], |row| {
-                row.get::<_, NaiveDateTime>(0)
+        // Add locations, and the range at each location for mobile sites
+        let mut stmt = db.prepare(&format!(
+            "
+                SELECT site_id, type_id, location_id, MIN(init_time), MAX(init_time)
+                FROM files
+                WHERE site_id IN ({})
+                GROUP BY site_id, type_id, location_id
+                ORDER BY site_id, type_id, location_id
+            ",
+            site_ids
+        ))?;
+        // site_id, type_id, location_id, first, last
+        type LocationRow = (i64, i64, i64, NaiveDateTime, NaiveDateTime);
+        let rows: std::result::Result<Vec<LocationRow>, _> = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
             })?
-            .filter_map(|res| res.ok())
-            .for_each(|init_time| {
-                if next_time < init_time {
-                    let start = next_time;
-                    let mut end = next_time;
-                    while next_time < init_time {
-                        end = next_time;
-                        next_time += delta_t;
-                    }
-
-                    missing_trs.push((start, end));
-                }
+            .collect();
+        for (site_id, type_id, location_id, first, last) in rows? {
+            let sounding_type = cached_sounding_type(db, &mut sounding_types, type_id)?;
+            let location = cached_location(db, &mut locations, location_id)?;
+            let inv = inventories
+                .get_mut(&site_id)
+                .expect("Only queried these sites.");
+
+            if inv.site.is_mobile() {
+                inv.location_range
+                    .insert((sounding_type.clone(), location_id), (first, last));
+            }
+            inv.locations
+                .entry(sounding_type)
+                .or_default()
+                .push(location);
+        }
+
+        // Add the init times and missing values
+        let mut stmt = db.prepare(&format!(
+            "
+                SELECT site_id, type_id, init_time
+                FROM files
+                WHERE site_id IN ({})
+                ORDER BY site_id, type_id, init_time ASC
+            ",
+            site_ids
+        ))?;
+        let rows: std::result::Result<Vec<(i64, i64, NaiveDateTime)>, _> = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect();
+        let rows = rows?;
 
+        // The rows are sorted, so each site and type is a run of consecutive rows.
+        let mut start = 0;
+        while start < rows.len() {
+            let (site_id, type_id, _) = rows[start];
+            let end = rows[start..]
+                .iter()
+                .position(|&(s, t, _)| (s, t) != (site_id, type_id))
+                .map_or(rows.len(), |len| start + len);
+            let group = &rows[start..end];
+            start = end;
+
+            let sounding_type = cached_sounding_type(db, &mut sounding_types, type_id)?;
+            let init_times: Vec<NaiveDateTime> =
+                group.iter().map(|&(_, _, init_time)| init_time).collect();
+
+            let inv = inventories
+                .get_mut(&site_id)
+                .expect("Only queried these sites.");
+
+            if let Some(delta_hours) = sounding_type.hours_between_initializations() {
+                let missing_trs = missing_ranges(
+                    init_times.iter().cloned(),
+                    Duration::hours(delta_hours as i64),
+                );
+                inv.missing.insert(sounding_type.clone(), missing_trs);
+            }
+            inv.init_times.insert(sounding_type, init_times);
+        }
+    }
+
+    Ok(sites
+        .iter()
+        .map(|site| inventories[&site.id()].clone())
+        .collect())
+}
+
+/// Find the inclusive ranges of times missing from a sorted list of initialization times.
+fn missing_ranges<I>(init_times: I, delta_t: Duration) -> Vec<(NaiveDateTime, NaiveDateTime)>
+where
+    I: Iterator<Item = NaiveDateTime>,
+{
+    let mut missing_trs = vec![];
+    let mut init_times = init_times.peekable();
+    let mut next_time = match init_times.peek() {
+        Some(first) => *first,
+        None => return missing_trs,
+    };
+
+    for init_time in init_times {
+        if next_time < init_time {
+            let start = next_time;
+            let mut end = next_time;
+            while next_time < init_time {
+                end = next_time;
                 next_time += delta_t;
-            });
+            }
+
+            missing_trs.push((start, end));
+        }
+
+        next_time += delta_t;
+    }
+
+    missing_trs
+}
+
+fn cached_sounding_type(
+    db: &Connection,
+    cache: &mut FnvHashMap<i64, SoundingType>,
+    id: i64,
+) -> Result<SoundingType> {
+    if let Some(sounding_type) = cache.get(&id) {
+        return Ok(sounding_type.clone());
+    }
+
+    let sounding_type = crate::sounding_type::retrieve_sounding_type_by_id(db, id)?;
+    cache.insert(id, sounding_type.clone());
+    Ok(sounding_type)
+}
+
+fn cached_location(
+    db: &Connection,
+    cache: &mut FnvHashMap<i64, Location>,
+    id: i64,
+) -> Result<Location> {
+    if let Some(location) = cache.get(&id) {
+        return Ok(*location);
+    }
+
+    let location = crate::location::retrieve_location_by_id(db, id)?;
+    cache.insert(id, location);
+    Ok(location)
+}
+
+/*--------------------------------------------------------------------------------------------------
+                                          Unit Tests
+--------------------------------------------------------------------------------------------------*/
+#[cfg(test)]
+mod unit {
+    use super::*;
+    use crate::{Archive, FileType};
+    use chrono::NaiveDate;
+    use tempdir::TempDir;
 
-            missing.insert(sounding_type.clone(), missing_trs);
+    #[test]
+    fn test_inventory_locations() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-inventory")?;
+        let arch = Archive::create(tmp.path())?;
+
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        for hour in &[0, 6, 18] {
+            let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(*hour, 0, 0)
+                .unwrap();
+            arch.add_file(
+                &kmso,
+                &gfs,
+                &loc,
+                &init_time,
+                &init_time,
+                &format!("example_data/20170401{:02}Z_gfs_kmso.buf", hour),
+            )?;
         }
+
+        let inv = arch.inventory(&kmso)?;
+        assert_eq!(inv.locations(&gfs), &[loc]);
+        assert_eq!(
+            inv.locations(&gfs),
+            arch.locations_for_site_and_type(&kmso, &gfs)?.as_slice()
+        );
+        assert!(inv.locations(&gfs)[0].is_valid());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_percent_complete() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-inventory")?;
+        let arch = Archive::create(tmp.path())?;
+
+        let kmso = arch.validate_or_add_site(Site::new("kmso"))?;
+        let gfs = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS",
+            FileType::BUFKIT,
+            6,
+        ))?;
+        let irregular = arch.validate_or_add_sounding_type(SoundingType::new_model(
+            "GFS3",
+            FileType::BUFKIT,
+            None,
+        ))?;
+        let loc = arch.validate_or_add_location(Location::new(46.92, -114.08, 972, None))?;
+        let add = |sounding_type: &SoundingType, hour: u32| {
+            let init_time = NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap();
+            arch.add_file(
+                &kmso,
+                sounding_type,
+                &loc,
+                &init_time,
+                &init_time,
+                &format!("example_data/20170401{:02}Z_gfs_kmso.buf", hour),
+            )
+        };
+
+        // A single run is complete.
+        add(&gfs, 0)?;
+        add(&irregular, 0)?;
+        let inv = arch.inventory(&kmso)?;
+        assert_eq!(inv.percent_complete(&gfs), Some(100.0));
+        assert_eq!(inv.percent_complete(&irregular), None);
+
+        // 00Z, 06Z, and 18Z, but no 12Z.
+        add(&gfs, 6)?;
+        add(&gfs, 18)?;
+        let inv = arch.inventory(&kmso)?;
+        assert_eq!(inv.percent_complete(&gfs), Some(75.0));
+
+        Ok(())
     }
 
-    Ok(Inventory {
-        site,
-        sounding_types,
-        range,
-        missing,
-        locations,
-    })
+    #[test]
+    fn test_missing_ranges() {
+        let hour = |h| {
+            NaiveDate::from_ymd_opt(2017, 4, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
+
+        let init_times = vec![hour(0), hour(6), hour(24), hour(36)];
+        assert_eq!(
+            missing_ranges(init_times.into_iter(), Duration::hours(6)),
+            vec![(hour(12), hour(18)), (hour(30), hour(30))]
+        );
+        assert!(missing_ranges(std::iter::empty(), Duration::hours(6)).is_empty());
+    }
 }
@@ -3,19 +3,48 @@
 //
 // Public API
 //
-pub use crate::archive::Archive;
+pub use crate::archive::{Archive, BackfillFn, FetchFn, InitTimeMatch, OnConflict};
+pub use crate::archive_config::ArchiveConfig;
+pub use crate::archive_stats::ArchiveStats;
+pub use crate::backfill_report::BackfillReport;
+pub use crate::blob_store::{BlobStore, FileSystemBlobStore};
+pub use crate::codec::Codec;
+pub use crate::compression_stats::CompressionStats;
+pub use crate::coverage::CoverageComparison;
+pub use crate::decoder::SoundingDecoder;
+pub use crate::diagnosis::Diagnosis;
 pub use crate::errors::BufkitDataErr;
+pub use crate::file_query::FileQuery;
+pub use crate::file_record::FileRecord;
 pub use crate::inventory::Inventory;
 pub use crate::location::Location;
 pub use crate::site::{Site, StateProv};
+pub use crate::size_estimate::SizeEstimate;
 pub use crate::sounding_type::{FileType, SoundingType};
+pub use crate::surface::SurfaceData;
 
 //
 // Implementation only
 //
 mod archive;
+mod archive_config;
+mod archive_stats;
+mod backfill_report;
+mod blob_store;
+mod codec;
+mod compression_stats;
+mod coverage;
+mod decoder;
+mod diagnosis;
 mod errors;
+mod file_name_template;
+mod file_query;
+mod file_record;
 mod inventory;
 mod location;
+mod meta;
+mod schema;
 mod site;
+mod size_estimate;
 mod sounding_type;
+mod surface;
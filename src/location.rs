@@ -3,10 +3,19 @@ use crate::{
     site::Site,
     sounding_type::SoundingType,
 };
+use metfor::Meters;
 use rusqlite::{types::ToSql, Connection, Row, NO_PARAMS};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
 
 /// A geographic location.
-#[derive(Clone, Copy, Debug, PartialEq)]
+///
+/// Two locations are equal, and hash the same, if they would be the same location in the index.
+/// That is they have the same coordinates to the micro-degree and the same elevation, the time
+/// zone offset is ignored.
+#[derive(Clone, Copy, Debug)]
 pub struct Location {
     /// Decimal degrees latitude
     latitude: f64,
@@ -62,6 +71,16 @@ impl Location {
         }
     }
 
+    /// Create a new location with the elevation as a length.
+    ///
+    /// The elevation is rounded to the nearest meter. Panics under the same conditions as `new`.
+    pub fn from_quantities<T>(lat: f64, lon: f64, elevation: Meters, tz_offset: T) -> Self
+    where
+        Option<i32>: From<T>,
+    {
+        Self::new(lat, lon, elevation.0.round() as i32, tz_offset)
+    }
+
     /// Add elevation in meters data to a location.
     pub fn with_elevation(self, elev: i32) -> Self {
         Location {
@@ -96,11 +115,45 @@ impl Location {
         self.elevation_m
     }
 
+    /// Get the elevation as a length.
+    pub fn elevation_length(&self) -> Meters {
+        Meters(f64::from(self.elevation_m))
+    }
+
     /// Get the time zone offset from UTC in seconds.
     pub fn tz_offset(&self) -> Option<i32> {
         self.tz_offset
     }
 
+    /// The great circle distance in kilometers to another location, ignoring elevation.
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        haversine_km(
+            self.latitude,
+            self.longitude,
+            other.latitude,
+            other.longitude,
+        )
+    }
+
+    /// The initial bearing in degrees clockwise from north, in the range [0, 360), along the great
+    /// circle to another location.
+    pub fn bearing_deg(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lon = (other.longitude - self.longitude).to_radians();
+
+        let y = d_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    /// Estimate the time zone offset from UTC in seconds from the longitude alone.
+    ///
+    /// This is the nautical time zone, one hour for every 15 degrees of longitude. It ignores
+    /// political boundaries and daylight saving time, so on land it can be off by an hour or more.
+    pub fn estimated_tz_offset(&self) -> i32 {
+        (self.longitude / 15.0).round() as i32 * 3600
+    }
+
     /// Determine if this location has been verified as being in the archive index.
     pub fn is_valid(&self) -> bool {
         self.id > 0
@@ -109,6 +162,111 @@ impl Location {
     pub(crate) fn id(&self) -> i64 {
         self.id
     }
+
+    /// The key the index uses to tell locations apart.
+    fn index_key(&self) -> (i64, i64, i32) {
+        (
+            to_micro_degrees(self.latitude),
+            to_micro_degrees(self.longitude),
+            self.elevation_m,
+        )
+    }
+}
+
+impl PartialEq for Location {
+    fn eq(&self, other: &Location) -> bool {
+        self.index_key() == other.index_key()
+    }
+}
+
+impl Eq for Location {}
+
+impl Hash for Location {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index_key().hash(state);
+    }
+}
+
+/// The mean radius of the Earth in kilometers.
+const EARTH_RADIUS_KM: f64 = 6_371.0;
+
+/// The great circle distance in kilometers between two points given in degrees, using the
+/// haversine formula on a spherical Earth.
+pub(crate) fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Convert degrees to the integer micro-degrees stored in the index.
+///
+/// This rounds to the nearest micro-degree, which is about 0.1 m, so only points closer together
+/// than that are the same location.
+fn to_micro_degrees(degrees: f64) -> i64 {
+    (degrees * 1_000_000.0).round() as i64
+}
+
+/// The meta key marking an index that rounds coordinates, older indexes truncated them instead.
+const COORDINATES_KEY: &str = "coordinates";
+
+/// Find the row in the index for these coordinates.
+///
+/// An index created before coordinates were rounded may have them stored truncated, so in those
+/// the truncated coordinates are tried too.
+fn find_location(
+    db: &Connection,
+    latitude: f64,
+    longitude: f64,
+    elevation_m: i32,
+) -> Result<Option<Location>> {
+    let query = |lat: i64, lon: i64| -> Result<Option<Location>> {
+        match db.query_row(
+            "
+                SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
+                FROM locations
+                WHERE latitude = ?1 AND longitude = ?2 AND elevation_meters = ?3
+            ",
+            &[&lat, &lon, &elevation_m as &dyn ToSql],
+            parse_row_to_location,
+        ) {
+            Ok(location) => Ok(Some(location)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(BufkitDataErr::from(err)),
+        }
+    };
+
+    let rounded = (to_micro_degrees(latitude), to_micro_degrees(longitude));
+    if let Some(location) = query(rounded.0, rounded.1)? {
+        return Ok(Some(location));
+    }
+
+    let truncated = (
+        (latitude * 1_000_000.0) as i64,
+        (longitude * 1_000_000.0) as i64,
+    );
+    if truncated != rounded && !rounds_coordinates(db)? {
+        return query(truncated.0, truncated.1);
+    }
+
+    Ok(None)
+}
+
+/// Whether this index was created rounding coordinates, an index older than the meta table did not.
+fn rounds_coordinates(db: &Connection) -> Result<bool> {
+    let has_meta: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'meta'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if !has_meta {
+        return Ok(false);
+    }
+
+    Ok(crate::meta::retrieve_meta(db, COORDINATES_KEY)?.is_some())
 }
 
 /// Get a list of locations from the index
@@ -129,6 +287,30 @@ pub(crate) fn all_locations(db: &Connection) -> Result<Vec<Location>> {
     vals
 }
 
+/// Get a page of the locations from the index, ordered by latitude, longitude, and elevation.
+#[inline]
+pub(crate) fn locations_paged(
+    db: &Connection,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<Location>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
+            FROM locations
+            ORDER BY latitude ASC, longitude ASC, elevation_meters ASC
+            LIMIT ?1 OFFSET ?2
+        ",
+    )?;
+
+    let vals: Result<Vec<Location>> = stmt
+        .query_and_then([limit as i64, offset as i64], parse_row_to_location)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
 /// Retrieve the location associated with these coordinates.
 #[inline]
 pub(crate) fn retrieve_location(
@@ -137,23 +319,23 @@ pub(crate) fn retrieve_location(
     longitude: f64,
     elevation_m: i32,
 ) -> Result<Option<Location>> {
-    match db.query_row(
+    find_location(db, latitude, longitude, elevation_m)
+}
+
+/// Retrieve the location with the given row id in the index.
+#[inline]
+pub(crate) fn retrieve_location_by_id(db: &Connection, id: i64) -> Result<Location> {
+    let location = db.query_row(
         "
             SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
             FROM locations
-            WHERE latitude = ?1 AND longitude = ?2 AND elevation_meters = ?3
+            WHERE id = ?1
         ",
-        &[
-            &((latitude * 1_000_000.0) as i64),
-            &((longitude * 1_000_000.0) as i64),
-            &elevation_m as &ToSql,
-        ],
+        &[&id],
         parse_row_to_location,
-    ) {
-        Ok(location) => Ok(Some(location)),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(err) => Err(BufkitDataErr::from(err)),
-    }
+    )?;
+
+    Ok(location)
 }
 
 /// Retrieve the location associated with these coordinates, it it doesn't exist yet add it to the
@@ -165,25 +347,9 @@ pub(crate) fn retrieve_or_add_location(
     longitude: f64,
     elevation_m: i32,
 ) -> Result<Location> {
-    match db.query_row(
-        "
-            SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
-            FROM locations
-            WHERE latitude = ?1 AND longitude = ?2 AND elevation_meters = ?3
-        ",
-        &[
-            &((latitude * 1_000_000.0) as i64),
-            &((longitude * 1_000_000.0) as i64),
-            &elevation_m as &ToSql,
-        ],
-        parse_row_to_location,
-    ) {
-        Ok(location) => Ok(location),
-        Err(rusqlite::Error::QueryReturnedNoRows) => {
-            // Query worked, but found nothing
-            insert_location_(db, latitude, longitude, elevation_m, None)
-        }
-        Err(err) => Err(BufkitDataErr::from(err)),
+    match find_location(db, latitude, longitude, elevation_m)? {
+        Some(location) => Ok(location),
+        None => insert_location_(db, latitude, longitude, elevation_m, None),
     }
 }
 
@@ -234,8 +400,8 @@ fn insert_location_(
             VALUES(?1, ?2, ?3, ?4)
         ",
         &[
-            &((latitude * 1_000_000.0) as i64),
-            &((longitude * 1_000_000.0) as i64),
+            &to_micro_degrees(latitude),
+            &to_micro_degrees(longitude),
             &elevation_m as &ToSql,
             &tz_offset,
         ],
@@ -278,6 +444,75 @@ pub(crate) fn all_locations_for_site_and_type(
     vals
 }
 
+/// Retrieve all the locations without a time zone offset.
+#[inline]
+pub(crate) fn locations_without_tz_offset(db: &Connection) -> Result<Vec<Location>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
+            FROM locations
+            WHERE tz_offset_seconds IS NULL
+        ",
+    )?;
+
+    let vals: Result<Vec<Location>> = stmt
+        .query_and_then(NO_PARAMS, parse_row_to_location)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Retrieve all the locations that are not referred to by any file in the index.
+#[inline]
+pub(crate) fn unused_locations(db: &Connection) -> Result<Vec<Location>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, latitude, longitude, elevation_meters, tz_offset_seconds
+            FROM locations
+            WHERE locations.id NOT IN (SELECT DISTINCT files.location_id FROM files);
+        ",
+    )?;
+
+    let vals: Result<Vec<Location>> = stmt
+        .query_and_then(NO_PARAMS, parse_row_to_location)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Count the distinct locations used by the files for a site.
+#[inline]
+pub(crate) fn location_count_for_site(db: &Connection, site: &Site) -> Result<i64> {
+    let count: i64 = db.query_row(
+        "SELECT COUNT(DISTINCT location_id) FROM files WHERE site_id = ?1",
+        [site.id()],
+        |row| row.get(0),
+    )?;
+
+    Ok(count)
+}
+
+/// Formats as `lat,lon @ elev m`, followed by the offset from UTC if it is known.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{} @ {} m",
+            self.latitude, self.longitude, self.elevation_m
+        )?;
+
+        if let Some(offset) = self.tz_offset {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let minutes = offset.abs() / 60;
+            write!(f, " (UTC{}{:02}:{:02})", sign, minutes / 60, minutes % 60)?;
+        }
+
+        Ok(())
+    }
+}
+
 fn parse_row_to_location(row: &Row) -> std::result::Result<Location, rusqlite::Error> {
     let id: i64 = row.get(0)?;
     let latitude: f64 = row.get::<_, i64>(1)? as f64 / 1_000_000.0;
@@ -296,5 +531,145 @@ fn parse_row_to_location(row: &Row) -> std::result::Result<Location, rusqlite::E
 
 #[cfg(test)]
 mod tests {
-    // TODO: make some tests
+    use super::*;
+
+    #[test]
+    fn test_display_location() {
+        let loc = Location::new(46.87, -114.16, 1335, None);
+        assert_eq!(loc.to_string(), "46.87,-114.16 @ 1335 m");
+
+        let loc = loc.with_tz_offset(-7 * 3600);
+        assert_eq!(loc.to_string(), "46.87,-114.16 @ 1335 m (UTC-07:00)");
+
+        let loc = Location::new(-12.5, 130.85, 0, 34_200);
+        assert_eq!(loc.to_string(), "-12.5,130.85 @ 0 m (UTC+09:30)");
+    }
+
+    #[test]
+    fn test_haversine_km() {
+        assert_eq!(haversine_km(46.87, -114.16, 46.87, -114.16), 0.0);
+
+        // A degree of latitude, or of longitude along the equator.
+        assert!((haversine_km(45.0, -114.0, 46.0, -114.0) - 111.19).abs() < 0.01);
+        assert!((haversine_km(0.0, 179.5, 0.0, -179.5) - 111.19).abs() < 0.01);
+
+        // Half way around the world.
+        assert!((haversine_km(90.0, 0.0, -90.0, 0.0) - 20_015.09).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_distance_and_bearing() {
+        let new_york = Location::new(40.7128, -74.0060, 10, None);
+        let los_angeles = Location::new(34.0522, -118.2437, 71, None);
+        assert!((new_york.distance_km(&los_angeles) - 3935.7).abs() < 0.1);
+        assert!((new_york.bearing_deg(&los_angeles) - 273.7).abs() < 0.1);
+        assert_eq!(
+            new_york.distance_km(&los_angeles),
+            los_angeles.distance_km(&new_york)
+        );
+
+        let london = Location::new(51.5074, -0.1278, 11, None);
+        let paris = Location::new(48.8566, 2.3522, 35, None);
+        assert!((london.distance_km(&paris) - 343.6).abs() < 0.1);
+        assert!((london.bearing_deg(&paris) - 148.1).abs() < 0.1);
+
+        let origin = Location::new(0.0, 0.0, 0, None);
+        assert_eq!(origin.distance_km(&origin), 0.0);
+        assert_eq!(origin.bearing_deg(&Location::new(1.0, 0.0, 0, None)), 0.0);
+        assert!((origin.bearing_deg(&Location::new(0.0, 1.0, 0, None)) - 90.0).abs() < 1.0e-9);
+        assert!((origin.bearing_deg(&Location::new(-1.0, 0.0, 0, None)) - 180.0).abs() < 1.0e-9);
+        assert!((origin.bearing_deg(&Location::new(0.0, -1.0, 0, None)) - 270.0).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn test_estimated_tz_offset() {
+        let greenwich = Location::new(51.48, 0.0, 0, None);
+        assert_eq!(greenwich.estimated_tz_offset(), 0);
+
+        let missoula = Location::new(46.87, -114.16, 1335, None);
+        assert_eq!(missoula.estimated_tz_offset(), -8 * 3600);
+
+        let darwin = Location::new(-12.5, 130.85, 0, None);
+        assert_eq!(darwin.estimated_tz_offset(), 9 * 3600);
+
+        let date_line = Location::new(0.0, -180.0, 0, None);
+        assert_eq!(date_line.estimated_tz_offset(), -12 * 3600);
+    }
+
+    #[test]
+    fn test_micro_degree_rounding() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        // Truncating would put both of these in the same micro-degree.
+        let a = Location::new(46.870_000_3, -114.160_000_3, 1335, None);
+        let b = Location::new(46.870_000_7, -114.160_000_7, 1335, None);
+        assert_ne!(a, b);
+
+        let a = insert_location(&db_conn, a)?;
+        assert!(retrieve_location(&db_conn, b.latitude(), b.longitude(), 1335)?.is_none());
+        let b = retrieve_or_add_location(&db_conn, b.latitude(), b.longitude(), 1335)?;
+        assert_ne!(a.id(), b.id());
+
+        // A point matches itself after a round trip through the index.
+        let c = insert_location(&db_conn, Location::new(46.92, -114.08, 972, None))?;
+        let retrieved = retrieve_location(&db_conn, 46.92, -114.08, 972)?.unwrap();
+        assert_eq!(retrieved.id(), c.id());
+        assert_eq!(retrieved, c);
+        let again = retrieve_location(&db_conn, retrieved.latitude(), retrieved.longitude(), 972)?;
+        assert_eq!(again.map(|loc| loc.id()), Some(c.id()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_coordinates_in_older_index() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("original_index.sql"))?;
+
+        // How an older index stored 46.870_000_7, -114.160_000_7.
+        db_conn.execute(
+            "
+                INSERT INTO locations(latitude, longitude, elevation_meters)
+                VALUES(46870000, -114160000, 1335)
+            ",
+            NO_PARAMS,
+        )?;
+        let id = db_conn.last_insert_rowid();
+        assert!(!rounds_coordinates(&db_conn)?);
+
+        // Upgrading the index adds the meta table, but the coordinates are still truncated.
+        crate::schema::migrate(&db_conn)?;
+        assert!(!rounds_coordinates(&db_conn)?);
+
+        let found = retrieve_or_add_location(&db_conn, 46.870_000_7, -114.160_000_7, 1335)?;
+        assert_eq!(found.id(), id);
+        assert_eq!(all_locations(&db_conn)?.len(), 1);
+
+        // A new index rounds them.
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+        assert!(rounds_coordinates(&db_conn)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_location_hash_set() {
+        use std::collections::HashSet;
+
+        // Less than a micro-degree apart, so they would be the same row in the index.
+        let a = Location::new(46.870_000_1, -114.160_000_2, 1335, None);
+        let b = Location::new(46.870_000_4, -114.160_000_3, 1335, -7 * 3600);
+        assert_eq!(a, b);
+
+        let locations: HashSet<Location> = [a, b].iter().cloned().collect();
+        assert_eq!(locations.len(), 1);
+
+        // Different elevations are different locations.
+        let c = b.with_elevation(1336);
+        assert_ne!(b, c);
+        let locations: HashSet<Location> = [a, b, c].iter().cloned().collect();
+        assert_eq!(locations.len(), 2);
+    }
 }
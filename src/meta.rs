@@ -0,0 +1,26 @@
+//! Module for key-value metadata about the archive itself.
+use crate::errors::{BufkitDataErr, Result};
+use rusqlite::Connection;
+
+/// Retrieve a value from the meta table, `Ok(None)` if the key is not present.
+#[inline]
+pub(crate) fn retrieve_meta(db: &Connection, key: &str) -> Result<Option<String>> {
+    match db.query_row("SELECT value FROM meta WHERE key = ?1", &[key], |row| {
+        row.get(0)
+    }) {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(BufkitDataErr::from(err)),
+    }
+}
+
+/// Insert or replace a value in the meta table.
+#[inline]
+pub(crate) fn store_meta(db: &Connection, key: &str, value: &str) -> Result<()> {
+    db.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+        &[key, value],
+    )?;
+
+    Ok(())
+}
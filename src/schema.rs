@@ -0,0 +1,178 @@
+//! Module for checking the tables in an index match what this version of the crate expects, and
+//! upgrading indexes made by older versions.
+use crate::errors::{BufkitDataErr, Result};
+use rusqlite::{Connection, NO_PARAMS};
+use std::collections::BTreeSet;
+
+/// Compare the tables and columns in `db` to those created by `create_index.sql`.
+///
+/// Returns `BufkitDataErr::SchemaMismatch` listing every missing table, missing column, and extra
+/// column. Extra tables are allowed, since they don't get in the way of any queries.
+pub(crate) fn check_schema(db: &Connection) -> Result<()> {
+    let expected = Connection::open_in_memory()?;
+    expected.execute_batch(include_str!("create_index.sql"))?;
+
+    let mut problems = vec![];
+    for table in table_names(&expected)? {
+        let expected_columns = column_names(&expected, &table)?;
+        let actual_columns = column_names(db, &table)?;
+
+        // A table without any columns doesn't exist.
+        if actual_columns.is_empty() {
+            problems.push(format!("missing table {}", table));
+            continue;
+        }
+
+        for column in expected_columns.difference(&actual_columns) {
+            problems.push(format!("missing column {}.{}", table, column));
+        }
+        for column in actual_columns.difference(&expected_columns) {
+            problems.push(format!("extra column {}.{}", table, column));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(BufkitDataErr::SchemaMismatch(problems))
+    }
+}
+
+/// Add any tables and columns from `create_index.sql` that are missing from `db`.
+///
+/// Indexes made by older versions of the crate are missing the tables and columns added since,
+/// this adds them with their defaults so the index can be used as is. Nothing is changed in an
+/// index that is already up to date, so it is safe to run every time an archive is opened.
+pub(crate) fn migrate(db: &Connection) -> Result<()> {
+    let expected = Connection::open_in_memory()?;
+    expected.execute_batch(include_str!("create_index.sql"))?;
+
+    let mut statements = vec![];
+    for table in table_names(&expected)? {
+        let actual_columns = column_names(db, &table)?;
+
+        if actual_columns.is_empty() {
+            let create: String = expected.query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                &[&table],
+                |row| row.get(0),
+            )?;
+            statements.push(create);
+            continue;
+        }
+
+        for (column, definition) in column_definitions(&expected, &table)? {
+            if !actual_columns.contains(&column) {
+                statements.push(format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table, column, definition
+                ));
+            }
+        }
+    }
+
+    if statements.is_empty() {
+        return Ok(());
+    }
+
+    db.execute_batch("BEGIN")?;
+    match statements
+        .iter()
+        .try_for_each(|statement| db.execute_batch(statement))
+    {
+        Ok(()) => db.execute_batch("COMMIT")?,
+        Err(err) => {
+            db.execute_batch("ROLLBACK")?;
+            return Err(err.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// The name of each column in a table, along with its type, constraint, and default as they would
+/// be written in a column definition.
+fn column_definitions(db: &Connection, table: &str) -> Result<Vec<(String, String)>> {
+    let mut stmt = db.prepare(&format!("PRAGMA table_info({})", table))?;
+
+    let definitions: Result<Vec<(String, String)>> = stmt
+        .query_map(NO_PARAMS, |row| {
+            let name: String = row.get(1)?;
+            let col_type: String = row.get(2)?;
+            let not_null: bool = row.get(3)?;
+            let default: Option<String> = row.get(4)?;
+
+            let mut definition = col_type;
+            if not_null {
+                definition.push_str(" NOT NULL");
+            }
+            if let Some(default) = default {
+                definition.push_str(" DEFAULT ");
+                definition.push_str(&default);
+            }
+
+            Ok((name, definition))
+        })?
+        .map(|res| res.map_err(BufkitDataErr::Database))
+        .collect();
+
+    definitions
+}
+
+fn table_names(db: &Connection) -> Result<Vec<String>> {
+    let mut stmt =
+        db.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+
+    let names: Result<Vec<String>> = stmt
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .map(|res| res.map_err(BufkitDataErr::Database))
+        .collect();
+
+    names
+}
+
+fn column_names(db: &Connection, table: &str) -> Result<BTreeSet<String>> {
+    let mut stmt = db.prepare(&format!("PRAGMA table_info({})", table))?;
+
+    let names: Result<BTreeSet<String>> = stmt
+        .query_map(NO_PARAMS, |row| row.get(1))?
+        .map(|res| res.map_err(BufkitDataErr::Database))
+        .collect();
+
+    names
+}
+
+/*--------------------------------------------------------------------------------------------------
+                                          Unit Tests
+--------------------------------------------------------------------------------------------------*/
+#[cfg(test)]
+mod unit {
+    use super::*;
+
+    #[test]
+    fn test_migrate_original_index() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("original_index.sql"))?;
+        assert!(check_schema(&db_conn).is_err());
+
+        migrate(&db_conn)?;
+        check_schema(&db_conn)?;
+
+        // Already up to date.
+        migrate(&db_conn)?;
+        check_schema(&db_conn)?;
+
+        // Added columns get their defaults.
+        db_conn.execute_batch(
+            "
+                INSERT INTO files (type_id, site_id, location_id, init_time, end_time, file_name)
+                VALUES (1, 1, 1, '2017-04-01T00:00:00', '2017-04-01T00:00:00', 'a.gz');
+            ",
+        )?;
+        let codec: String =
+            db_conn.query_row("SELECT codec FROM files", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(codec, "Gzip");
+
+        Ok(())
+    }
+}
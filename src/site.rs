@@ -1,6 +1,7 @@
 use crate::errors::{BufkitDataErr, Result};
+use chrono::NaiveDateTime;
 use rusqlite::{types::ToSql, Connection, Row, NO_PARAMS};
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 use strum::AsStaticRef;
 use strum_macros::{AsStaticStr, EnumIter, EnumString};
 
@@ -126,6 +127,22 @@ impl Site {
     }
 }
 
+/// Formats as `short_name (long_name, ST)`, leaving out whatever parts are missing.
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.short_name)?;
+
+        match (self.long_name(), self.state) {
+            (Some(long_name), Some(state)) => {
+                write!(f, " ({}, {})", long_name, state.as_static())
+            }
+            (Some(long_name), None) => write!(f, " ({})", long_name),
+            (None, Some(state)) => write!(f, " ({})", state.as_static()),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
 /// Retrieve the sounding type information from the database for the given source name.
 #[inline]
 pub(crate) fn retrieve_site(db: &Connection, short_name: &str) -> Result<Option<Site>> {
@@ -144,6 +161,22 @@ pub(crate) fn retrieve_site(db: &Connection, short_name: &str) -> Result<Option<
     }
 }
 
+/// Retrieve the site with the given row id in the index.
+#[inline]
+pub(crate) fn retrieve_site_by_id(db: &Connection, id: i64) -> Result<Site> {
+    let site = db.query_row(
+        "
+            SELECT id, short_name, long_name, state, notes, mobile_sounding_site
+            FROM sites
+            WHERE id = ?1
+        ",
+        &[&id],
+        parse_row_to_site,
+    )?;
+
+    Ok(site)
+}
+
 /// Update the site information in the index.
 #[inline]
 pub(crate) fn update_site(db: &Connection, site: Site) -> Result<Site> {
@@ -166,9 +199,32 @@ pub(crate) fn update_site(db: &Connection, site: Site) -> Result<Site> {
     retrieve_site(db, &site.short_name).map(|opt| opt.unwrap())
 }
 
+/// Change the short name of a site in the index.
+///
+/// It is an error if the new short name is blank.
+#[inline]
+pub(crate) fn rename_site(db: &Connection, site: &Site, new_short_name: &str) -> Result<Site> {
+    if new_short_name.trim().is_empty() {
+        return Err(BufkitDataErr::InvalidSite(Site::new(new_short_name)));
+    }
+
+    db.execute(
+        "UPDATE sites SET short_name = ?2 WHERE id = ?1",
+        &[&site.id as &dyn ToSql, &new_short_name],
+    )?;
+
+    retrieve_site_by_id(db, site.id)
+}
+
 /// Insert the site information in the database.
+///
+/// It is an error if the short name is blank, since it is used to name the site's files.
 #[inline]
 pub(crate) fn insert_site(db: &Connection, site: Site) -> Result<Site> {
+    if site.short_name.trim().is_empty() {
+        return Err(BufkitDataErr::InvalidSite(site));
+    }
+
     db.execute(
         "
             INSERT INTO sites(short_name, long_name, state, notes, mobile_sounding_site) 
@@ -205,6 +261,124 @@ pub(crate) fn all_sites(db: &Connection) -> Result<Vec<Site>> {
     vals
 }
 
+/// Get a page of the sites stored in the database, ordered by short name.
+#[inline]
+pub(crate) fn sites_paged(db: &Connection, limit: usize, offset: usize) -> Result<Vec<Site>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, short_name, long_name, state, notes, mobile_sounding_site
+            FROM sites
+            ORDER BY short_name ASC
+            LIMIT ?1 OFFSET ?2
+        ",
+    )?;
+
+    let vals: Result<Vec<Site>> = stmt
+        .query_and_then([limit as i64, offset as i64], parse_row_to_site)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sites that are, or aren't, mobile, or all of them if `mobile` is `None`, ordered by
+/// short name.
+#[inline]
+pub(crate) fn sites_filtered(db: &Connection, mobile: Option<bool>) -> Result<Vec<Site>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, short_name, long_name, state, notes, mobile_sounding_site
+            FROM sites
+            WHERE ?1 IS NULL OR mobile_sounding_site = ?1
+            ORDER BY short_name ASC
+        ",
+    )?;
+
+    let vals: Result<Vec<Site>> = stmt
+        .query_and_then([mobile], parse_row_to_site)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sites in a state or province, ordered by short name.
+#[inline]
+pub(crate) fn sites_in_state(db: &Connection, state: StateProv) -> Result<Vec<Site>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, short_name, long_name, state, notes, mobile_sounding_site
+            FROM sites
+            WHERE state = ?1
+            ORDER BY short_name ASC
+        ",
+    )?;
+
+    let vals: Result<Vec<Site>> = stmt
+        .query_and_then([state.as_static()], parse_row_to_site)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sites with a long name containing `substring`, ignoring ASCII case, ordered by short
+/// name.
+#[inline]
+pub(crate) fn sites_by_long_name(db: &Connection, substring: &str) -> Result<Vec<Site>> {
+    let mut stmt = db.prepare(
+        r"
+            SELECT id, short_name, long_name, state, notes, mobile_sounding_site
+            FROM sites
+            WHERE long_name LIKE ?1 ESCAPE '\'
+            ORDER BY short_name ASC
+        ",
+    )?;
+
+    // Match the wildcards literally.
+    let pattern = format!(
+        "%{}%",
+        substring
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    let vals: Result<Vec<Site>> = stmt
+        .query_and_then([pattern], parse_row_to_site)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sites with any files, along with the most recent initialization time of their files,
+/// newest first.
+#[inline]
+pub(crate) fn sites_by_recency(db: &Connection) -> Result<Vec<(Site, NaiveDateTime)>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT sites.id, short_name, long_name, state, notes, mobile_sounding_site,
+                   MAX(files.init_time) AS newest
+            FROM sites JOIN files ON files.site_id = sites.id
+            GROUP BY sites.id
+            ORDER BY newest DESC, short_name ASC;
+        ",
+    )?;
+
+    let vals: Result<Vec<(Site, NaiveDateTime)>> = stmt
+        .query_and_then(
+            NO_PARAMS,
+            |row| -> std::result::Result<_, rusqlite::Error> {
+                Ok((parse_row_to_site(row)?, row.get(6)?))
+            },
+        )?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
 fn parse_row_to_site(row: &Row) -> std::result::Result<Site, rusqlite::Error> {
     let short_name: String = row.get(1)?;
     let long_name: Option<String> = row.get(2)?;
@@ -326,6 +500,21 @@ mod unit {
         assert!(incomplete_site.incomplete());
     }
 
+    #[test]
+    fn test_display_site() {
+        let site = Site::new("kmso");
+        assert_eq!(site.to_string(), "kmso");
+
+        let site = site.with_long_name("Missoula".to_owned());
+        assert_eq!(site.to_string(), "kmso (Missoula)");
+
+        let site = site.with_state_prov(StateProv::MT);
+        assert_eq!(site.to_string(), "kmso (Missoula, MT)");
+
+        let site = Site::new("kmso").with_state_prov(StateProv::MT);
+        assert_eq!(site.to_string(), "kmso (MT)");
+    }
+
     #[test]
     fn test_to_string_for_state_prov() {
         assert_eq!(StateProv::AL.as_static(), "AL");
@@ -363,4 +552,96 @@ mod unit {
 
         Ok(())
     }
+
+    #[test]
+    fn test_blank_short_name() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        for short_name in &["", "  ", "\t\n"] {
+            assert!(matches!(
+                insert_site(&db_conn, Site::new(short_name)),
+                Err(BufkitDataErr::InvalidSite(_))
+            ));
+        }
+        assert!(all_sites(&db_conn)?.is_empty());
+
+        let site = insert_site(&db_conn, Site::new("kmso"))?;
+        assert!(matches!(
+            rename_site(&db_conn, &site, " "),
+            Err(BufkitDataErr::InvalidSite(_))
+        ));
+        assert!(retrieve_site(&db_conn, "kmso")?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_sites() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        let sites = [
+            Site::new("kmso")
+                .with_long_name("Missoula".to_owned())
+                .with_state_prov(StateProv::MT),
+            Site::new("kgpi")
+                .with_long_name("Kalispell".to_owned())
+                .with_state_prov(StateProv::MT),
+            Site::new("kboi")
+                .with_long_name("Boise 100%_sure".to_owned())
+                .with_state_prov(StateProv::ID),
+            Site::new("kxly"),
+        ];
+        for site in &sites {
+            insert_site(&db_conn, site.clone())?;
+        }
+
+        let names = |sites: Vec<Site>| -> Vec<String> {
+            sites.iter().map(|s| s.short_name().to_owned()).collect()
+        };
+
+        assert_eq!(
+            names(sites_in_state(&db_conn, StateProv::MT)?),
+            vec!["kgpi", "kmso"]
+        );
+        assert!(sites_in_state(&db_conn, StateProv::WA)?.is_empty());
+
+        assert_eq!(names(sites_by_long_name(&db_conn, "SOUL")?), vec!["kmso"]);
+        assert_eq!(
+            names(sites_by_long_name(&db_conn, "is")?),
+            vec!["kboi", "kgpi", "kmso"]
+        );
+        assert_eq!(names(sites_by_long_name(&db_conn, "0%_s")?), vec!["kboi"]);
+        assert_eq!(names(sites_by_long_name(&db_conn, "%")?), vec!["kboi"]);
+        assert!(sites_by_long_name(&db_conn, "Helena")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sites_filtered() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        insert_site(&db_conn, Site::new("kmso"))?;
+        insert_site(&db_conn, Site::new("unit2").set_mobile(true))?;
+        insert_site(&db_conn, Site::new("unit1").set_mobile(true))?;
+
+        let names = |sites: Vec<Site>| -> Vec<String> {
+            sites.iter().map(|s| s.short_name().to_owned()).collect()
+        };
+
+        assert_eq!(
+            names(sites_filtered(&db_conn, Some(true))?),
+            vec!["unit1", "unit2"]
+        );
+        assert_eq!(names(sites_filtered(&db_conn, Some(false))?), vec!["kmso"]);
+        assert_eq!(
+            names(sites_filtered(&db_conn, None)?),
+            vec!["kmso", "unit1", "unit2"]
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,47 @@
+//! Module for estimating how much room the files in the archive take up once uncompressed.
+
+/// An estimate of the total uncompressed size of the files in the archive.
+///
+/// Created by `Archive::estimated_uncompressed_size`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SizeEstimate {
+    /// The estimated total size of the files once decoded.
+    pub(crate) bytes: u64,
+    /// How much to trust the estimate, from 0 to 1.
+    pub(crate) confidence: f64,
+    /// The number of files decoded to measure the compression ratio.
+    pub(crate) sampled_files: usize,
+    /// Files in the index that are missing from storage, and left out of the estimate.
+    pub(crate) missing_files: Vec<String>,
+}
+
+impl SizeEstimate {
+    /// The estimated total size in bytes of the files once decoded.
+    #[inline]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// How much to trust the estimate, from 0 for a guess to 1 for an exact answer.
+    ///
+    /// This is one minus the relative standard error of the compression ratio measured from the
+    /// sample, so it grows as more files are sampled and as their ratios agree more closely. It is
+    /// 1 when every file was sampled, and 0 when fewer than two files were sampled from a larger
+    /// archive.
+    #[inline]
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    /// The number of files decoded to measure the compression ratio.
+    #[inline]
+    pub fn sampled_files(&self) -> usize {
+        self.sampled_files
+    }
+
+    /// Files in the index that are missing from storage, and so are left out of the estimate.
+    #[inline]
+    pub fn missing_files(&self) -> &[String] {
+        &self.missing_files
+    }
+}
@@ -2,8 +2,9 @@ use crate::{
     errors::{BufkitDataErr, Result},
     site::Site,
 };
+use chrono::NaiveDateTime;
 use rusqlite::{types::ToSql, Connection, Row, NO_PARAMS};
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 use strum::AsStaticRef;
 use strum_macros::{AsStaticStr, EnumString};
 
@@ -18,11 +19,15 @@ pub struct SoundingType {
     file_type: FileType,
     source: String,             // Description such as model name or RAWIN_SONDE
     hours_between: Option<u16>, // Hours between observations or model initializations
+    source_url: Option<String>, // Where the data comes from, e.g. a provider URL pattern
     id: i64,                    // id code from the database
 }
 
 impl SoundingType {
     /// Create a new sounding type.
+    ///
+    /// The source is converted to upper case, so "NamNest" and "NAMNEST" are the same source.
+    /// Looking a sounding type up by source in the archive ignores case too.
     pub fn new<T>(src: &str, observed: bool, file_type: FileType, hours_between: T) -> Self
     where
         Option<u16>: From<T>,
@@ -32,6 +37,7 @@ impl SoundingType {
             file_type,
             source: src.to_uppercase(),
             hours_between: Option::from(hours_between),
+            source_url: None,
             id: -1, // Uninitialized in the database.
         }
     }
@@ -54,6 +60,42 @@ impl SoundingType {
         Self::new(src, true, file_type, hours_between)
     }
 
+    /// Add a description of where the data comes from, such as a provider URL pattern.
+    #[inline]
+    pub fn with_source_url<T>(self, source_url: T) -> Self
+    where
+        Option<String>: From<T>,
+    {
+        SoundingType {
+            source_url: Option::from(source_url),
+            ..self
+        }
+    }
+
+    /// Set whether this is an observed sounding type, or a model if `false`.
+    #[inline]
+    pub fn with_observed(self, observed: bool) -> Self {
+        SoundingType { observed, ..self }
+    }
+
+    /// Set the hours between model initializations or launches.
+    #[inline]
+    pub fn with_hours_between<T>(self, hours_between: T) -> Self
+    where
+        Option<u16>: From<T>,
+    {
+        SoundingType {
+            hours_between: Option::from(hours_between),
+            ..self
+        }
+    }
+
+    /// Set the file type the decompressed data is stored in.
+    #[inline]
+    pub fn with_file_type(self, file_type: FileType) -> Self {
+        SoundingType { file_type, ..self }
+    }
+
     /// `true` if this type represents a model sounding.
     #[inline]
     pub fn is_modeled(&self) -> bool {
@@ -73,6 +115,8 @@ impl SoundingType {
     }
 
     /// Get the unique string that represents the sounding source such as "GFS", "NAM", etc.
+    ///
+    /// This is always upper case.
     #[inline]
     pub fn source(&self) -> &str {
         &self.source
@@ -84,8 +128,13 @@ impl SoundingType {
         self.hours_between
     }
 
+    /// Where the data for this sounding type comes from, if it was recorded.
+    #[inline]
+    pub fn source_url(&self) -> Option<&str> {
+        self.source_url.as_ref().map(|val| val.as_ref())
+    }
+
     /// This is the file type that the decompressed data is stored in.
-    // FIXME: factor this out to its own module and store it in the database on its own.
     #[inline]
     pub fn file_type(&self) -> FileType {
         self.file_type
@@ -96,7 +145,8 @@ impl SoundingType {
     }
 }
 
-/// Retrieve the sounding type information from the database for the given source name.
+/// Retrieve the sounding type information from the database for the given source name, ignoring
+/// case the same way `SoundingType::new` does.
 #[inline]
 pub(crate) fn retrieve_sounding_type(
     db: &Connection,
@@ -104,11 +154,11 @@ pub(crate) fn retrieve_sounding_type(
 ) -> Result<Option<SoundingType>> {
     match db.query_row(
         "
-            SELECT id, type, file_type, interval, observed
+            SELECT id, type, file_type, interval, observed, source_url
             FROM types
             WHERE type = ?1
         ",
-        &[sounding_type_as_str],
+        &[sounding_type_as_str.to_uppercase()],
         parse_row_to_sounding_type,
     ) {
         Ok(sounding_type) => Ok(Some(sounding_type)),
@@ -117,6 +167,22 @@ pub(crate) fn retrieve_sounding_type(
     }
 }
 
+/// Retrieve the sounding type with the given row id in the index.
+#[inline]
+pub(crate) fn retrieve_sounding_type_by_id(db: &Connection, id: i64) -> Result<SoundingType> {
+    let sounding_type = db.query_row(
+        "
+            SELECT id, type, file_type, interval, observed, source_url
+            FROM types
+            WHERE id = ?1
+        ",
+        &[&id],
+        parse_row_to_sounding_type,
+    )?;
+
+    Ok(sounding_type)
+}
+
 /// Update the sounding type information in the index.
 #[inline]
 pub(crate) fn update_sounding_type(
@@ -126,20 +192,37 @@ pub(crate) fn update_sounding_type(
     db.execute(
         "
                 UPDATE types
-                SET (interval, observed)
-                = (?2, ?3)
+                SET (interval, observed, source_url, file_type)
+                = (?2, ?3, ?4, ?5)
                 WHERE type = ?1
             ",
         &[
             &sounding_type.source,
             &sounding_type.hours_between as &ToSql,
             &sounding_type.observed,
+            &sounding_type.source_url,
+            &sounding_type.file_type.as_static(),
         ],
     )?;
 
     retrieve_sounding_type(db, &sounding_type.source).map(|opt| opt.unwrap())
 }
 
+/// Change the file type of a sounding type in the index.
+pub(crate) fn update_file_type(
+    db: &Connection,
+    sounding_type: &SoundingType,
+    file_type: FileType,
+) -> Result<SoundingType> {
+    db.execute(
+        "UPDATE types SET file_type = ?2 WHERE type = ?1",
+        &[&sounding_type.source, &file_type.as_static() as &dyn ToSql],
+    )?;
+
+    retrieve_sounding_type(db, &sounding_type.source)?
+        .ok_or_else(|| BufkitDataErr::InvalidSoundingType(sounding_type.clone()))
+}
+
 /// Insert the `SoundingType` information in the index.
 #[inline]
 pub(crate) fn insert_sounding_type(
@@ -148,14 +231,15 @@ pub(crate) fn insert_sounding_type(
 ) -> Result<SoundingType> {
     db.execute(
         "
-            INSERT INTO types(type, file_type, interval, observed) 
-            VALUES(?1, ?2, ?3, ?4)
+            INSERT INTO types(type, file_type, interval, observed, source_url) 
+            VALUES(?1, ?2, ?3, ?4, ?5)
         ",
         &[
             &sounding_type.source,
             &sounding_type.file_type.as_static() as &ToSql,
             &sounding_type.hours_between as &ToSql,
             &sounding_type.observed,
+            &sounding_type.source_url,
         ],
     )?;
 
@@ -171,7 +255,7 @@ pub(crate) fn insert_sounding_type(
 pub(crate) fn all_sounding_types(db: &Connection) -> Result<Vec<SoundingType>> {
     let mut stmt = db.prepare(
         "
-             SELECT id, type, file_type, interval, observed
+             SELECT id, type, file_type, interval, observed, source_url
              FROM types;
         ",
     )?;
@@ -184,6 +268,30 @@ pub(crate) fn all_sounding_types(db: &Connection) -> Result<Vec<SoundingType>> {
     vals
 }
 
+/// Get a page of the sounding types stored in the database, ordered by source.
+#[inline]
+pub(crate) fn sounding_types_paged(
+    db: &Connection,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<SoundingType>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, type, file_type, interval, observed, source_url
+            FROM types
+            ORDER BY type ASC
+            LIMIT ?1 OFFSET ?2
+        ",
+    )?;
+
+    let vals: Result<Vec<SoundingType>> = stmt
+        .query_and_then([limit as i64, offset as i64], parse_row_to_sounding_type)?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
 /// Get a list of all the sounding types stored in the database for a particular site
 #[inline]
 pub(crate) fn all_sounding_types_for_site(
@@ -192,7 +300,7 @@ pub(crate) fn all_sounding_types_for_site(
 ) -> Result<Vec<SoundingType>> {
     let mut stmt = db.prepare(
         "
-            SELECT id, type, file_type, interval, observed 
+            SELECT id, type, file_type, interval, observed, source_url
             FROM types
             WHERE types.id IN 
                 (SELECT DISTINCT files.type_id FROM files WHERE files.site_id = ?1);
@@ -207,6 +315,122 @@ pub(crate) fn all_sounding_types_for_site(
     vals
 }
 
+/// Get the sounding types with files for a site initialized in the range from `start` up to, but
+/// not including, `end`, ordered by source.
+pub(crate) fn sounding_types_for_site_between(
+    db: &Connection,
+    site: &Site,
+    start: &NaiveDateTime,
+    end: &NaiveDateTime,
+) -> Result<Vec<SoundingType>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT id, type, file_type, interval, observed, source_url
+            FROM types
+            WHERE types.id IN
+                (SELECT DISTINCT files.type_id FROM files
+                 WHERE files.site_id = ?1 AND files.init_time >= ?2 AND files.init_time < ?3)
+            ORDER BY type ASC;
+        ",
+    )?;
+
+    let vals: Result<Vec<SoundingType>> = stmt
+        .query_and_then(
+            &[&site.id(), start as &dyn ToSql, end as &dyn ToSql],
+            parse_row_to_sounding_type,
+        )?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get every sounding type along with the number of distinct sites that have files of that type.
+#[inline]
+pub(crate) fn sounding_type_site_counts(db: &Connection) -> Result<Vec<(SoundingType, i64)>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT types.id, types.type, types.file_type, types.interval, types.observed,
+                   types.source_url, COUNT(DISTINCT files.site_id)
+            FROM types LEFT JOIN files ON files.type_id = types.id
+            GROUP BY types.id
+            ORDER BY COUNT(DISTINCT files.site_id) DESC, types.type ASC;
+        ",
+    )?;
+
+    let vals: Result<Vec<(SoundingType, i64)>> = stmt
+        .query_and_then(
+            NO_PARAMS,
+            |row| -> std::result::Result<_, rusqlite::Error> {
+                Ok((parse_row_to_sounding_type(row)?, row.get(6)?))
+            },
+        )?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sounding types with files at a site, along with the number of files of each, sorted by
+/// source.
+pub(crate) fn file_counts_for_site(
+    db: &Connection,
+    site: &Site,
+) -> Result<Vec<(SoundingType, i64)>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT types.id, types.type, types.file_type, types.interval, types.observed,
+                   types.source_url, COUNT(*)
+            FROM types JOIN files ON files.type_id = types.id
+            WHERE files.site_id = ?1
+            GROUP BY types.id
+            ORDER BY types.type ASC;
+        ",
+    )?;
+
+    let vals: Result<Vec<(SoundingType, i64)>> = stmt
+        .query_and_then(
+            [site.id()],
+            |row| -> std::result::Result<_, rusqlite::Error> {
+                Ok((parse_row_to_sounding_type(row)?, row.get(6)?))
+            },
+        )?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
+/// Get the sounding types without any files initialized at or after `cutoff`, along with the most
+/// recent init time of each, which is `None` if there are no files at all.
+pub(crate) fn stale_sounding_types(
+    db: &Connection,
+    cutoff: &NaiveDateTime,
+) -> Result<Vec<(SoundingType, Option<NaiveDateTime>)>> {
+    let mut stmt = db.prepare(
+        "
+            SELECT types.id, types.type, types.file_type, types.interval, types.observed,
+                   types.source_url, MAX(files.init_time)
+            FROM types LEFT JOIN files ON files.type_id = types.id
+            GROUP BY types.id
+            HAVING MAX(files.init_time) IS NULL OR MAX(files.init_time) < ?1
+            ORDER BY types.type ASC;
+        ",
+    )?;
+
+    let vals: Result<Vec<(SoundingType, Option<NaiveDateTime>)>> = stmt
+        .query_and_then(
+            &[cutoff as &dyn ToSql],
+            |row| -> std::result::Result<_, rusqlite::Error> {
+                Ok((parse_row_to_sounding_type(row)?, row.get(6)?))
+            },
+        )?
+        .map(|res| res.map_err(BufkitDataErr::from))
+        .collect();
+
+    vals
+}
+
 fn parse_row_to_sounding_type(row: &Row) -> std::result::Result<SoundingType, rusqlite::Error> {
     let id: i64 = row.get(0)?;
     let source = row.get(1)?;
@@ -214,6 +438,7 @@ fn parse_row_to_sounding_type(row: &Row) -> std::result::Result<SoundingType, ru
         FileType::from_str(&row.get::<_, String>(2)?).unwrap_or(FileType::UNKNOWN);
     let hours_between = row.get(3)?;
     let observed = row.get(4)?;
+    let source_url = row.get(5)?;
 
     Ok(SoundingType {
         id,
@@ -221,9 +446,22 @@ fn parse_row_to_sounding_type(row: &Row) -> std::result::Result<SoundingType, ru
         file_type,
         hours_between,
         observed,
+        source_url,
     })
 }
 
+/// Formats as `SOURCE [modeled, 6h]`, leaving out the interval if it isn't known.
+impl fmt::Display for SoundingType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.observed { "observed" } else { "modeled" };
+
+        match self.hours_between {
+            Some(hours) => write!(f, "{} [{}, {}h]", self.source, kind, hours),
+            None => write!(f, "{} [{}]", self.source, kind),
+        }
+    }
+}
+
 /// Flag for how the sounding data is encoded in the file
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, AsStaticStr)]
 pub enum FileType {
@@ -235,6 +473,29 @@ pub enum FileType {
     UNKNOWN,
 }
 
+impl FileType {
+    /// Guess the file type from the uncompressed contents of a file.
+    ///
+    /// BUFR messages start with "BUFR", possibly after a WMO bulletin header, and BUFKIT files
+    /// start with the "SNPARM" line. Anything else is `FileType::UNKNOWN`.
+    pub fn detect(bytes: &[u8]) -> FileType {
+        const BUFR_HEADER_LEN: usize = 256;
+
+        // BUFKIT text may mention BUFR anywhere, so only look for the marker if it isn't BUFKIT.
+        let start = &bytes[..bytes.len().min(BUFR_HEADER_LEN)];
+        if String::from_utf8_lossy(start)
+            .trim_start()
+            .starts_with("SNPARM")
+        {
+            FileType::BUFKIT
+        } else if start.windows(4).any(|window| window == b"BUFR") {
+            FileType::BUFR
+        } else {
+            FileType::UNKNOWN
+        }
+    }
+}
+
 /*--------------------------------------------------------------------------------------------------
                                           Unit Tests
 --------------------------------------------------------------------------------------------------*/
@@ -244,6 +505,22 @@ mod unit {
     use rusqlite::{Connection, OpenFlags};
     use tempdir::TempDir;
 
+    #[test]
+    fn test_detect_file_type() {
+        let bufkit = std::fs::read("example_data/2017040112Z_nam_kmso.buf").unwrap();
+        assert_eq!(FileType::detect(&bufkit), FileType::BUFKIT);
+        assert_eq!(
+            FileType::detect(b"IUSZ52 KWBC 011200\r\r\nBUFR\x00\x01"),
+            FileType::BUFR
+        );
+        assert_eq!(
+            FileType::detect(b"SNPARM = PRES;TMPC;DWPC;BUFR\r\n"),
+            FileType::BUFKIT
+        );
+        assert_eq!(FileType::detect(b"not a sounding"), FileType::UNKNOWN);
+        assert_eq!(FileType::detect(b""), FileType::UNKNOWN);
+    }
+
     #[test]
     fn test_insert_retrieve_sounding_type() -> Result<()> {
         let tmp = TempDir::new("bufkit-data-test-archive")?;
@@ -262,6 +539,83 @@ mod unit {
         let snd_tp = retrieve_sounding_type(&db_conn, "GFS3")?.expect("No such sounding type.");
 
         assert_eq!(snd_tp.source(), "GFS3");
+        assert_eq!(snd_tp.source_url(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_sounding_type() {
+        let gfs = SoundingType::new_model("gfs", FileType::BUFKIT, 6);
+        assert_eq!(gfs.to_string(), "GFS [modeled, 6h]");
+
+        let raob = SoundingType::new_observed("raob", FileType::BUFR, 12);
+        assert_eq!(raob.to_string(), "RAOB [observed, 12h]");
+
+        let special = SoundingType::new_observed("special", FileType::BUFR, None);
+        assert_eq!(special.to_string(), "SPECIAL [observed]");
+    }
+
+    #[test]
+    fn test_builders() -> Result<()> {
+        let db_conn = Connection::open_in_memory()?;
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        insert_sounding_type(
+            &db_conn,
+            SoundingType::new_model("special", FileType::BUFKIT, 6),
+        )?;
+        let special = retrieve_sounding_type(&db_conn, "special")?.unwrap();
+
+        let changed = special.clone().with_observed(true).with_hours_between(None);
+        assert!(changed.is_observed());
+        assert_eq!(changed.hours_between_initializations(), None);
+        assert_eq!(changed.source(), special.source());
+        assert_eq!(changed.id(), special.id());
+
+        update_sounding_type(&db_conn, changed.with_hours_between(12))?;
+        let special = retrieve_sounding_type(&db_conn, "special")?.unwrap();
+        assert!(special.is_observed());
+        assert_eq!(special.hours_between_initializations(), Some(12));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_url_round_trip() -> Result<()> {
+        let tmp = TempDir::new("bufkit-data-test-archive")?;
+        let db_file = tmp.as_ref().join("test_index.sqlite");
+        let db_conn = Connection::open_with_flags(
+            db_file,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        )?;
+
+        db_conn.execute_batch(include_str!("create_index.sql"))?;
+
+        let url = "https://example.com/bufkit/{site}.buf";
+        insert_sounding_type(
+            &db_conn,
+            SoundingType::new_model("GFS", FileType::BUFKIT, 6).with_source_url(url.to_owned()),
+        )?;
+        let snd_tp = retrieve_sounding_type(&db_conn, "GFS")?.expect("No such sounding type.");
+        assert_eq!(snd_tp.source_url(), Some(url));
+
+        let all = all_sounding_types(&db_conn)?;
+        assert_eq!(all[0].source_url(), Some(url));
+
+        let new_url = "https://example.com/v2/bufkit/{site}.buf";
+        let updated = update_sounding_type(
+            &db_conn,
+            SoundingType::new_model("GFS", FileType::BUFKIT, 12)
+                .with_source_url(new_url.to_owned()),
+        )?;
+        assert_eq!(updated.source_url(), Some(new_url));
+        assert_eq!(updated.hours_between_initializations(), Some(12));
+
+        update_sounding_type(&db_conn, updated.with_file_type(FileType::BUFR))?;
+        let snd_tp = retrieve_sounding_type(&db_conn, "GFS")?.expect("No such sounding type.");
+        assert_eq!(snd_tp.file_type(), FileType::BUFR);
+        assert_eq!(snd_tp.source_url(), Some(new_url));
 
         Ok(())
     }
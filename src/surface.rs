@@ -0,0 +1,62 @@
+//! Module for the surface conditions from a sounding.
+use chrono::NaiveDateTime;
+use sounding_analysis::Analysis;
+
+/// The surface values from a single sounding, without the rest of the profile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceData {
+    /// Temperature in Celsius.
+    temperature: Option<f64>,
+    /// Dew point in Celsius.
+    dew_point: Option<f64>,
+    /// Wind direction in degrees and speed in knots.
+    wind: Option<(f64, f64)>,
+    /// Station pressure in hPa.
+    station_pressure: Option<f64>,
+}
+
+impl SurfaceData {
+    /// The surface temperature in Celsius.
+    #[inline]
+    pub fn temperature(&self) -> Option<f64> {
+        self.temperature
+    }
+
+    /// The surface dew point in Celsius.
+    #[inline]
+    pub fn dew_point(&self) -> Option<f64> {
+        self.dew_point
+    }
+
+    /// The surface wind as a tuple of direction in degrees and speed in knots.
+    #[inline]
+    pub fn wind(&self) -> Option<(f64, f64)> {
+        self.wind
+    }
+
+    /// The station pressure in hPa.
+    #[inline]
+    pub fn station_pressure(&self) -> Option<f64> {
+        self.station_pressure
+    }
+}
+
+/// Get the valid time and surface values from an analysis, `None` if it has no valid time.
+// The sounding-base crate is deprecated upstream, but it is still what sounding-analysis uses.
+#[allow(deprecated)]
+pub(crate) fn surface_data(anal: &Analysis) -> Option<(NaiveDateTime, SurfaceData)> {
+    let snd = anal.sounding();
+    let valid_time = snd.valid_time()?;
+
+    let surface = SurfaceData {
+        temperature: snd.sfc_temperature().into_option().map(|t| t.0),
+        dew_point: snd.sfc_dew_point().into_option().map(|dp| dp.0),
+        wind: snd
+            .sfc_wind()
+            .into_option()
+            .map(|w| (w.direction, w.speed.0)),
+        station_pressure: snd.station_pressure().into_option().map(|p| p.0),
+    };
+
+    Some((valid_time, surface))
+}